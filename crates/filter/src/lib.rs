@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     cmp::Ordering,
     collections::HashMap,
     fmt::{self, Display},
@@ -16,6 +17,14 @@ pub enum Operator {
     Lt,
     GtEq,
     LtEq,
+    /// The key is present with a value other than null
+    Exists,
+    /// The key is missing or its value is null
+    NotExists,
+    /// The value is a string within max_distance edits of the value of the
+    /// filter. Vector databases can't evaluate it, it is applied to search
+    /// results after they are retrieved.
+    Fuzzy { max_distance: usize },
 }
 
 impl Operator {
@@ -27,9 +36,17 @@ impl Operator {
             "<" => Ok(Self::Lt),
             ">=" => Ok(Self::GtEq),
             "<=" => Ok(Self::LtEq),
+            "exists" => Ok(Self::Exists),
+            "not_exists" => Ok(Self::NotExists),
             _ => Err(anyhow::anyhow!("Invalid filter operator: {}", operator)),
         }
     }
+
+    /// Whether the filter is applied to search results after they are
+    /// retrieved, instead of by the vector database
+    pub fn is_post_filter(&self) -> bool {
+        matches!(self, Operator::Fuzzy { .. })
+    }
 }
 
 impl Display for Operator {
@@ -44,6 +61,9 @@ impl Display for Operator {
                 Operator::Lt => "<",
                 Operator::GtEq => ">=",
                 Operator::LtEq => "<=",
+                Operator::Exists => "exists",
+                Operator::NotExists => "not_exists",
+                Operator::Fuzzy { .. } => "fuzzy",
             }
         )
     }
@@ -77,6 +97,43 @@ impl<'de> Deserialize<'de> for Expression {
 
 impl Expression {
     pub fn from_str(str: &str) -> Result<Self> {
+        // Fuzzy filters are written as fuzzy(key,max_distance)=value
+        if let Some((args, value)) = str
+            .strip_prefix("fuzzy(")
+            .and_then(|rest| rest.split_once(")="))
+        {
+            let (key, max_distance) = args.rsplit_once(',').ok_or(anyhow::anyhow!(
+                "Invalid fuzzy filter: {}, expected fuzzy(key,max_distance)=value",
+                str
+            ))?;
+            let max_distance = max_distance
+                .trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid distance of fuzzy filter {}: {}", str, e))?;
+            let value = serde_json::from_str(value).unwrap_or(serde_json::json!(value));
+            if !value.is_string() {
+                return Err(anyhow::anyhow!("fuzzy filter {} must match a string", str));
+            }
+            return Ok(Self {
+                key: key.to_string(),
+                value,
+                operator: Operator::Fuzzy { max_distance },
+            });
+        }
+        // Existence filters are written as exists(key) and not_exists(key)
+        for operator in ["exists", "not_exists"] {
+            if let Some(key) = str
+                .strip_prefix(operator)
+                .and_then(|rest| rest.strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                return Ok(Self {
+                    key: key.to_string(),
+                    value: Value::Null,
+                    operator: Operator::from_str(operator)?,
+                });
+            }
+        }
         // This parser must start with the longest operators first.
         let operators = vec!["!=", ">=", "<=", "=", ">", "<"];
         for operator in operators {
@@ -96,12 +153,66 @@ impl Expression {
         }
         Err(anyhow::anyhow!("Invalid filter: {}", str))
     }
+
+    /// Checks that the expression can match some value. Expressions which
+    /// parse but never match, like comparisons with lists, are accepted by
+    /// searches and return no results.
+    pub fn validate(&self) -> Result<()> {
+        if self.key.is_empty() {
+            return Err(anyhow::anyhow!("filter {} has no key", self));
+        }
+        if matches!(
+            self.operator,
+            Operator::Exists | Operator::NotExists | Operator::Fuzzy { .. }
+        ) {
+            return Ok(());
+        }
+        match self.value {
+            Value::Null => Err(anyhow::anyhow!(
+                "filter {} compares with null, use exists({}) or not_exists({}) instead",
+                self,
+                self.key,
+                self.key
+            )),
+            Value::Array(_) | Value::Object(_) => Err(anyhow::anyhow!(
+                "filter {} compares with {}, only numbers, strings and booleans can be compared",
+                self,
+                self.value
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}{}", self.key, self.operator, self.value)
+        match self.operator {
+            Operator::Exists | Operator::NotExists => write!(f, "{}({})", self.operator, self.key),
+            Operator::Fuzzy { max_distance } => write!(
+                f,
+                "{}({},{})={}",
+                self.operator, self.key, max_distance, self.value
+            ),
+            _ => write!(f, "{}{}{}", self.key, self.operator, self.value),
+        }
+    }
+}
+
+/// Number of characters to insert, delete or substitute to turn lhs into rhs
+fn levenshtein(lhs: &str, rhs: &str) -> usize {
+    let rhs: Vec<char> = rhs.chars().collect();
+    // Distances from the prefix of lhs read so far to every prefix of rhs
+    let mut distances: Vec<usize> = (0..=rhs.len()).collect();
+    for (i, lhs_char) in lhs.chars().enumerate() {
+        let mut diagonal = distances[0];
+        distances[0] = i + 1;
+        for (j, rhs_char) in rhs.iter().enumerate() {
+            let substitution = diagonal + usize::from(lhs_char != *rhs_char);
+            diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(diagonal + 1);
+        }
     }
+    distances[rhs.len()]
 }
 
 fn partial_cmp(lhs: &Value, rhs: &Value) -> Option<Ordering> {
@@ -113,6 +224,14 @@ fn partial_cmp(lhs: &Value, rhs: &Value) -> Option<Ordering> {
     }
 }
 
+/// Lowercases strings if ignore_case is set, other values are unchanged
+fn fold_case(value: &Value, ignore_case: bool) -> Cow<'_, Value> {
+    match value {
+        Value::String(s) if ignore_case => Cow::Owned(Value::String(s.to_lowercase())),
+        _ => Cow::Borrowed(value),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct LabelsFilter(pub Vec<Expression>);
 
@@ -125,11 +244,63 @@ impl LabelsFilter {
         &self.0
     }
 
+    /// Returns the filter matching values which match both filters
+    pub fn and(mut self, other: &LabelsFilter) -> Self {
+        self.0.extend(other.0.iter().cloned());
+        self
+    }
+
+    /// Splits the filter into the expressions evaluated by vector databases
+    /// and the expressions applied to search results after they are
+    /// retrieved
+    pub fn split_post_filters(self) -> (Self, Self) {
+        let (post_filters, filters): (Vec<_>, Vec<_>) = self
+            .0
+            .into_iter()
+            .partition(|expr| expr.operator.is_post_filter());
+        (LabelsFilter(filters), LabelsFilter(post_filters))
+    }
+
+    /// Splits the filter into the expressions comparing numbers and booleans,
+    /// or checking whether keys exist, and the expressions comparing strings
+    pub fn split_string_filters(self) -> (Self, Self) {
+        let (string_filters, filters): (Vec<_>, Vec<_>) =
+            self.0.into_iter().partition(|expr| expr.value.is_string());
+        (LabelsFilter(filters), LabelsFilter(string_filters))
+    }
+
     pub fn matches(&self, values: &HashMap<String, Value>) -> bool {
+        self.matches_case(values, false)
+    }
+
+    /// Like matches, but strings are lowercased on both sides before they are
+    /// compared
+    pub fn matches_ignore_case(&self, values: &HashMap<String, Value>) -> bool {
+        self.matches_case(values, true)
+    }
+
+    fn matches_case(&self, values: &HashMap<String, Value>, ignore_case: bool) -> bool {
         self.0.iter().all(|expr| {
-            let value = values.get(&expr.key);
+            let value = values
+                .get(&expr.key)
+                .map(|value| fold_case(value, ignore_case));
+            let value = value.as_deref();
+            let expected = fold_case(&expr.value, ignore_case);
+            match expr.operator {
+                Operator::Exists => return value.is_some_and(|value| !value.is_null()),
+                Operator::NotExists => return value.map_or(true, Value::is_null),
+                Operator::Fuzzy { max_distance } => {
+                    return match (value, expected.as_ref()) {
+                        (Some(Value::String(s)), Value::String(t)) => {
+                            levenshtein(s, t) <= max_distance
+                        }
+                        _ => false,
+                    }
+                }
+                _ => {}
+            }
             match value {
-                Some(value) => match partial_cmp(value, &expr.value) {
+                Some(value) => match partial_cmp(value, &expected) {
                     Some(ordering) => match expr.operator {
                         Operator::Eq => ordering == std::cmp::Ordering::Equal,
                         Operator::Neq => ordering != std::cmp::Ordering::Equal,
@@ -137,6 +308,9 @@ impl LabelsFilter {
                         Operator::Lt => ordering == std::cmp::Ordering::Less,
                         Operator::GtEq => ordering != std::cmp::Ordering::Less,
                         Operator::LtEq => ordering != std::cmp::Ordering::Greater,
+                        Operator::Exists | Operator::NotExists | Operator::Fuzzy { .. } => {
+                            unreachable!()
+                        }
                     },
                     None => false,
                 },
@@ -202,4 +376,145 @@ mod tests {
         values.insert("key2".to_string(), serde_json::json!(3));
         assert!(filter.matches(&values));
     }
+
+    #[test]
+    fn test_exists() {
+        let filter = Expression::from_str("exists(author)").unwrap();
+        assert_eq!(filter.operator, Operator::Exists);
+        assert_eq!(filter.key, "author");
+        assert_eq!(filter.to_string(), "exists(author)");
+        let filter = Expression::from_str("not_exists(author)").unwrap();
+        assert_eq!(filter.operator, Operator::NotExists);
+        assert_eq!(filter.to_string(), "not_exists(author)");
+        // Keys starting with exists are still compared
+        let filter = Expression::from_str("existsince=1").unwrap();
+        assert_eq!(filter.operator, Operator::Eq);
+        assert_eq!(filter.key, "existsince");
+
+        let exists = LabelsFilter(vec![Expression::from_str("exists(author)").unwrap()]);
+        let not_exists = LabelsFilter(vec![Expression::from_str("not_exists(author)").unwrap()]);
+        let mut values = HashMap::new();
+        assert!(!exists.matches(&values));
+        assert!(not_exists.matches(&values));
+
+        // A null value counts as absent
+        values.insert("author".to_string(), serde_json::Value::Null);
+        assert!(!exists.matches(&values));
+        assert!(not_exists.matches(&values));
+
+        values.insert("author".to_string(), serde_json::json!(""));
+        assert!(exists.matches(&values));
+        assert!(!not_exists.matches(&values));
+    }
+
+    #[test]
+    fn test_validate() {
+        for filter in [
+            "key=value",
+            "key>=1",
+            "key!=true",
+            "exists(key)",
+            "fuzzy(key,1)=a",
+        ] {
+            assert!(Expression::from_str(filter).unwrap().validate().is_ok());
+        }
+        for filter in [
+            "=value",
+            "exists()",
+            "key=null",
+            "key>[1,2]",
+            "key<{\"a\":1}",
+        ] {
+            assert!(Expression::from_str(filter).unwrap().validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("smith", "smith"), 0);
+        assert_eq!(levenshtein("smith", "smyth"), 1);
+        assert_eq!(levenshtein("smith", "smit"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("müller", "muller"), 1);
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let filter = Expression::from_str("fuzzy(author,1)=smith").unwrap();
+        assert_eq!(filter.operator, Operator::Fuzzy { max_distance: 1 });
+        assert_eq!(filter.key, "author");
+        assert_eq!(filter.value, serde_json::json!("smith"));
+        assert_eq!(filter.to_string(), "fuzzy(author,1)=\"smith\"");
+        assert_eq!(Expression::from_str(&filter.to_string()).unwrap(), filter);
+        assert!(Expression::from_str("fuzzy(author)=smith").is_err());
+        assert!(Expression::from_str("fuzzy(author,-1)=smith").is_err());
+        assert!(Expression::from_str("fuzzy(year,1)=2020").is_err());
+
+        let filter = LabelsFilter(vec![
+            Expression::from_str("fuzzy(author,1)=smith").unwrap(),
+            Expression::from_str("year=2020").unwrap(),
+        ]);
+        let mut values = HashMap::new();
+        values.insert("author".to_string(), serde_json::json!("smyth"));
+        values.insert("year".to_string(), serde_json::json!(2020));
+        assert!(filter.matches(&values));
+        values.insert("author".to_string(), serde_json::json!("smythe"));
+        assert!(!filter.matches(&values));
+
+        let (filters, post_filters) = filter.split_post_filters();
+        assert_eq!(filters.0, vec![Expression::from_str("year=2020").unwrap()]);
+        assert_eq!(
+            post_filters.0,
+            vec![Expression::from_str("fuzzy(author,1)=smith").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_and() {
+        let filter = LabelsFilter(vec![Expression::from_str("key1=1").unwrap()])
+            .and(&LabelsFilter(vec![Expression::from_str("key2=2").unwrap()]));
+        assert_eq!(filter.0.len(), 2);
+
+        let mut values = HashMap::new();
+        values.insert("key1".to_string(), serde_json::json!(1));
+        assert!(!filter.matches(&values));
+
+        values.insert("key2".to_string(), serde_json::json!(2));
+        assert!(filter.matches(&values));
+    }
+
+    #[test]
+    fn test_matches_ignore_case() {
+        let filter = LabelsFilter(vec![
+            Expression {
+                key: "Author".to_string(),
+                value: serde_json::json!("Alice"),
+                operator: Operator::Eq,
+            },
+            Expression {
+                key: "year".to_string(),
+                value: serde_json::json!(2020),
+                operator: Operator::GtEq,
+            },
+        ]);
+
+        let mut values = HashMap::new();
+        values.insert("Author".to_string(), serde_json::json!("ALICE"));
+        values.insert("year".to_string(), serde_json::json!(2021));
+        assert!(!filter.matches(&values));
+        assert!(filter.matches_ignore_case(&values));
+
+        // Keys are still compared by case
+        values.insert("author".to_string(), values["Author"].clone());
+        values.remove("Author");
+        assert!(!filter.matches_ignore_case(&values));
+
+        let (filters, string_filters) = filter.split_string_filters();
+        assert_eq!(filters.0.len(), 1);
+        assert_eq!(filters.0[0].key, "year");
+        assert_eq!(string_filters.0.len(), 1);
+        assert_eq!(string_filters.0[0].key, "Author");
+    }
 }
@@ -13,7 +13,15 @@ use serde_with::{serde_as, BytesOrString};
 use strum::{Display, EnumString};
 use utoipa::{openapi, IntoParams, ToSchema};
 
-use crate::{api_utils, metadata_storage, state::forwardable_raft::RaftState, vectordbs};
+use crate::{
+    api_utils,
+    embedding_cache::EmbeddingCacheStats,
+    metadata_storage,
+    rate_limiter::RateLimitStatus,
+    slow_query_log::SlowQuery,
+    state::forwardable_raft::RaftState,
+    vectordbs,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ExtractionGraphLink {
@@ -164,11 +172,15 @@ fn filter_schema() -> openapi::Array {
             openapi::ObjectBuilder::new()
                 .schema_type(openapi::SchemaType::String)
                 .description(Some(
-                    "filter expression in format key/operator/value, e.g. key>=value",
+                    "filter expression in format key/operator/value, e.g. key>=value, or exists(key) and not_exists(key)",
                 ))
                 .build(),
         )
-        .example(Some(serde_json::json!(vec!["key1=value1", "key2>=value2"])))
+        .example(Some(serde_json::json!(vec![
+            "key1=value1",
+            "key2>=value2",
+            "not_exists(key3)"
+        ])))
         .build()
 }
 
@@ -316,9 +328,171 @@ pub struct ListIndexesResponse {
     pub indexes: Vec<Index>,
 }
 
+/// The stored configuration of an index
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DescribeIndexResponse {
+    pub name: String,
+    pub namespace: String,
+    /// Name of the index in its vector database
+    pub table_name: String,
+    pub embedding_schema: EmbeddingSchema,
+    /// Vector database storing the index
+    pub index_store: String,
+    pub extraction_graph: String,
+    pub extraction_policy: String,
+    /// Extractor embedding the content of the index
+    pub extractor: String,
+    /// Input params of the extraction policy, such as the chunking of the
+    /// content
+    pub extractor_params: Option<serde_json::Value>,
+    /// Models embedding search queries when the index has query embedding
+    /// services, in the order they are tried
+    pub query_embedding_models: Vec<String>,
+    /// Index settings from the server config
+    pub settings: serde_json::Value,
+    /// Whether writes to the index are rejected, by its settings or its read
+    /// only flag
+    pub read_only: bool,
+}
+
+/// A search query, several phrasings of a query are searched with the mean of
+/// their embeddings and return a single list of results.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum SearchQuery {
+    Text(String),
+    Texts(Vec<String>),
+}
+
+impl SearchQuery {
+    pub fn into_texts(self) -> Vec<String> {
+        match self {
+            SearchQuery::Text(text) => vec![text],
+            SearchQuery::Texts(texts) => texts,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, IntoParams, ToSchema)]
 pub struct SearchRequest {
-    pub query: String,
+    pub query: SearchQuery,
+    pub k: Option<u64>,
+    #[serde(default)]
+    #[schema(schema_with = filter_schema)]
+    pub filters: LabelsFilter,
+    pub include_content: Option<bool>,
+    /// Include the text of the root content every fragment was extracted
+    /// from. Root content is kept in blob storage anyway, the option costs no
+    /// storage but an extra blob read per distinct root content.
+    pub include_source: Option<bool>,
+    /// With exact, the query is compared with every vector of the index
+    /// instead of searching its approximate nearest neighbor index
+    #[serde(default)]
+    pub search_mode: SearchMode,
+    /// Multiply the scores of results whose metadata matches a boost. More
+    /// candidates than k are retrieved when boosts are set, so that boosted
+    /// results just outside of the k nearest neighbors can still be returned.
+    #[serde(default)]
+    pub boosts: Vec<SearchBoost>,
+    /// Group the results by the value of this metadata field. k is then the
+    /// number of groups, and the results are returned in groups instead of
+    /// results.
+    pub group_by: Option<String>,
+    /// Number of results returned per group, 1 by default
+    pub per_group: Option<u64>,
+    /// Number of nearest neighbors retrieved before boosts and grouping are
+    /// applied and the results are truncated to k, at least k. Defaults to
+    /// k, or to a multiple of k with boosts, group_by or dedup_threshold.
+    pub candidates: Option<u64>,
+    /// Add the raw distance and the normalized score of every result to the
+    /// results, next to their confidence score
+    pub score_details: Option<bool>,
+    /// Sort the k most relevant results by a metadata field instead of
+    /// their relevance
+    pub sort_by: Option<SearchSort>,
+    /// Texts the results should be unlike. The mean of their embeddings is
+    /// subtracted from the embedding of the query before searching.
+    #[serde(default)]
+    pub negative_queries: Vec<String>,
+    /// Factor the embedding of the negative queries is scaled by before it
+    /// is subtracted, 0.5 by default
+    pub negative_weight: Option<f32>,
+    /// Drop results whose embedding has a cosine similarity above this
+    /// threshold with the embedding of a better result. More candidates than
+    /// k are retrieved, so that k results can still be returned.
+    pub dedup_threshold: Option<f32>,
+    /// Embed the queries even if their embeddings are cached, for example to
+    /// measure the latency of the embedding model. The new embeddings are
+    /// still cached unless no_cache_write is set.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Don't cache the embeddings of the queries of this search
+    #[serde(default)]
+    pub no_cache_write: bool,
+    /// next_cursor of the previous page of results, to return the next page.
+    /// The rest of the request must be the same as for the previous page.
+    pub cursor: Option<String>,
+}
+
+/// Sorts search results by the value of a metadata field. Numbers are sorted
+/// numerically and strings lexically, results without the field are last.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SearchSort {
+    pub field: String,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Boosts the results whose metadata field equals value by factor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SearchBoost {
+    pub field: String,
+    pub value: serde_json::Value,
+    pub factor: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Approximate,
+    Exact,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SimilarRequest {
+    pub id: String,
+    pub k: Option<u64>,
+    #[serde(default)]
+    #[schema(schema_with = filter_schema)]
+    pub filters: LabelsFilter,
+    pub include_content: Option<bool>,
+}
+
+/// A text scored against the content of an index as if it were added to it
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScoreRequest {
+    /// Text embedded like the content of the index, not added to it
+    pub text: String,
+    pub k: Option<u64>,
+    #[serde(default)]
+    #[schema(schema_with = filter_schema)]
+    pub filters: LabelsFilter,
+    pub include_content: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchVectorRequest {
+    /// Query embedding, must have the dimension of the index
+    pub vector: Vec<f32>,
     pub k: Option<u64>,
     #[serde(default)]
     #[schema(schema_with = filter_schema)]
@@ -393,13 +567,366 @@ pub struct DocumentFragment {
     pub mime_type: String,
     pub confidence_score: f32,
     pub labels: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_text: Option<String>,
     pub root_content_metadata: Option<ContentMetadata>,
     pub content_metadata: ContentMetadata,
+    /// Distance of the result to the query, as computed by the vector
+    /// database, returned with score_details
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_distance: Option<f32>,
+    /// Score of the result between 0 and 1, higher for closer results,
+    /// returned with score_details
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_score: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct SearchStats {
+    /// Time taken by the search, including embedding the query
+    pub took_ms: u64,
+    /// Number of results returned by the vector database, before results
+    /// without content are dropped
+    pub candidates_considered: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
 pub struct IndexSearchResponse {
     pub results: Vec<DocumentFragment>,
+    #[serde(default)]
+    pub stats: SearchStats,
+    /// Results grouped by the group_by field of the request, ordered by the
+    /// score of their best result
+    #[serde(default)]
+    pub groups: Vec<SearchResultGroup>,
+    /// Cursor of the next page of results, set when k results were returned
+    /// and more may follow. Not returned with group_by.
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchResultGroup {
+    /// Value of the group_by field of the results, null for results without
+    /// the field
+    pub value: serde_json::Value,
+    pub results: Vec<DocumentFragment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+pub struct GetEmbeddingQuery {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetEmbeddingResponse {
+    pub content_id: String,
+    pub dim: usize,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+pub struct CountIndexQuery {
+    /// Use the estimate of the vector database instead of counting the
+    /// vectors, where the vector database has one
+    #[serde(default)]
+    pub approximate: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CountIndexResponse {
+    pub count: u64,
+    /// Whether count is an estimate of the vector database
+    pub approximate: bool,
+}
+
+/// A metadata field of the vectors of an index
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexField {
+    pub name: String,
+    /// JSON types of the values of the field, like string or number
+    pub types: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListIndexFieldsResponse {
+    pub fields: Vec<IndexField>,
+}
+
+/// Filters checked without searching, in the format of the filters of a
+/// search
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ValidateFilterRequest {
+    #[schema(schema_with = filter_schema)]
+    pub filters: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FilterError {
+    pub filter: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ValidateFilterResponse {
+    /// Whether every filter is valid
+    pub valid: bool,
+    /// Errors of the invalid filters, in the order of the filters
+    pub errors: Vec<FilterError>,
+}
+
+/// A document split into chunks with a splitter extractor and embedded with
+/// an embedding extractor, without storing anything
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmbedDocumentRequest {
+    pub text: String,
+    /// Extractor splitting the text into chunks
+    pub splitter: String,
+    pub splitter_params: Option<serde_json::Value>,
+    /// Extractor embedding every chunk
+    pub extractor: String,
+    pub extractor_params: Option<serde_json::Value>,
+}
+
+/// A chunk of a document with its embedding. The offsets are counted in
+/// characters, they are missing if the chunk wasn't found in the document.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmbeddedChunk {
+    pub chunk_text: String,
+    pub char_start: Option<u64>,
+    pub char_end: Option<u64>,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmbedDocumentResponse {
+    pub chunks: Vec<EmbeddedChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+pub struct ExportIndexQuery {
+    #[serde(default)]
+    pub include_embedding: bool,
+}
+
+/// A vector of an index, written as one line of an index export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedVector {
+    pub content_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    pub metadata: HashMap<String, serde_json::Value>,
+    pub content_metadata: internal_api::ContentMetadata,
+    pub root_content_metadata: Option<internal_api::ContentMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportIndexResponse {
+    pub imported: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateSnapshotRequest {
+    /// Name of the snapshot, made of letters, digits, - and _. An existing
+    /// snapshot with the same name is replaced
+    pub name: String,
+}
+
+/// A point-in-time copy of the vectors of an index
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct IndexSnapshot {
+    pub name: String,
+    /// Unix timestamp in seconds of when the snapshot was created
+    pub created_at: u64,
+    /// Size of the snapshot in bytes
+    pub size_bytes: u64,
+    /// Number of vectors in the snapshot
+    pub num_vectors: u64,
+    /// Location of the snapshot in blob storage, an index export with
+    /// embeddings
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListSnapshotsResponse {
+    pub snapshots: Vec<IndexSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RestoreSnapshotRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RestoreSnapshotResponse {
+    /// Number of vectors written from the snapshot
+    pub restored: u64,
+    /// Number of vectors removed because they are not in the snapshot
+    pub removed: u64,
+}
+
+/// Whether writes to an index are rejected
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct IndexReadOnly {
+    pub read_only: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReembedRequest {
+    /// Extractor embedding the content of the index, the extractor of the
+    /// index if not set. It must return embeddings of the dimension of the
+    /// index.
+    pub extractor: Option<String>,
+    /// Embedding output of the extractor, required if the extractor has
+    /// several embedding outputs of the dimension of the index
+    pub output: Option<String>,
+    /// Maximum number of requests per second sent to the extractor, 10 if not
+    /// set
+    pub requests_per_second: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReembedJobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Progress of the re-embedding of the vectors of an index
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReembedJob {
+    pub id: String,
+    pub namespace: String,
+    pub index: String,
+    pub extractor: String,
+    pub status: ReembedJobStatus,
+    /// Number of vectors in the index when the job started
+    pub total: u64,
+    /// Number of vectors re-embedded so far
+    pub processed: u64,
+    /// Number of vectors skipped because their content couldn't be read
+    pub skipped: u64,
+    /// Error which failed the job
+    pub error: Option<String>,
+    /// Unix timestamp in seconds of when the job started
+    pub started_at: u64,
+    /// Unix timestamp in seconds of when the job completed or failed
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResetRequest {
+    /// Must be set to "delete all indexes"
+    pub confirm: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResetResponse {
+    /// Names of the deleted indexes, prefixed with their namespace
+    pub deleted_indexes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+pub struct ReconcileQuery {
+    pub namespace: String,
+    pub index: String,
+    /// Report the orphaned vectors without removing them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReconcileResponse {
+    pub index: String,
+    /// Number of vectors read from the index
+    pub vectors_checked: u64,
+    /// Ids of the vectors whose content no longer exists
+    pub orphaned_content_ids: Vec<String>,
+    /// Ids of the content extracted by the extraction policy of the index
+    /// which has no vector in the index
+    pub missing_content_ids: Vec<String>,
+    /// Whether the orphaned and missing vectors were only reported
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VersionResponse {
+    pub version: String,
+    pub git_sha: String,
+    pub git_branch: String,
+    /// Vector database used by this server
+    pub index_store: String,
+    /// Vector databases supported by this build
+    pub index_stores: Vec<String>,
+    /// Services which can embed search queries
+    pub query_embedding_providers: Vec<String>,
+}
+
+/// Distances, storage dtypes and search features supported by a vector
+/// database
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VectorDbCapabilities {
+    /// Table name of the index stored in the vector database, none for the
+    /// default vector database of the server
+    pub index: Option<String>,
+    pub name: String,
+    /// Distances indexes can be created with
+    pub distances: Vec<String>,
+    /// Precisions embeddings can be stored with, see storage_dtype
+    pub storage_dtypes: Vec<String>,
+    /// Whether exact searches compare the query with every vector, instead of
+    /// searching the approximate nearest neighbor index
+    pub exact_search: bool,
+    /// Whether vectors can be counted approximately
+    pub approximate_count: bool,
+    /// Whether index names differing only by case are distinct indexes
+    pub case_sensitive_names: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VectorDbsResponse {
+    pub vector_dbs: Vec<VectorDbCapabilities>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EmbeddingCacheResponse {
+    pub enabled: bool,
+    pub models: Vec<EmbeddingCacheStats>,
+}
+
+/// An http service embedding the search queries of an index
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EmbeddingProvider {
+    /// Table name of the index
+    pub index: String,
+    /// query_embedding, or query_embedding_fallback for fallbacks
+    pub role: String,
+    /// Position of a fallback among the fallbacks of the index
+    pub fallback_position: Option<usize>,
+    pub model: String,
+    pub url: String,
+    pub dim: usize,
+    /// Whether the service embedded a test query
+    pub healthy: bool,
+    /// Error of the test query if the service is not healthy
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EmbeddingProvidersResponse {
+    pub providers: Vec<EmbeddingProvider>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RateLimitsResponse {
+    pub limits: Vec<RateLimitStatus>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SlowQueriesResponse {
+    pub threshold_ms: Option<u64>,
+    pub queries: Vec<SlowQuery>,
 }
 
 #[derive(Debug)]
@@ -423,6 +950,10 @@ impl IndexifyAPIError {
     pub fn not_found(message: &str) -> Self {
         Self::new(StatusCode::NOT_FOUND, message)
     }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
 }
 
 impl IntoResponse for IndexifyAPIError {
@@ -493,6 +1024,49 @@ pub struct UpdateLabelsRequest {
     pub labels: HashMap<String, serde_json::Value>,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateIndexMetadataRequest {
+    pub id: String,
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Merge the metadata into the existing metadata instead of replacing it
+    #[serde(default)]
+    pub merge: bool,
+}
+
+/// A precomputed embedding and the text it was computed from
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VectorItem {
+    pub vector: Vec<f32>,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddVectorsRequest {
+    pub items: Vec<VectorItem>,
+    /// Creates the index if it doesn't exist, the index is not found
+    /// otherwise
+    pub create_if_missing: Option<CreateIndexIfMissing>,
+}
+
+/// Extraction policy creating a missing index. The index name must be
+/// <graph>.<policy>.<output>, the extraction graph is created with a policy
+/// whose extractor has an embedding output named output.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateIndexIfMissing {
+    /// Extractor embedding the content of the index, whose embedding schema
+    /// sets the dimension and distance of the index
+    pub extractor: String,
+    pub input_params: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddVectorsResponse {
+    /// Ids of the content created for the items, in the order of the items
+    pub content_ids: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, ToSchema, Clone)]
 pub struct ContentMetadata {
     pub id: String,
@@ -752,6 +1326,11 @@ pub enum IngestExtractedContent {
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
 pub enum IngestExtractedContentResponse {
     Success,
+    /// The content was ingested but some of its chunks were not indexed, for
+    /// example chunks beyond the max_fragments_per_document of an index
+    SuccessWithWarnings {
+        warnings: Vec<String>,
+    },
     Error(String),
 }
 
@@ -923,6 +1502,10 @@ pub struct TaskAssignments {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UploadFileResponse {
     pub content_id: String,
+    /// Why the content was not added, set for text which is empty or only
+    /// contains whitespace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
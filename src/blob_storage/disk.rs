@@ -13,6 +13,7 @@ use tokio::{
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use super::{
+    forward_chunks,
     BlobStoragePartWriter,
     BlobStorageReader,
     BlobStorageWriter,
@@ -125,6 +126,16 @@ impl DiskStorage {
         std::fs::create_dir_all(tmp_path)?;
         Ok(Self { config })
     }
+
+    /// Reads the file written at key, None if there is no such file
+    pub async fn read(&self, key: &str) -> Result<Option<Bytes>> {
+        let path = format!("{}/{}", self.config.path, key);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(bytes.into())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("unable to read file {}: {}", path, e)),
+        }
+    }
 }
 
 #[async_trait]
@@ -193,18 +204,7 @@ impl BlobStorageReader for DiskFileReader {
             .await
             .map_err(|e| anyhow::anyhow!("Failed to read file: {:?}, error: {}", file_path, e))?;
         tokio::spawn(async move {
-            let mut stream = get_result.into_stream();
-            while let Some(chunk) = stream.next().await {
-                if let Ok(chunk) = chunk {
-                    let _ = tx.send(Ok(chunk));
-                } else {
-                    let _ = tx.send(Err(anyhow::anyhow!(
-                        "Error reading file: {:?}",
-                        chunk.err()
-                    )));
-                    break;
-                }
-            }
+            forward_chunks(get_result.into_stream(), tx, &file_path).await;
         });
         Ok(Box::pin(UnboundedReceiverStream::new(rx)))
     }
@@ -246,6 +246,10 @@ mod tests {
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
         assert_eq!(contents, "testdatatestdata1testdata2");
+        assert_eq!(
+            storage.read(key).await?,
+            Some(Bytes::from_static(b"testdatatestdata1testdata2"))
+        );
 
         let entries = std::fs::read_dir(dir.path().join("tmp"))?;
         assert!(entries.count() == 0, "temp directory is not empty");
@@ -271,6 +275,7 @@ mod tests {
 
         let result = storage.put(key, Box::pin(data)).await;
         assert!(result.is_err());
+        assert!(storage.read(key).await?.is_none());
 
         let entries = std::fs::read_dir(dir.path().join("tmp"))?;
         assert!(entries.count() == 0, "temp directory is not empty");
@@ -9,7 +9,7 @@ use bytes::{Bytes, BytesMut};
 use futures::{stream::BoxStream, StreamExt};
 use object_store::aws::AmazonS3Builder;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWrite;
+use tokio::{io::AsyncWrite, sync::mpsc};
 
 use self::{disk::DiskFileReader, s3::S3FileReader};
 use crate::server_config::ServerConfig;
@@ -122,6 +122,46 @@ impl BlobStorage {
             storage.writer(key).await
         }
     }
+
+    /// Reads the blob written at key with put, None if there is no such blob
+    pub async fn read(&self, key: &str) -> Result<Option<Bytes>> {
+        if let Some(s3) = self.config.s3.as_ref() {
+            self.s3_storage(s3)?.read(key).await
+        } else {
+            disk::DiskStorage::new(
+                self.config
+                    .disk
+                    .clone()
+                    .unwrap_or_else(|| DiskStorageConfig {
+                        path: "blobs".to_string(),
+                    }),
+            )?
+            .read(key)
+            .await
+        }
+    }
+}
+
+impl BlobStorage {
+    /// Removes the blob written at key with put, if there is one
+    pub async fn remove(&self, key: &str) -> Result<()> {
+        if let Some(s3) = self.config.s3.as_ref() {
+            return self.s3_storage(s3)?.delete(key).await;
+        }
+        let config = self
+            .config
+            .disk
+            .clone()
+            .unwrap_or_else(|| DiskStorageConfig {
+                path: "blobs".to_string(),
+            });
+        let path = format!("{}/{}", config.path, key);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!("unable to remove file {}: {}", path, e)),
+        }
+    }
 }
 
 #[async_trait]
@@ -202,6 +242,23 @@ fn parse_s3_url(s3_url: &str) -> Result<(&str, &str), &str> {
     Ok((bucket, key))
 }
 
+/// Sends the chunks read from a blob to tx until the blob has been read or
+/// reading fails. Reading stops early once the receiver is dropped, so that
+/// blobs are not read to the end for requests which have been cancelled.
+pub(crate) async fn forward_chunks<E: Debug>(
+    mut stream: BoxStream<'static, Result<Bytes, E>>,
+    tx: mpsc::UnboundedSender<Result<Bytes>>,
+    path: &str,
+) {
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| anyhow::anyhow!("error reading {}: {:?}", path, e));
+        let failed = chunk.is_err();
+        if tx.send(chunk).is_err() || failed {
+            break;
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ContentReader {
     config: Arc<ServerConfig>,
@@ -346,4 +403,35 @@ mod tests {
 
         storage.delete("s3://test-bucket/test-key-3").await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_forward_chunks() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let chunks = stream::iter(vec![
+            Ok(Bytes::from("chunk_1")),
+            Err("read failed"),
+            Ok(Bytes::from("chunk_2")),
+        ])
+        .boxed();
+        forward_chunks(chunks, tx, "test-key").await;
+        let received: Vec<Result<Bytes>> = tokio_stream::wrappers::UnboundedReceiverStream::new(rx)
+            .collect()
+            .await;
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].as_ref().unwrap(), "chunk_1");
+        assert!(received[1].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forward_chunks_stops_when_receiver_dropped() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        drop(rx);
+        let chunks = stream::repeat(Ok::<_, String>(Bytes::from("chunk"))).boxed();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            forward_chunks(chunks, tx, "test-key"),
+        )
+        .await
+        .expect("reading should stop once the receiver is dropped");
+    }
 }
@@ -11,7 +11,13 @@ use object_store::{
 use tokio::{io::AsyncWriteExt, sync::mpsc};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
-use super::{BlobStoragePartWriter, BlobStorageReader, BlobStorageWriter, StoragePartWriter};
+use super::{
+    forward_chunks,
+    BlobStoragePartWriter,
+    BlobStorageReader,
+    BlobStorageWriter,
+    StoragePartWriter,
+};
 use crate::{blob_storage::PutResult, server_config::ServerConfig};
 
 pub struct S3Storage {
@@ -26,6 +32,15 @@ impl S3Storage {
             client,
         }
     }
+
+    /// Reads the object at key, None if there is no such object
+    pub async fn read(&self, key: &str) -> Result<Option<Bytes>> {
+        match self.client.get(&key.into()).await {
+            Ok(result) => Ok(Some(result.bytes().await?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(anyhow!("can't get s3 object {:?}: {:?}", key, e)),
+        }
+    }
 }
 
 #[async_trait]
@@ -104,16 +119,11 @@ impl BlobStorageReader for S3FileReader {
         let (tx, rx) = mpsc::unbounded_channel();
         let key = self.key.clone();
         let get_result = client_clone
-            .get(&key.into())
+            .get(&key.as_str().into())
             .await
             .map_err(|e| anyhow!("can't get s3 object {:?}: {:?}", self.key, e))?;
         tokio::spawn(async move {
-            let mut stream = get_result.into_stream();
-            while let Some(chunk) = stream.next().await {
-                if let Ok(chunk) = chunk {
-                    let _ = tx.send(Ok(chunk));
-                }
-            }
+            forward_chunks(get_result.into_stream(), tx, &key).await;
         });
         Ok(Box::pin(UnboundedReceiverStream::new(rx)))
     }
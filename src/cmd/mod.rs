@@ -1,5 +1,7 @@
 use clap::{Args, Parser, Subcommand};
 
+use crate::server_config::{ServerConfig, TracingConfig};
+
 mod coordinator;
 mod discovery;
 mod init_compose;
@@ -44,6 +46,18 @@ pub struct Cli {
 }
 
 impl Cli {
+    /// Tracing config of the config file of the server and coordinator
+    /// commands. It is read before tracing is set up, errors loading the
+    /// config are reported when the command loads it.
+    pub fn tracing_config(&self) -> Option<TracingConfig> {
+        let config_path = match &self.command {
+            Commands::Server(args) => args.config_path()?,
+            Commands::Coordinator(args) => args.config_path(),
+            _ => return None,
+        };
+        ServerConfig::from_path(config_path).ok()?.tracing
+    }
+
     /// Run the CLI
     pub async fn run(self) {
         match self.command {
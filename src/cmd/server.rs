@@ -21,6 +21,10 @@ pub struct Args {
 }
 
 impl Args {
+    pub fn config_path(&self) -> Option<&str> {
+        self.config_path.as_deref()
+    }
+
     pub async fn run(self, _: GlobalArgs) {
         let Self {
             config_path,
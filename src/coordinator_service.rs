@@ -123,7 +123,12 @@ use crate::{
     coordinator_client::CoordinatorClient,
     garbage_collector::GarbageCollector,
     server_config::ServerConfig,
-    state::{self, forwardable_raft::RaftState, grpc_config::GrpcConfig},
+    state::{
+        self,
+        forwardable_raft::RaftState,
+        grpc_config::GrpcConfig,
+        ExtractionGraphExistsError,
+    },
 };
 
 type HBResponseStream = Pin<Box<dyn Stream<Item = Result<HeartbeatResponse, Status>> + Send>>;
@@ -547,7 +552,12 @@ impl CoordinatorService for CoordinatorServiceServer {
             .coordinator
             .create_extraction_graph(graph.clone())
             .await
-            .map_err(|e| tonic::Status::aborted(e.to_string()))?;
+            .map_err(|e| {
+                if e.is::<ExtractionGraphExistsError>() {
+                    return tonic::Status::already_exists(e.to_string());
+                }
+                tonic::Status::aborted(e.to_string())
+            })?;
         let mut policies = HashMap::new();
         for policy in creation_result.extraction_policies {
             let extraction_policy = policy
@@ -1,15 +1,29 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
+    collections::{
+        hash_map::DefaultHasher,
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+        HashSet,
+    },
     fmt,
     hash::{Hash, Hasher},
     str::FromStr,
-    sync::Arc,
-    time::SystemTime,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{anyhow, Result};
+use arrow_array::{
+    builder::{Float32Builder, ListBuilder},
+    ArrayRef,
+    RecordBatch,
+    StringArray,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
 use bytes::Bytes;
-use filter::LabelsFilter;
+use filter::{Expression, LabelsFilter};
 use futures::{Stream, StreamExt};
 use indexify_internal_api::{self as internal_api};
 use indexify_proto::indexify_coordinator::{self, CreateContentStatus, ListActiveContentsRequest};
@@ -17,11 +31,13 @@ use mime::Mime;
 use nanoid::nanoid;
 use serde_json::json;
 use sha2::{Digest, Sha256};
+use thiserror::Error;
 use tracing::{error, info};
+use unicode_normalization::UnicodeNormalization as _;
 
 use crate::{
     api::{self, BeginExtractedContentIngest, ExtractionGraphLink, ExtractionGraphRequest},
-    blob_storage::{BlobStorage, BlobStorageWriter, PutResult, StoragePartWriter},
+    blob_storage::{BlobStorage, BlobStorageWriter, ContentReader, PutResult, StoragePartWriter},
     coordinator_client::{CoordinatorClient, CoordinatorServiceClient},
     grpc_helper::GrpcHelper,
     metadata_storage::{
@@ -30,14 +46,460 @@ use crate::{
         MetadataReaderTS,
         MetadataStorageTS,
     },
-    vector_index::{ScoredText, VectorIndexManager},
+    server_config::{
+        FragmentLimitAction,
+        IndexNameCase,
+        InvalidUtf8Action,
+        TextHashingConfig,
+        UnicodeNormalization,
+    },
+    vector_index::{SearchOptions, SearchResults, VectorIndexManager},
+    vectordbs::{IndexDistance, VectorChunk},
 };
 
+const EXPORT_PAGE_SIZE: u64 = 100;
+const IMPORT_BATCH_SIZE: usize = 100;
+const RECONCILE_PAGE_SIZE: u64 = 100;
+const DEFAULT_REEMBED_REQUESTS_PER_SECOND: f64 = 10.0;
+const INDEX_FIELDS_WRITE_ATTEMPTS: usize = 5;
+// Known metadata fields are read again after this long, so that fields
+// overwritten by the concurrent write of another server are written again
+const INDEX_FIELDS_CACHE_TTL: Duration = Duration::from_secs(60);
+// Policy params of indexes are read again after this long, so that graphs
+// deleted and created again by another server are picked up
+const POLICY_PARAMS_CACHE_TTL: Duration = Duration::from_secs(60);
+// Read only flags are read again after this long, so that a flag set on
+// another server applies to the writes of this server shortly after
+const READ_ONLY_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Returned when a write is sent to an index which is read only
+#[derive(Debug, Error)]
+#[error("index {index} is read only")]
+pub struct ReadOnlyIndexError {
+    pub index: String,
+}
+
+/// Returned when the missing index of add_vectors can't be created from the
+/// create_if_missing params of the request
+#[derive(Debug, Error)]
+#[error("unable to create index {index}: {reason}")]
+pub struct MissingIndexError {
+    pub index: String,
+    pub reason: String,
+}
+
+/// Returned when an extraction graph would create an index with an invalid
+/// name
+#[derive(Debug, Error)]
+#[error("invalid index name {name}: {reason}")]
+pub struct InvalidIndexNameError {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Checks that the name of an extraction graph or policy, which are parts of
+/// the names of indexes, is made of letters, digits, - and _
+fn check_name_part(kind: &str, name: &str) -> std::result::Result<(), InvalidIndexNameError> {
+    if name.is_empty() ||
+        !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(InvalidIndexNameError {
+            name: name.to_string(),
+            reason: format!("the {} name must be made of letters, digits, - and _", kind),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the name among names which differs from name only by case
+fn case_collision<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let lowercase = name.to_lowercase();
+    names
+        .into_iter()
+        .find(|other| *other != name && other.to_lowercase() == lowercase)
+}
+
+/// Returned when an index can't be re-embedded as requested
+#[derive(Debug, Error)]
+pub enum ReembedError {
+    #[error("unable to re-embed index {index} with extractor {extractor}: {reason}")]
+    InvalidExtractor {
+        index: String,
+        extractor: String,
+        reason: String,
+    },
+    #[error("re-embed job {job_id} of index {index} is still running")]
+    AlreadyRunning { index: String, job_id: String },
+}
+
+/// Hash of a text after the preprocessing of config, so that texts which only
+/// differ by their Unicode normal form or case are duplicates.
+fn text_hash(text: &str, config: &TextHashingConfig) -> String {
+    let mut text = match config.normalization {
+        Some(UnicodeNormalization::Nfc) => text.nfc().collect(),
+        Some(UnicodeNormalization::Nfkc) => text.nfkc().collect(),
+        None => text.to_string(),
+    };
+    if config.case_fold {
+        text = text.to_lowercase();
+    }
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Checks that content written in chunks is valid UTF-8, characters can be
+/// split across chunks. Invalid bytes are replaced or rejected depending on
+/// the action.
+struct Utf8Validator {
+    action: InvalidUtf8Action,
+    // Start of a character which continues in the next chunk
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Utf8Validator {
+    fn new(action: InvalidUtf8Action) -> Self {
+        Self {
+            action,
+            pending: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Returns the valid text of the chunk, with invalid bytes replaced.
+    /// Bytes of a character which continues in the next chunk are kept until
+    /// the next chunk.
+    fn push(&mut self, chunk: &[u8]) -> Result<Bytes> {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(chunk);
+        let mut output = Vec::with_capacity(input.len());
+        let mut start = 0;
+        while let Err(e) = std::str::from_utf8(&input[start..]) {
+            let valid_end = start + e.valid_up_to();
+            output.extend_from_slice(&input[start..valid_end]);
+            let Some(invalid_len) = e.error_len() else {
+                self.pending = input[valid_end..].to_vec();
+                start = input.len();
+                break;
+            };
+            self.invalid(valid_end, &mut output)?;
+            start = valid_end + invalid_len;
+        }
+        output.extend_from_slice(&input[start..]);
+        self.offset += input.len() - self.pending.len();
+        Ok(Bytes::from(output))
+    }
+
+    /// Returns the replacement of the character left incomplete at the end
+    /// of the content, if any.
+    fn finish(mut self) -> Result<Bytes> {
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            self.invalid(0, &mut output)?;
+        }
+        Ok(Bytes::from(output))
+    }
+
+    fn invalid(&mut self, position: usize, output: &mut Vec<u8>) -> Result<()> {
+        match self.action {
+            InvalidUtf8Action::Reject => Err(anyhow!(
+                "content is not valid UTF-8 at byte {}",
+                self.offset + position
+            )),
+            _ => {
+                output.extend_from_slice(char::REPLACEMENT_CHARACTER.to_string().as_bytes());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Validates the UTF-8 of the stream with a Utf8Validator
+fn validate_utf8(
+    mut data: impl Stream<Item = Result<Bytes>> + Send + Unpin,
+    action: InvalidUtf8Action,
+) -> impl Stream<Item = Result<Bytes>> + Send + Unpin {
+    Box::pin(async_stream::try_stream! {
+        let mut validator = Utf8Validator::new(action);
+        while let Some(chunk) = data.next().await {
+            yield validator.push(&chunk?)?;
+        }
+        yield validator.finish()?;
+    })
+}
+
+/// Returns the embedding output of an extractor used to re-embed an index
+/// of dimension dim, the requested output if it is set.
+fn reembed_output(
+    outputs: &HashMap<String, api::ExtractorOutputSchema>,
+    dim: usize,
+    requested: Option<&str>,
+) -> std::result::Result<String, String> {
+    let embedding_dim = |name: &str| match outputs.get(name) {
+        Some(api::ExtractorOutputSchema::Embedding(schema)) => Some(schema.dim),
+        _ => None,
+    };
+    if let Some(output) = requested {
+        return match embedding_dim(output) {
+            Some(output_dim) if output_dim == dim => Ok(output.to_string()),
+            Some(output_dim) => Err(format!(
+                "output {} has dimension {}, the index has dimension {}",
+                output, output_dim, dim
+            )),
+            None => Err(format!("extractor has no embedding output {}", output)),
+        };
+    }
+    let mut matching: Vec<&String> = outputs
+        .keys()
+        .filter(|name| embedding_dim(name) == Some(dim))
+        .collect();
+    match matching.len() {
+        0 => Err(format!(
+            "extractor has no embedding output of dimension {}",
+            dim
+        )),
+        1 => Ok(matching.remove(0).clone()),
+        _ => {
+            matching.sort();
+            Err(format!(
+                "extractor has several embedding outputs of dimension {}, select one of {:?}",
+                dim, matching
+            ))
+        }
+    }
+}
+
+/// Schema of the record batches of an Arrow index export. Metadata columns
+/// hold the metadata serialized as JSON, since its fields differ between
+/// vectors.
+fn export_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("content_id", DataType::Utf8, false),
+        Field::new(
+            "embedding",
+            DataType::List(Arc::new(Field::new("item", DataType::Float32, true))),
+            true,
+        ),
+        Field::new("metadata", DataType::Utf8, false),
+        Field::new("content_metadata", DataType::Utf8, false),
+        Field::new("root_content_metadata", DataType::Utf8, true),
+    ]))
+}
+
+fn export_batch(schema: &SchemaRef, vectors: Vec<api::ExportedVector>) -> Result<RecordBatch> {
+    let mut content_ids = Vec::new();
+    let mut embeddings = ListBuilder::new(Float32Builder::new());
+    let mut metadata = Vec::new();
+    let mut content_metadata = Vec::new();
+    let mut root_content_metadata = Vec::new();
+    for vector in vectors {
+        content_ids.push(vector.content_id);
+        match vector.embedding {
+            Some(embedding) => embeddings.append_value(embedding.into_iter().map(Some)),
+            None => embeddings.append_null(),
+        }
+        metadata.push(serde_json::to_string(&vector.metadata)?);
+        content_metadata.push(serde_json::to_string(&vector.content_metadata)?);
+        root_content_metadata.push(
+            vector
+                .root_content_metadata
+                .map(|root| serde_json::to_string(&root))
+                .transpose()?,
+        );
+    }
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(content_ids)),
+        Arc::new(embeddings.finish()),
+        Arc::new(StringArray::from(metadata)),
+        Arc::new(StringArray::from(content_metadata)),
+        Arc::new(StringArray::from(root_content_metadata)),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Buffer an Arrow StreamWriter writes into, from which the written bytes are
+/// taken to be sent after every batch
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct WriteStreamResult {
     pub url: String,
     pub size_bytes: u64,
     pub hash: String,
     pub file_name: String,
+    /// The content is empty or only made of ASCII whitespace
+    pub only_whitespace: bool,
+}
+
+impl WriteStreamResult {
+    /// Why content of content_type is not added, if it is text which is empty
+    /// or only contains whitespace, since it has nothing to extract
+    pub fn empty_text_reason(&self, content_type: &str) -> Option<&'static str> {
+        if !content_type.starts_with("text/") || !self.only_whitespace {
+            None
+        } else if self.size_bytes == 0 {
+            Some("text is empty")
+        } else {
+            Some("text only contains whitespace")
+        }
+    }
+}
+
+/// Outcome of writing an extracted embedding to its index
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmbeddingWrite {
+    Written,
+    /// The chunk is shorter than the min_chunk_chars of the index
+    TooShort,
+    /// The chunk is beyond the max_fragments_per_document of the index
+    BeyondMaxFragments(String),
+}
+
+/// Position of an extracted chunk within the content it was extracted from.
+/// Offsets are counted in characters of text chunks and assume the extractor
+/// emits chunks in document order without overlap. source_length is the
+/// number of characters of the source content, it is only known for text
+/// sources.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkPosition {
+    pub chunk_index: u64,
+    pub char_start: u64,
+    pub char_end: u64,
+    pub source_length: Option<u64>,
+}
+
+impl ChunkPosition {
+    pub fn char_len(&self) -> u64 {
+        self.char_end - self.char_start
+    }
+
+    pub fn add_to_metadata(&self, metadata: &mut HashMap<String, serde_json::Value>) {
+        metadata.insert("chunk_index".to_string(), json!(self.chunk_index));
+        metadata.insert("char_start".to_string(), json!(self.char_start));
+        metadata.insert("char_end".to_string(), json!(self.char_end));
+        if let Some(source_length) = self.source_length {
+            metadata.insert("source_length".to_string(), json!(source_length));
+        }
+    }
+}
+
+/// Uses the distance recommended for the extractor in model_distances for
+/// embedding schemas without a distance. A schema with a distance other than
+/// the recommended one is kept, since the extractor chose it, but logged.
+fn resolve_distance(
+    model_distances: &HashMap<String, IndexDistance>,
+    extractor: &str,
+    mut schema: internal_api::EmbeddingSchema,
+) -> Result<internal_api::EmbeddingSchema> {
+    let Some(recommended) = model_distances.get(extractor) else {
+        return Ok(schema);
+    };
+    if schema.distance.is_empty() {
+        schema.distance = recommended.to_string();
+        return Ok(schema);
+    }
+    let distance = IndexDistance::from_str(&schema.distance)?;
+    if distance != *recommended {
+        tracing::warn!(
+            "embedding schema of extractor {} uses distance {}, but {} is recommended for it",
+            extractor,
+            distance,
+            recommended
+        );
+    }
+    Ok(schema)
+}
+
+/// Metadata fields of the vectors of an index, with the JSON types of their
+/// values
+type IndexFields = BTreeMap<String, BTreeSet<String>>;
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+/// Returns the fields of metadata with the types of their values
+fn metadata_fields<'a>(
+    metadata: impl IntoIterator<Item = &'a HashMap<String, serde_json::Value>>,
+) -> IndexFields {
+    let mut fields = IndexFields::new();
+    for (name, value) in metadata.into_iter().flatten() {
+        fields
+            .entry(name.clone())
+            .or_default()
+            .insert(json_type(value).to_string());
+    }
+    fields
+}
+
+/// Whether fields has a field, or a type of a field, which known hasn't
+fn has_new_fields(known: &IndexFields, fields: &IndexFields) -> bool {
+    fields.iter().any(|(name, types)| {
+        known
+            .get(name)
+            .map_or(true, |known_types| !types.is_subset(known_types))
+    })
+}
+
+fn merge_fields(into: &mut IndexFields, from: IndexFields) {
+    for (name, types) in from {
+        into.entry(name).or_default().extend(types);
+    }
+}
+
+/// Checks that a filter compares a metadata field of an index with a value of
+/// one of the types of the field. Indexes without recorded fields, for
+/// example written to before fields were recorded, accept every filter.
+fn check_filter_field(fields: &IndexFields, expression: &Expression) -> Result<()> {
+    if fields.is_empty() ||
+        matches!(
+            expression.operator,
+            filter::Operator::Exists | filter::Operator::NotExists
+        )
+    {
+        return Ok(());
+    }
+    let types = fields.get(&expression.key).ok_or(anyhow!(
+        "filter {} has an unknown field {}",
+        expression,
+        expression.key
+    ))?;
+    let value_type = json_type(&expression.value);
+    if !types.contains(value_type) {
+        return Err(anyhow!(
+            "filter {} compares with a {}, values of field {} are of type {}",
+            expression,
+            value_type,
+            expression.key,
+            types.iter().cloned().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    Ok(())
 }
 
 fn index_in_features(
@@ -59,6 +521,49 @@ pub struct DataManager {
     metadata_reader: MetadataReaderTS,
     blob_storage: Arc<BlobStorage>,
     coordinator_client: Arc<CoordinatorClient>,
+    // Serializes updates of the snapshot lists of the indexes
+    snapshots_lock: tokio::sync::Mutex<()>,
+    // Re-embed jobs started on this server, keyed by job id
+    reembed_jobs: RwLock<HashMap<String, api::ReembedJob>>,
+    // Serializes the creation of missing indexes by add_vectors
+    index_creation_lock: tokio::sync::Mutex<()>,
+    // Metadata fields of the indexes written to by this server, keyed by
+    // table name. The lock of every index serializes the updates of its
+    // fields in blob storage.
+    index_fields: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<KnownFields>>>>>,
+    // Input params of the extraction policies of indexes, keyed by table name
+    policy_params: std::sync::Mutex<HashMap<String, Cached<Option<serde_json::Value>>>>,
+    // Embedding outputs of the extractors of indexes, keyed by table name
+    embedding_outputs: std::sync::Mutex<HashMap<String, Cached<String>>>,
+    // Read only flags of indexes in blob storage, keyed by table name
+    read_only_flags: std::sync::Mutex<HashMap<String, Cached<bool>>>,
+}
+
+/// Metadata fields of an index as they were last read from blob storage
+struct KnownFields {
+    fields: IndexFields,
+    read_at: Instant,
+}
+
+/// Value of an index as it was last read from the coordinator or blob
+/// storage
+struct Cached<T> {
+    value: T,
+    read_at: Instant,
+}
+
+impl<T: Clone> Cached<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            read_at: Instant::now(),
+        }
+    }
+
+    /// Returns the value unless it is older than ttl
+    fn fresh(&self, ttl: Duration) -> Option<T> {
+        (self.read_at.elapsed() < ttl).then(|| self.value.clone())
+    }
 }
 
 impl fmt::Debug for DataManager {
@@ -81,6 +586,13 @@ impl DataManager {
             metadata_reader,
             blob_storage,
             coordinator_client,
+            snapshots_lock: tokio::sync::Mutex::new(()),
+            reembed_jobs: RwLock::new(HashMap::new()),
+            index_creation_lock: tokio::sync::Mutex::new(()),
+            index_fields: std::sync::Mutex::new(HashMap::new()),
+            policy_params: std::sync::Mutex::new(HashMap::new()),
+            embedding_outputs: std::sync::Mutex::new(HashMap::new()),
+            read_only_flags: std::sync::Mutex::new(HashMap::new()),
         }
     }
 
@@ -93,6 +605,8 @@ impl DataManager {
         namespace: String,
         extraction_graph: String,
     ) -> Result<()> {
+        // A graph created again with the same name can have other params
+        let prefix = format!("{}.{}.", namespace, extraction_graph);
         let req = indexify_coordinator::DeleteExtractionGraphRequest {
             namespace,
             extraction_graph,
@@ -101,6 +615,293 @@ impl DataManager {
             .await?
             .delete_extraction_graph(req)
             .await?;
+        self.policy_params
+            .lock()
+            .unwrap()
+            .retain(|table_name, _| !table_name.starts_with(&prefix));
+        self.embedding_outputs
+            .lock()
+            .unwrap()
+            .retain(|table_name, _| !table_name.starts_with(&prefix));
+        Ok(())
+    }
+
+    /// Deletes every extraction graph of every namespace, which deletes their
+    /// indexes. Returns the names of the deleted indexes, the vector
+    /// databases drop their tables once the coordinator has garbage collected
+    /// the indexes.
+    #[tracing::instrument]
+    pub async fn reset(&self) -> Result<Vec<String>> {
+        let deleted_indexes = self.list_all_indexes().await?;
+        for namespace in self.list_namespaces().await? {
+            for graph in self.list_extraction_graphs(&namespace.name).await? {
+                info!(
+                    "reset: deleting extraction graph {}/{}",
+                    namespace.name, graph.name
+                );
+                self.delete_extraction_graph(namespace.name.clone(), graph.name)
+                    .await?;
+            }
+        }
+        Ok(deleted_indexes)
+    }
+
+    /// Loads an index from its vector database, see
+    /// VectorIndexManager::preload. Returns the number of vectors of the
+    /// index.
+    pub async fn preload_index(&self, namespace: &str, index_name: &str) -> Result<u64> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.vector_index_manager.preload(&index.table_name).await
+    }
+
+    /// Returns the names of the indexes of every namespace, prefixed with
+    /// their namespace.
+    pub async fn list_all_indexes(&self) -> Result<Vec<String>> {
+        let mut indexes = Vec::new();
+        for namespace in self.list_namespaces().await? {
+            for index in self.list_indexes(&namespace.name).await? {
+                indexes.push(format!("{}/{}", namespace.name, index.name));
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// Removes the vectors of an index whose content no longer exists, for
+    /// example when the content was deleted while the vector database was
+    /// unreachable. Content without vectors is not changed, its vectors are
+    /// written again when its extraction policy runs. With dry_run the
+    /// orphaned vectors are only reported.
+    #[tracing::instrument(skip(self))]
+    pub async fn reconcile_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        dry_run: bool,
+    ) -> Result<api::ReconcileResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        // Reconciling removes orphaned vectors and embeds missing content
+        if !dry_run {
+            self.check_writable(&index).await?;
+        }
+        let mut vectors_checked = 0;
+        let mut orphaned_content_ids = Vec::new();
+        // Content with a vector, or whose chunks have vectors
+        let mut indexed_content_ids = HashSet::new();
+        let mut cursor = None;
+        loop {
+            let page = self
+                .vector_index_manager
+                .scroll(&index.table_name, cursor, RECONCILE_PAGE_SIZE)
+                .await?;
+            vectors_checked += page.chunks.len() as u64;
+            let content_ids: Vec<String> = page
+                .chunks
+                .into_iter()
+                .map(|chunk| {
+                    if let Some(parent_id) = chunk.content_metadata.parent_id {
+                        indexed_content_ids.insert(parent_id.id);
+                    }
+                    chunk.content_id
+                })
+                .collect();
+            indexed_content_ids.extend(content_ids.iter().cloned());
+            let existing: HashSet<String> = self
+                .get_content_metadata(namespace, content_ids.clone())
+                .await?
+                .into_iter()
+                .map(|content| content.id)
+                .collect();
+            orphaned_content_ids.extend(
+                content_ids
+                    .into_iter()
+                    .filter(|content_id| !existing.contains(content_id)),
+            );
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        // Vectors are removed once the whole index is read, so that removing
+        // them doesn't move the cursor of the scroll
+        for content_id in &orphaned_content_ids {
+            if dry_run {
+                info!(
+                    "reconcile: found orphaned vector {} in index {}/{}",
+                    content_id, namespace, index_name
+                );
+                continue;
+            }
+            self.vector_index_manager
+                .remove_embedding(&index.table_name, content_id)
+                .await?;
+            info!(
+                "reconcile: removed orphaned vector {} from index {}/{}",
+                content_id, namespace, index_name
+            );
+        }
+        let missing_content_ids = self
+            .reconcile_missing_content(&index, &indexed_content_ids, dry_run)
+            .await?;
+        Ok(api::ReconcileResponse {
+            index: index_name.to_string(),
+            vectors_checked,
+            orphaned_content_ids,
+            missing_content_ids,
+            dry_run,
+        })
+    }
+
+    /// Finds the content the extraction policy of the index completed on
+    /// which has no vector in the index, and embeds it with the extractor of
+    /// the index unless dry_run is set.
+    async fn reconcile_missing_content(
+        &self,
+        index: &indexify_coordinator::Index,
+        indexed_content_ids: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<Vec<String>> {
+        let namespace = &index.namespace;
+        let Some(policy) = self
+            .coordinator_client
+            .list_extraction_graphs(namespace)
+            .await?
+            .into_iter()
+            .filter(|graph| graph.name == index.graph_name)
+            .flat_map(|graph| graph.extraction_policies)
+            .find(|policy| policy.name == index.extraction_policy)
+        else {
+            return Ok(Vec::new());
+        };
+        let Some(output) = policy
+            .output_table_mapping
+            .iter()
+            .find(|(_, table_name)| **table_name == index.table_name)
+            .map(|(output, _)| output.clone())
+        else {
+            return Ok(Vec::new());
+        };
+        let mut missing = Vec::new();
+        let mut restart_key = Vec::new();
+        loop {
+            let response = self
+                .get_coordinator_client()
+                .await?
+                .list_content(indexify_coordinator::ListContentRequest {
+                    namespace: namespace.clone(),
+                    graph: index.graph_name.clone(),
+                    source: Some(String::new().into()),
+                    limit: RECONCILE_PAGE_SIZE,
+                    restart_key,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            missing.extend(response.content_list.into_iter().filter(|content| {
+                content.extraction_policy_ids.contains_key(&policy.id) &&
+                    !indexed_content_ids.contains(&content.id)
+            }));
+            restart_key = response.restart_key;
+            if restart_key.is_empty() {
+                break;
+            }
+        }
+        let input_params = self.policy_input_params(namespace, index).await?;
+        let mut missing_content_ids = Vec::new();
+        for content in missing {
+            missing_content_ids.push(content.id.clone());
+            if dry_run {
+                info!(
+                    "reconcile: found content {} without vector in index {}",
+                    content.id, index.table_name
+                );
+                continue;
+            }
+            let content_id = content.id.clone();
+            let embedded = self
+                .embed_missing_content(index, &output, input_params.clone(), content)
+                .await?;
+            if embedded {
+                info!(
+                    "reconcile: added missing vector {} to index {}",
+                    content_id, index.table_name
+                );
+            }
+        }
+        Ok(missing_content_ids)
+    }
+
+    /// Embeds content the index has no vector of, with the metadata its
+    /// vector gets when it is extracted
+    async fn embed_missing_content(
+        &self,
+        index: &indexify_coordinator::Index,
+        output: &str,
+        input_params: Option<serde_json::Value>,
+        content: indexify_coordinator::ContentMetadata,
+    ) -> Result<bool> {
+        let metadata = self
+            .metadata_index_manager
+            .get_metadata_for_content(&index.namespace, &content.id)
+            .await?;
+        let labels = internal_api::utils::convert_map_prost_to_serde_json(content.labels.clone())?;
+        let root_content_metadata =
+            if content.root_content_id.is_empty() || content.root_content_id == content.id {
+                None
+            } else {
+                self.get_coordinator_client()
+                    .await?
+                    .get_content_metadata(indexify_coordinator::GetContentMetadataRequest {
+                        content_list: vec![content.root_content_id.clone()],
+                    })
+                    .await?
+                    .into_inner()
+                    .content_list
+                    .into_iter()
+                    .next()
+                    .map(|root| root.try_into())
+                    .transpose()?
+            };
+        let chunk = VectorChunk {
+            content_id: content.id.clone(),
+            embedding: Vec::new(),
+            metadata: DataManager::combine_metadata(metadata, &[], labels),
+            root_content_metadata,
+            content_metadata: content.try_into()?,
+        };
+        self.vector_index_manager
+            .reembed(
+                &index.table_name,
+                &index.extractor,
+                output,
+                input_params,
+                chunk,
+            )
+            .await
+    }
+
+    /// Reconciles every index of every namespace. Indexes which fail to
+    /// reconcile are logged and skipped.
+    pub async fn reconcile_all(&self) -> Result<()> {
+        for namespace in self.list_namespaces().await? {
+            for index in self.list_indexes(&namespace.name).await? {
+                if let Err(e) = self
+                    .reconcile_index(&namespace.name, &index.name, false)
+                    .await
+                {
+                    if e.is::<ReadOnlyIndexError>() {
+                        info!(
+                            "reconcile: skipping read only index {}/{}",
+                            namespace.name, index.name
+                        );
+                        continue;
+                    }
+                    error!(
+                        "reconcile: unable to reconcile index {}/{}: {}",
+                        namespace.name, index.name, e
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
@@ -203,6 +1004,74 @@ impl DataManager {
         Ok(api_links)
     }
 
+    /// Checks the names of the indexes the extraction policies of a graph
+    /// create, and checks the indexes against the capabilities of their
+    /// vector databases
+    async fn check_graph_indexes(
+        &self,
+        namespace: &str,
+        req: &ExtractionGraphRequest,
+    ) -> Result<()> {
+        // The namespace is the first part of the table names of the indexes
+        check_name_part("namespace", namespace)?;
+        check_name_part("extraction graph", &req.name)?;
+        for policy in &req.extraction_policies {
+            check_name_part("extraction policy", &policy.name)?;
+        }
+        let name_case = self.coordinator_client.config.index_config.index_name_case;
+        // Tables of every namespace share the vector databases, so table
+        // names are compared across namespaces
+        let mut table_names = Vec::new();
+        if name_case == IndexNameCase::Insensitive {
+            for other_namespace in self.list_namespaces().await? {
+                for index in self.list_indexes(&other_namespace.name).await? {
+                    table_names.push(format!("{}.{}", other_namespace.name, index.name));
+                }
+            }
+        }
+        let extractors = self
+            .get_coordinator_client()
+            .await?
+            .list_extractors(indexify_coordinator::ListExtractorsRequest {})
+            .await?
+            .into_inner()
+            .extractors;
+        for policy in &req.extraction_policies {
+            // Unknown extractors are rejected by the coordinator
+            let Some(extractor) = extractors.iter().find(|e| e.name == policy.extractor) else {
+                continue;
+            };
+            for (output, output_schema) in &extractor.embedding_schemas {
+                let embedding_schema = resolve_distance(
+                    &self.coordinator_client.config.model_distances,
+                    &policy.extractor,
+                    serde_json::from_str(output_schema)?,
+                )?;
+                let index_name = format!("{}.{}.{}", req.name, policy.name, output);
+                let table_name = format!("{}.{}", namespace, index_name);
+                if name_case == IndexNameCase::Insensitive {
+                    if let Some(other) =
+                        case_collision(&table_name, table_names.iter().map(String::as_str))
+                    {
+                        return Err(InvalidIndexNameError {
+                            reason: format!(
+                                "its table {} differs only by case from table {}",
+                                table_name, other
+                            ),
+                            name: index_name,
+                        }
+                        .into());
+                    }
+                    table_names.push(table_name.clone());
+                }
+                self.vector_index_manager
+                    .check_index(&table_name, &embedding_schema)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn create_extraction_graph(
         &self,
         namespace: &str,
@@ -232,6 +1101,9 @@ impl DataManager {
             };
             extraction_policies.push(req);
         }
+        // Indexes are checked before the graph is created, so that an invalid
+        // index, or one its vector database doesn't support, fails the request
+        self.check_graph_indexes(namespace, &req).await?;
         let req = indexify_coordinator::CreateExtractionGraphRequest {
             namespace: namespace.to_string(),
             name: req.name,
@@ -253,8 +1125,11 @@ impl DataManager {
                     policy.extractor
                 ),))?;
             for (name, output_schema) in &extractor.embedding_schemas {
-                let embedding_schema: internal_api::EmbeddingSchema =
-                    serde_json::from_str(output_schema)?;
+                let embedding_schema = resolve_distance(
+                    &self.coordinator_client.config.model_distances,
+                    &policy.extractor,
+                    serde_json::from_str(output_schema)?,
+                )?;
                 let table_name = policy.output_table_mapping.get(name).unwrap();
                 let _ = self
                     .vector_index_manager
@@ -402,9 +1277,37 @@ impl DataManager {
         }
     }
 
+    /// Returns the indexes of a garbage collection task which are writable,
+    /// read only indexes are skipped with a warning
+    async fn writable_gc_tables<'a>(
+        &self,
+        gc_task: &'a indexify_coordinator::GcTask,
+    ) -> Result<Vec<&'a String>> {
+        let mut tables = Vec::new();
+        for table in &gc_task.output_tables {
+            if self.is_read_only(table).await? {
+                tracing::warn!(
+                    "skipping read only index {} of gc task {}",
+                    table,
+                    gc_task.task_id
+                );
+                continue;
+            }
+            tables.push(table);
+        }
+        Ok(tables)
+    }
+
+    /// Drops the indexes of a deleted extraction graph, read only indexes
+    /// included since nothing else could delete them, along with their read
+    /// only flags
     async fn drop_indexes(&self, gc_task: &indexify_coordinator::GcTask) -> Result<()> {
         for table in &gc_task.output_tables {
             self.vector_index_manager.drop_index(table).await?;
+            self.blob_storage
+                .remove(&Self::read_only_key(table))
+                .await?;
+            self.read_only_flags.lock().unwrap().remove(table);
         }
         Ok(())
     }
@@ -430,9 +1333,14 @@ impl DataManager {
         let content_metadata_labels =
             internal_api::utils::convert_map_prost_to_serde_json(content_metadata.labels.clone())?;
         let new_metadata = DataManager::combine_metadata(metadata, &[], content_metadata_labels);
-        for table in &gc_task.output_tables {
+        for table in self.writable_gc_tables(gc_task).await? {
             self.vector_index_manager
-                .update_metadata(table, gc_task.content_id.clone(), new_metadata.clone())
+                .update_metadata(
+                    table,
+                    gc_task.content_id.clone(),
+                    new_metadata.clone(),
+                    true,
+                )
                 .await?;
         }
         Ok(())
@@ -443,8 +1351,9 @@ impl DataManager {
         //  Remove content from blob storage
         self.blob_storage.delete(&gc_task.blob_store_path).await?;
 
-        //  Remove features and embeddings from vector stores
-        for table in &gc_task.output_tables {
+        //  Remove features and embeddings from vector stores, the vectors of read
+        //  only indexes are removed by reconciliation once they are writable
+        for table in self.writable_gc_tables(gc_task).await? {
             self.vector_index_manager
                 .remove_embedding(table, &gc_task.content_id)
                 .await?;
@@ -642,6 +1551,35 @@ impl DataManager {
         format!("{:x}", s.finish())
     }
 
+    /// Id of a chunk extracted from the content with id source_id, derived
+    /// from the position and hash of the chunk so that the same chunk always
+    /// gets the same id.
+    pub fn make_fragment_id(source_id: &str, chunk_index: u64, content_hash: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}:{}:{}", source_id, chunk_index, content_hash));
+        format!("{:x}", hasher.finalize())[..16].to_string()
+    }
+
+    /// Whether chunks written to any of the indexes of output_index_map get
+    /// deterministic ids.
+    pub fn deterministic_fragment_ids(&self, output_index_map: &HashMap<String, String>) -> bool {
+        output_index_map.values().any(|index_table| {
+            self.vector_index_manager
+                .index_settings(index_table)
+                .deterministic_fragment_ids
+        })
+    }
+
+    /// Whether chunks written to any of the indexes of output_index_map carry
+    /// their position in the metadata.
+    pub fn enrich_chunk_metadata(&self, output_index_map: &HashMap<String, String>) -> bool {
+        output_index_map.values().any(|index_table| {
+            self.vector_index_manager
+                .index_settings(index_table)
+                .enrich_chunk_metadata
+        })
+    }
+
     /// Checks if the given string is a valid hexadecimal.
     pub fn is_hex_string(s: &str) -> bool {
         s.chars().all(|c| c.is_ascii_hexdigit())
@@ -654,9 +1592,11 @@ impl DataManager {
         file_name: Option<&str>,
     ) -> Result<WriteStreamResult> {
         let mut hasher = Sha256::new();
+        let mut only_whitespace = true;
         let hashed_stream = data.map(|item| {
             item.map(|bytes| {
                 hasher.update(&bytes);
+                only_whitespace = only_whitespace && bytes.iter().all(u8::is_ascii_whitespace);
                 bytes
             })
         });
@@ -675,9 +1615,35 @@ impl DataManager {
             size_bytes: res.size_bytes,
             hash: format!("{:x}", hash_result),
             file_name,
+            only_whitespace,
         })
     }
 
+    /// Hash of content written by write_stream, which detects duplicate
+    /// content. Text content is read again and hashed after the preprocessing
+    /// of text_hashing if it is configured, since normalization applies to the
+    /// whole text.
+    pub async fn content_hash(
+        &self,
+        content_type: &str,
+        write_result: &WriteStreamResult,
+    ) -> Result<String> {
+        let text_hashing = &self.coordinator_client.config.text_hashing;
+        if !content_type.starts_with("text/") || *text_hashing == TextHashingConfig::default() {
+            return Ok(write_result.hash.clone());
+        }
+        let bytes = self
+            .blob_storage
+            .read(&write_result.file_name)
+            .await?
+            .ok_or_else(|| anyhow!("content {} was not written", write_result.url))?;
+        // Text which isn't valid UTF-8 is hashed as it is stored
+        Ok(std::str::from_utf8(&bytes).map_or_else(
+            |_| write_result.hash.clone(),
+            |text| text_hash(text, text_hashing),
+        ))
+    }
+
     async fn write_content_bytes(
         &self,
         namespace: &str,
@@ -698,8 +1664,17 @@ impl DataManager {
             id = original_content_id.unwrap().to_string();
         }
 
-        let res = self.write_stream(namespace, data, file_name).await?;
+        let invalid_utf8 = self.coordinator_client.config.invalid_utf8;
+        let validate =
+            content_type.starts_with("text/") && invalid_utf8 != InvalidUtf8Action::Accept;
+        let res = if validate {
+            let data = validate_utf8(data, invalid_utf8);
+            self.write_stream(namespace, data, file_name).await?
+        } else {
+            self.write_stream(namespace, data, file_name).await?
+        };
 
+        let hash = self.content_hash(&content_type, &res).await?;
         let labels = internal_api::utils::convert_map_serde_to_prost_json(labels)?;
 
         Ok(indexify_coordinator::ContentMetadata {
@@ -714,7 +1689,7 @@ impl DataManager {
             labels,
             source: source.to_string(),
             size_bytes: res.size_bytes,
-            hash: res.hash,
+            hash,
             extraction_policy_ids: HashMap::new(),
             extraction_graph_names: extraction_graph_names.to_vec(),
             extracted_metadata: json!({}).to_string(),
@@ -745,25 +1720,75 @@ impl DataManager {
         embedding: &[f32],
         content_id: &str,
         output_index_map: &HashMap<String, String>,
-        metadata: HashMap<String, serde_json::Value>,
+        mut metadata: HashMap<String, serde_json::Value>,
         root_content_metadata: Option<internal_api::ContentMetadata>,
         content_metadata: internal_api::ContentMetadata,
-    ) -> Result<()> {
-        let embeddings = internal_api::ExtractedEmbeddings {
+        chunk_position: Option<&ChunkPosition>,
+    ) -> Result<EmbeddingWrite> {
+        let index_table = output_index_map
+            .get(name)
+            .ok_or(anyhow!("index table not {} found", name))?;
+        self.check_table_writable(index_table, index_table).await?;
+        let settings = self.vector_index_manager.index_settings(index_table);
+        if let Some(chunk_position) = chunk_position {
+            if let Some(max_fragments) = settings.max_fragments_per_document {
+                if chunk_position.chunk_index >= max_fragments {
+                    // Chunks are counted per content they are extracted from
+                    let document = content_metadata
+                        .parent_id
+                        .as_ref()
+                        .map_or(content_id, |id| id.id.as_str());
+                    if settings.fragment_limit_action == FragmentLimitAction::Reject {
+                        return Err(anyhow!(
+                            "content {} has more than {} chunks, the max_fragments_per_document of index {}",
+                            document,
+                            max_fragments,
+                            index_table
+                        ));
+                    }
+                    tracing::warn!(
+                        "dropping chunk {} of content {} from index {}, max_fragments_per_document is {}",
+                        chunk_position.chunk_index,
+                        document,
+                        index_table,
+                        max_fragments
+                    );
+                    self.vector_index_manager.record_dropped_chunk(index_table);
+                    return Ok(EmbeddingWrite::BeyondMaxFragments(index_table.clone()));
+                }
+            }
+            if let Some(min_chunk_chars) = settings.min_chunk_chars {
+                if content_metadata.content_type.starts_with("text/") &&
+                    chunk_position.char_len() < min_chunk_chars
+                {
+                    info!(
+                        "dropping chunk {} of {} characters from index {}, min_chunk_chars is {}",
+                        content_id,
+                        chunk_position.char_len(),
+                        index_table,
+                        min_chunk_chars
+                    );
+                    self.vector_index_manager.record_dropped_chunk(index_table);
+                    return Ok(EmbeddingWrite::TooShort);
+                }
+            }
+            if settings.enrich_chunk_metadata {
+                chunk_position.add_to_metadata(&mut metadata);
+            }
+        }
+        let mut embeddings = vec![internal_api::ExtractedEmbeddings {
             content_id: content_id.to_string(),
             embedding: embedding.to_vec(),
             metadata,
             root_content_metadata,
             content_metadata,
-        };
-        let index_table = output_index_map
-            .get(name)
-            .ok_or(anyhow!("index table not {} found", name))?;
+        }];
         self.vector_index_manager
-            .add_embedding(index_table, vec![embeddings])
+            .truncate_embeddings(index_table, &mut embeddings)?;
+        self.add_embeddings(index_table, embeddings)
             .await
             .map_err(|e| anyhow!("unable to add embedding to vector index {}", e))?;
-        Ok(())
+        Ok(EmbeddingWrite::Written)
     }
 
     // Combine metadata from existing metadata and new features into single json
@@ -823,19 +1848,26 @@ impl DataManager {
             features.clone(),
             new_metadata.clone(),
             output_index_mapping,
+            None,
         )
         .await?;
         if metadata_updated && !new_metadata.is_empty() {
             // For all embeddings not updated with new values, update their metadata
             for index in index_tables {
                 if !index_in_features(output_index_mapping, &features, index) {
+                    self.check_table_writable(index, index).await?;
                     info!(
                         "updating metadata for content {} index {}",
                         content_metadata.id.clone(),
                         index
                     );
                     self.vector_index_manager
-                        .update_metadata(index, content_metadata.id.clone(), new_metadata.clone())
+                        .update_metadata(
+                            index,
+                            content_metadata.id.clone(),
+                            new_metadata.clone(),
+                            true,
+                        )
                         .await?;
                 }
             }
@@ -852,8 +1884,10 @@ impl DataManager {
         features: Vec<api::Feature>,
         metadata: HashMap<String, serde_json::Value>,
         output_index_map: &HashMap<String, String>,
-    ) -> Result<()> {
+        chunk_position: Option<&ChunkPosition>,
+    ) -> Result<Vec<String>> {
         let content_metadata: internal_api::ContentMetadata = content_metadata.try_into()?;
+        let mut beyond_max_fragments = Vec::new();
         for feature in &features {
             match feature.feature_type {
                 api::FeatureType::Embedding => {
@@ -861,16 +1895,21 @@ impl DataManager {
                         serde_json::from_value(feature.data.clone()).map_err(|e| {
                             anyhow!("unable to get embedding from extracted data {}", e)
                         })?;
-                    self.write_extracted_embedding(
-                        &feature.name,
-                        &embedding_payload.values,
-                        &content_metadata.id.id,
-                        output_index_map,
-                        metadata.clone(),
-                        root_content_metadata.clone(),
-                        content_metadata.clone(),
-                    )
-                    .await?;
+                    let write = self
+                        .write_extracted_embedding(
+                            &feature.name,
+                            &embedding_payload.values,
+                            &content_metadata.id.id,
+                            output_index_map,
+                            metadata.clone(),
+                            root_content_metadata.clone(),
+                            content_metadata.clone(),
+                            chunk_position,
+                        )
+                        .await?;
+                    if let EmbeddingWrite::BeyondMaxFragments(index) = write {
+                        beyond_max_fragments.push(index);
+                    }
                 }
                 api::FeatureType::Metadata => {
                     let extracted_attributes = ExtractedMetadata::new(
@@ -894,7 +1933,7 @@ impl DataManager {
                 }
             }
         }
-        Ok(())
+        Ok(beyond_max_fragments)
     }
 
     pub async fn create_content_and_write_features(
@@ -905,7 +1944,8 @@ impl DataManager {
         extraction_graph_name: &str,
         features: Vec<api::Feature>,
         output_index_map: &HashMap<String, String>,
-    ) -> Result<()> {
+        chunk_position: Option<&ChunkPosition>,
+    ) -> Result<Vec<String>> {
         let req = indexify_coordinator::CreateContentRequest {
             content: Some(content_metadata.clone()),
         };
@@ -929,7 +1969,7 @@ impl DataManager {
                     e
                 );
             }
-            return Ok(());
+            return Ok(Vec::new());
         }
         let content_metadata_labels =
             internal_api::utils::convert_map_prost_to_serde_json(content_metadata.labels.clone())?;
@@ -942,6 +1982,7 @@ impl DataManager {
             features,
             metadata,
             output_index_map,
+            chunk_position,
         )
         .await
     }
@@ -984,33 +2025,1152 @@ impl DataManager {
         Ok(api_indexes)
     }
 
-    #[tracing::instrument]
-    pub async fn search(
+    pub async fn get_index(
         &self,
         namespace: &str,
         index_name: &str,
-        query: &str,
-        k: u64,
-        filter: LabelsFilter,
-        include_content: bool,
-    ) -> Result<Vec<ScoredText>> {
+    ) -> Result<indexify_coordinator::Index> {
+        self.find_index(namespace, index_name)
+            .await?
+            .ok_or(anyhow!("Index not found"))
+    }
+
+    async fn find_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+    ) -> Result<Option<indexify_coordinator::Index>> {
         let req = indexify_coordinator::GetIndexRequest {
             namespace: namespace.to_string(),
             name: index_name.to_string(),
         };
-        let index = self
+        Ok(self
             .get_coordinator_client()
             .await?
             .get_index(req)
             .await?
             .into_inner()
-            .index
-            .ok_or(anyhow!("Index not found"))?;
+            .index)
+    }
+
+    /// Creates the missing index of add_vectors with an extraction graph of
+    /// a single policy, named after the parts of the index name. Indexes
+    /// created concurrently, by this server or another one, are returned
+    /// instead of failing.
+    async fn create_missing_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        params: api::CreateIndexIfMissing,
+        items: &[api::VectorItem],
+    ) -> Result<indexify_coordinator::Index> {
+        let missing_index_error = |reason: String| MissingIndexError {
+            index: index_name.to_string(),
+            reason,
+        };
+        let [graph, policy, output] = index_name.split('.').collect::<Vec<_>>()[..] else {
+            return Err(missing_index_error(
+                "the index name must be <graph>.<policy>.<output>".to_string(),
+            )
+            .into());
+        };
+        let description = self
+            .list_extractors()
+            .await?
+            .into_iter()
+            .find(|description| description.name == params.extractor)
+            .ok_or_else(|| {
+                missing_index_error(format!("extractor {} not found", params.extractor))
+            })?;
+        let Some(api::ExtractorOutputSchema::Embedding(schema)) = description.outputs.get(output)
+        else {
+            return Err(missing_index_error(format!(
+                "extractor {} has no embedding output {}",
+                params.extractor, output
+            ))
+            .into());
+        };
+        if let Some(item) = items.iter().find(|item| item.vector.len() != schema.dim) {
+            return Err(missing_index_error(format!(
+                "vectors must have the dimension {} of output {}, got {}",
+                schema.dim,
+                output,
+                item.vector.len()
+            ))
+            .into());
+        }
+
+        let _guard = self.index_creation_lock.lock().await;
+        if let Some(index) = self.find_index(namespace, index_name).await? {
+            return Ok(index);
+        }
+        let request = ExtractionGraphRequest {
+            name: graph.to_string(),
+            description: Some(format!("created by add_vectors for index {}", index_name)),
+            extraction_policies: vec![api::ExtractionPolicyRequest {
+                extractor: params.extractor,
+                name: policy.to_string(),
+                filter: LabelsFilter::default(),
+                input_params: params.input_params,
+                content_source: None,
+            }],
+        };
+        if let Err(e) = self.create_extraction_graph(namespace, request).await {
+            // Another server may have created the index since it was looked up
+            return self
+                .find_index(namespace, index_name)
+                .await?
+                .ok_or_else(|| missing_index_error(e.to_string()).into());
+        }
+        info!(
+            "created index {}/{} for add_vectors with extractor {}",
+            namespace, index_name, description.name
+        );
+        self.get_index(namespace, index_name).await
+    }
+
+    /// Returns the configuration of an index stored by the coordinator and
+    /// the server config. The vector database of the index is not queried.
+    #[tracing::instrument(skip(self))]
+    pub async fn describe_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+    ) -> Result<api::DescribeIndexResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        let extractor_params = self.policy_input_params(namespace, &index).await?;
+        let config = &self.coordinator_client.config.index_config;
+        let settings = config
+            .indexes
+            .get(&index.table_name)
+            .cloned()
+            .unwrap_or_default();
+        let index_store = settings
+            .index_store
+            .as_ref()
+            .unwrap_or(&config.index_store)
+            .to_string();
+        let query_embedding_models = settings
+            .query_embedding
+            .iter()
+            .chain(settings.query_embedding_fallbacks.iter())
+            .map(|query_embedding| query_embedding.model.clone())
+            .collect();
+        let api_index: api::Index = index.clone().try_into()?;
+        Ok(api::DescribeIndexResponse {
+            name: index.name,
+            namespace: index.namespace,
+            table_name: index.table_name,
+            embedding_schema: api_index.embedding_schema,
+            index_store,
+            extraction_graph: index.graph_name,
+            extraction_policy: index.extraction_policy,
+            extractor: index.extractor,
+            extractor_params,
+            query_embedding_models,
+            settings: serde_json::to_value(settings)?,
+            read_only: self.is_read_only(&index.table_name).await?,
+        })
+    }
+
+    /// Counts the vectors of an index, see VectorIndexManager::count_vectors
+    #[tracing::instrument(skip(self))]
+    pub async fn count_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        approximate: bool,
+    ) -> Result<api::CountIndexResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        let (count, approximate) = self
+            .vector_index_manager
+            .count_vectors(&index.table_name, approximate)
+            .await?;
+        Ok(api::CountIndexResponse { count, approximate })
+    }
+
+    /// Parses and checks the filters of a search on an index without
+    /// searching, see Expression::validate. Filters are also checked against
+    /// the metadata fields of the index, if it has any.
+    #[tracing::instrument(skip(self))]
+    pub async fn validate_filters(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        filters: &[String],
+    ) -> Result<api::ValidateFilterResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        let fields = self.read_index_fields(&index.table_name).await?;
+        let errors: Vec<_> = filters
+            .iter()
+            .filter_map(|filter| {
+                let error = Expression::from_str(filter)
+                    .and_then(|expression| {
+                        expression.validate()?;
+                        check_filter_field(&fields, &expression)
+                    })
+                    .err()?;
+                Some(api::FilterError {
+                    filter: filter.clone(),
+                    error: error.to_string(),
+                })
+            })
+            .collect();
+        Ok(api::ValidateFilterResponse {
+            valid: errors.is_empty(),
+            errors,
+        })
+    }
+
+    /// Adds embeddings to an index and records their metadata fields
+    async fn add_embeddings(
+        &self,
+        table_name: &str,
+        embeddings: Vec<internal_api::ExtractedEmbeddings>,
+    ) -> Result<()> {
+        // The default metadata of the index is added to every embedding
+        let settings = self.vector_index_manager.index_settings(table_name);
+        let mut fields = metadata_fields(embeddings.iter().map(|embedding| &embedding.metadata));
+        if !embeddings.is_empty() {
+            merge_fields(&mut fields, metadata_fields([&settings.default_metadata]));
+        }
+        self.vector_index_manager
+            .add_embedding(table_name, embeddings)
+            .await?;
+        self.merge_index_fields(table_name, fields).await
+    }
+
+    fn fields_key(table_name: &str) -> String {
+        format!("{}.fields.json", table_name)
+    }
+
+    async fn read_index_fields(&self, table_name: &str) -> Result<IndexFields> {
+        let Some(bytes) = self
+            .blob_storage
+            .read(&Self::fields_key(table_name))
+            .await?
+        else {
+            return Ok(IndexFields::new());
+        };
+        serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow!("unable to read metadata fields of {}: {}", table_name, e))
+    }
+
+    /// Adds fields to the metadata fields of an index. The fields are only
+    /// written to blob storage if they contain a field or type which isn't
+    /// known yet, merged with the fields written by other servers. Blob
+    /// storage has no conditional writes, so the fields are read back once
+    /// written and written again if the write of another server overwrote
+    /// them.
+    async fn merge_index_fields(&self, table_name: &str, fields: IndexFields) -> Result<()> {
+        if fields.is_empty() {
+            return Ok(());
+        }
+        let lock = self
+            .index_fields
+            .lock()
+            .unwrap()
+            .entry(table_name.to_string())
+            .or_default()
+            .clone();
+        let mut cached = lock.lock().await;
+        let fresh = cached
+            .as_ref()
+            .is_some_and(|known| known.read_at.elapsed() < INDEX_FIELDS_CACHE_TTL);
+        if !fresh {
+            *cached = Some(KnownFields {
+                fields: self.read_index_fields(table_name).await?,
+                read_at: Instant::now(),
+            });
+        }
+        let known = cached.as_mut().unwrap();
+        if !has_new_fields(&known.fields, &fields) {
+            return Ok(());
+        }
+        for _ in 0..INDEX_FIELDS_WRITE_ATTEMPTS {
+            let mut stored = self.read_index_fields(table_name).await?;
+            merge_fields(&mut stored, fields.clone());
+            let bytes = Bytes::from(serde_json::to_vec(&stored)?);
+            self.blob_storage
+                .put(
+                    &Self::fields_key(table_name),
+                    futures::stream::iter([Ok(bytes)]),
+                )
+                .await?;
+            known.fields = self.read_index_fields(table_name).await?;
+            known.read_at = Instant::now();
+            if !has_new_fields(&known.fields, &fields) {
+                return Ok(());
+            }
+        }
+        // The fields are still new, so the next write with them retries
+        tracing::warn!(
+            "metadata fields of index {} were overwritten by concurrent writes {} times",
+            table_name,
+            INDEX_FIELDS_WRITE_ATTEMPTS
+        );
+        Ok(())
+    }
+
+    /// Lists the metadata fields of the vectors of an index along with the
+    /// types of their values, as recorded when the vectors were written.
+    #[tracing::instrument(skip(self))]
+    pub async fn list_index_fields(
+        &self,
+        namespace: &str,
+        index_name: &str,
+    ) -> Result<api::ListIndexFieldsResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        let fields = self
+            .read_index_fields(&index.table_name)
+            .await?
+            .into_iter()
+            .map(|(name, types)| api::IndexField {
+                name,
+                types: types.into_iter().collect(),
+            })
+            .collect();
+        Ok(api::ListIndexFieldsResponse { fields })
+    }
+
+    fn read_only_key(table_name: &str) -> String {
+        format!("{}.read_only.json", table_name)
+    }
+
+    /// Whether writes to the index are rejected, because its settings make it
+    /// read only or its read only flag is set in blob storage. Flags are
+    /// cached for READ_ONLY_CACHE_TTL, so a flag set on another server
+    /// applies to this server after at most that long.
+    async fn is_read_only(&self, table_name: &str) -> Result<bool> {
+        if self
+            .vector_index_manager
+            .index_settings(table_name)
+            .read_only
+        {
+            return Ok(true);
+        }
+        let cached = self
+            .read_only_flags
+            .lock()
+            .unwrap()
+            .get(table_name)
+            .and_then(|cached| cached.fresh(READ_ONLY_CACHE_TTL));
+        if let Some(read_only) = cached {
+            return Ok(read_only);
+        }
+        let read_only = match self
+            .blob_storage
+            .read(&Self::read_only_key(table_name))
+            .await?
+        {
+            Some(bytes) => {
+                let flag: api::IndexReadOnly = serde_json::from_slice(&bytes).map_err(|e| {
+                    anyhow!("unable to read read only flag of {}: {}", table_name, e)
+                })?;
+                flag.read_only
+            }
+            None => false,
+        };
+        self.read_only_flags
+            .lock()
+            .unwrap()
+            .insert(table_name.to_string(), Cached::new(read_only));
+        Ok(read_only)
+    }
+
+    async fn check_writable(&self, index: &indexify_coordinator::Index) -> Result<()> {
+        self.check_table_writable(&index.table_name, &index.name)
+            .await
+    }
+
+    /// Checks that the index stored in table_name is writable, index_name is
+    /// the name the error reports
+    async fn check_table_writable(&self, table_name: &str, index_name: &str) -> Result<()> {
+        if self.is_read_only(table_name).await? {
+            return Err(ReadOnlyIndexError {
+                index: index_name.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Sets the read only flag of an index in blob storage and returns
+    /// whether the index is read only, which it stays if its settings make it
+    /// read only.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_read_only(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        read_only: bool,
+    ) -> Result<api::IndexReadOnly> {
+        let index = self.get_index(namespace, index_name).await?;
+        let flag = Bytes::from(serde_json::to_vec(&api::IndexReadOnly { read_only })?);
+        self.blob_storage
+            .put(
+                &Self::read_only_key(&index.table_name),
+                futures::stream::iter([Ok(flag)]),
+            )
+            .await?;
+        self.read_only_flags
+            .lock()
+            .unwrap()
+            .insert(index.table_name.clone(), Cached::new(read_only));
+        info!(
+            "set read only flag of index {}/{} to {}",
+            namespace, index_name, read_only
+        );
+        Ok(api::IndexReadOnly {
+            read_only: self.is_read_only(&index.table_name).await?,
+        })
+    }
+
+    /// Input params of the extractor of the extraction policy of the index.
+    /// They are cached, so that searches don't list the extraction graphs of
+    /// the namespace every time.
+    async fn policy_input_params(
+        &self,
+        namespace: &str,
+        index: &indexify_coordinator::Index,
+    ) -> Result<Option<serde_json::Value>> {
+        let cached = self
+            .policy_params
+            .lock()
+            .unwrap()
+            .get(&index.table_name)
+            .and_then(|cached| cached.fresh(POLICY_PARAMS_CACHE_TTL));
+        if let Some(input_params) = cached {
+            return Ok(input_params);
+        }
+        let input_params = self
+            .list_extraction_graphs(namespace)
+            .await?
+            .into_iter()
+            .filter(|graph| graph.name == index.graph_name)
+            .flat_map(|graph| graph.extraction_policies)
+            .find(|policy| policy.name == index.extraction_policy)
+            .and_then(|policy| policy.input_params);
+        self.policy_params
+            .lock()
+            .unwrap()
+            .insert(index.table_name.clone(), Cached::new(input_params.clone()));
+        Ok(input_params)
+    }
+
+    /// Embedding output of the extractor of the index, which the index is
+    /// named after. It is cached like the policy input params.
+    async fn embedding_output(&self, index: &indexify_coordinator::Index) -> Result<String> {
+        let cached = self
+            .embedding_outputs
+            .lock()
+            .unwrap()
+            .get(&index.table_name)
+            .and_then(|cached| cached.fresh(POLICY_PARAMS_CACHE_TTL));
+        if let Some(output) = cached {
+            return Ok(output);
+        }
+        let description = self
+            .list_extractors()
+            .await?
+            .into_iter()
+            .find(|description| description.name == index.extractor)
+            .ok_or_else(|| {
+                anyhow!(
+                    "extractor {} of index {} not found",
+                    index.extractor,
+                    index.name
+                )
+            })?;
+        let api_index: api::Index = index.clone().try_into()?;
+        let output = reembed_output(
+            &description.outputs,
+            api_index.embedding_schema.dim,
+            index.table_name.rsplit('.').next(),
+        )
+        .map_err(|e| anyhow!("unable to embed text for index {}: {}", index.name, e))?;
+        self.embedding_outputs
+            .lock()
+            .unwrap()
+            .insert(index.table_name.clone(), Cached::new(output.clone()));
+        Ok(output)
+    }
+
+    #[tracing::instrument]
+    pub async fn search(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        queries: &[String],
+        options: SearchOptions,
+    ) -> Result<SearchResults> {
+        let index = self.get_index(namespace, index_name).await?;
+        let input_params = if self
+            .vector_index_manager
+            .embeds_queries_with_extractor(&index.table_name)
+        {
+            self.policy_input_params(namespace, &index).await?
+        } else {
+            None
+        };
+        self.vector_index_manager
+            .search(index, input_params, queries, options)
+            .await
+    }
+
+    /// Writes precomputed embeddings to an index without running its
+    /// extractor. The text of every item is stored as text content which is
+    /// not added to any extraction graph. The dimension of every embedding is
+    /// checked before anything is written.
+    #[tracing::instrument(skip(self, items))]
+    pub async fn add_vectors(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        items: Vec<api::VectorItem>,
+        create_if_missing: Option<api::CreateIndexIfMissing>,
+    ) -> Result<Vec<String>> {
+        let index = match (
+            self.find_index(namespace, index_name).await?,
+            create_if_missing,
+        ) {
+            (Some(index), _) => index,
+            (None, Some(params)) => {
+                self.create_missing_index(namespace, index_name, params, &items)
+                    .await?
+            }
+            (None, None) => return Err(anyhow!("Index not found")),
+        };
+        self.check_writable(&index).await?;
+        let mut items_with_ids = Vec::new();
+        for item in items {
+            let content_id = DataManager::make_id();
+            self.vector_index_manager
+                .check_dim(&index.table_name, &content_id, &item.vector)
+                .await?;
+            items_with_ids.push((content_id, item));
+        }
+        let mut content_ids = Vec::new();
+        let mut embeddings = Vec::new();
+        for (content_id, item) in items_with_ids {
+            let stream = futures::stream::once(async { Ok(Bytes::from(item.text)) });
+            let content_metadata = self
+                .write_content_bytes(
+                    namespace,
+                    Box::pin(stream),
+                    HashMap::new(),
+                    mime::TEXT_PLAIN.to_string(),
+                    None,
+                    "",
+                    Some(&content_id),
+                    &vec![],
+                )
+                .await?;
+            let req = indexify_coordinator::CreateContentRequest {
+                content: Some(content_metadata.clone()),
+            };
+            self.get_coordinator_client()
+                .await?
+                .create_content(GrpcHelper::into_req(req))
+                .await
+                .map_err(|e| anyhow!("unable to write content metadata to coordinator {}", e))?;
+            embeddings.push(internal_api::ExtractedEmbeddings {
+                content_id: content_id.clone(),
+                embedding: item.vector,
+                metadata: item.metadata,
+                root_content_metadata: None,
+                content_metadata: content_metadata.try_into()?,
+            });
+            content_ids.push(content_id);
+            if embeddings.len() >= IMPORT_BATCH_SIZE {
+                self.add_embeddings(&index.table_name, std::mem::take(&mut embeddings))
+                    .await?;
+            }
+        }
+        if !embeddings.is_empty() {
+            self.add_embeddings(&index.table_name, embeddings).await?;
+        }
+        Ok(content_ids)
+    }
+
+    #[tracing::instrument]
+    pub async fn update_index_metadata(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        content_id: &str,
+        metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
+    ) -> Result<bool> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.check_writable(&index).await?;
+        let fields = metadata_fields([&metadata]);
+        let updated = self
+            .vector_index_manager
+            .update_content_metadata(&index.table_name, content_id, metadata, merge)
+            .await?;
+        if updated {
+            self.merge_index_fields(&index.table_name, fields).await?;
+        }
+        Ok(updated)
+    }
+
+    #[tracing::instrument]
+    pub async fn similar_by_id(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        content_id: &str,
+        k: u64,
+        filter: LabelsFilter,
+        include_content: bool,
+    ) -> Result<Option<SearchResults>> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.vector_index_manager
+            .similar_by_id(index, content_id, k as usize, filter, include_content)
+            .await
+    }
+
+    #[tracing::instrument(skip(embedding))]
+    pub async fn search_by_vector(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        embedding: Vec<f32>,
+        k: u64,
+        filter: LabelsFilter,
+        include_content: bool,
+    ) -> Result<SearchResults> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.vector_index_manager
+            .search_by_vector(index, embedding, k as usize, filter, include_content)
+            .await
+    }
+
+    /// Embeds the text like the content of the index, with the extractor and
+    /// the input params of its extraction policy, and searches the index with
+    /// the embedding. Nothing is written to the index.
+    #[tracing::instrument(skip(text))]
+    pub async fn score_text(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        text: &str,
+        k: u64,
+        filter: LabelsFilter,
+        include_content: bool,
+    ) -> Result<SearchResults> {
+        let index = self.get_index(namespace, index_name).await?;
+        let output = self.embedding_output(&index).await?;
+        let input_params = self.policy_input_params(namespace, &index).await?;
+        let content = api::Content {
+            content_type: mime::TEXT_PLAIN.to_string(),
+            bytes: text.as_bytes().to_vec(),
+            features: vec![],
+            labels: HashMap::new(),
+        };
+        let embedding = self
+            .vector_index_manager
+            .extract_embedding(&index.extractor, &output, input_params, content)
+            .await?;
         self.vector_index_manager
-            .search(index, query, k as usize, filter, include_content)
+            .search_by_vector(index, embedding, k as usize, filter, include_content)
             .await
     }
 
+    #[tracing::instrument]
+    pub async fn get_embedding(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        content_id: &str,
+    ) -> Result<Option<Vec<f32>>> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.vector_index_manager
+            .get_vector(&index.table_name, content_id)
+            .await
+    }
+
+    /// Reads every vector of the index one page at a time, so memory use does
+    /// not grow with the size of the index.
+    fn export_pages(
+        &self,
+        index: indexify_coordinator::Index,
+        include_embedding: bool,
+    ) -> impl Stream<Item = Result<Vec<api::ExportedVector>>> + Send + 'static {
+        let vector_index_manager = self.vector_index_manager.clone();
+        async_stream::try_stream! {
+            let mut cursor = None;
+            loop {
+                let page = vector_index_manager
+                    .scroll(&index.table_name, cursor, EXPORT_PAGE_SIZE)
+                    .await?;
+                yield page
+                    .chunks
+                    .into_iter()
+                    .map(|chunk| api::ExportedVector {
+                        content_id: chunk.content_id,
+                        embedding: include_embedding.then_some(chunk.embedding),
+                        metadata: chunk.metadata,
+                        content_metadata: chunk.content_metadata,
+                        root_content_metadata: chunk.root_content_metadata,
+                    })
+                    .collect();
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Streams every vector of the index as JSON lines.
+    #[tracing::instrument]
+    pub async fn export_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        include_embedding: bool,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + Send + 'static> {
+        let index = self.get_index(namespace, index_name).await?;
+        let mut pages = Box::pin(self.export_pages(index, include_embedding));
+        Ok(async_stream::try_stream! {
+            while let Some(page) = pages.next().await {
+                for exported in page? {
+                    let mut line = serde_json::to_vec(&exported)?;
+                    line.push(b'\n');
+                    yield Bytes::from(line);
+                }
+            }
+        })
+    }
+
+    /// Streams every vector of the index as an Arrow IPC stream, with one
+    /// record batch per page of the index. Metadata is written as JSON
+    /// strings, see export_schema.
+    #[tracing::instrument(skip(self))]
+    pub async fn export_index_arrow(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        include_embedding: bool,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + Send + 'static> {
+        let index = self.get_index(namespace, index_name).await?;
+        let mut pages = Box::pin(self.export_pages(index, include_embedding));
+        Ok(async_stream::try_stream! {
+            let schema = export_schema();
+            let buffer = SharedBuffer::default();
+            let mut writer = StreamWriter::try_new(buffer.clone(), &schema)?;
+            while let Some(page) = pages.next().await {
+                writer.write(&export_batch(&schema, page?)?)?;
+                yield buffer.take();
+            }
+            writer.finish()?;
+            yield buffer.take();
+        })
+    }
+
+    /// Writes the vectors of an index export into the index. The embeddings
+    /// stored in the export are written as is, so every line must include
+    /// its embedding.
+    #[tracing::instrument(skip(body))]
+    pub async fn import_index(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        body: impl Stream<Item = Result<Bytes>> + Unpin,
+    ) -> Result<u64> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.check_writable(&index).await?;
+        let content_ids = self.import_vectors(&index.table_name, body).await?;
+        Ok(content_ids.len() as u64)
+    }
+
+    /// Writes the vectors of an index export into the table, returns the
+    /// content ids of the written vectors
+    async fn import_vectors(
+        &self,
+        table_name: &str,
+        mut body: impl Stream<Item = Result<Bytes>> + Unpin,
+    ) -> Result<Vec<String>> {
+        let mut buffer = Vec::new();
+        let mut batch = Vec::new();
+        let mut line_number = 0;
+        let mut content_ids = Vec::new();
+        loop {
+            let bytes = body.next().await.transpose()?;
+            let finished = bytes.is_none();
+            if let Some(bytes) = bytes {
+                buffer.extend_from_slice(&bytes);
+            } else {
+                // The last line of the export is not required to end with a
+                // newline
+                buffer.push(b'\n');
+            }
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                line_number += 1;
+                if let Some(embeddings) = Self::parse_exported_vector(&line, line_number)? {
+                    batch.push(embeddings);
+                }
+                if batch.len() >= IMPORT_BATCH_SIZE || (finished && !batch.is_empty()) {
+                    content_ids.extend(batch.iter().map(|e| e.content_id.clone()));
+                    self.add_embeddings(table_name, std::mem::take(&mut batch))
+                        .await?;
+                }
+            }
+            if finished {
+                break;
+            }
+        }
+        Ok(content_ids)
+    }
+
+    fn parse_exported_vector(
+        line: &[u8],
+        line_number: u64,
+    ) -> Result<Option<internal_api::ExtractedEmbeddings>> {
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            return Ok(None);
+        }
+        let exported: api::ExportedVector = serde_json::from_slice(line)
+            .map_err(|e| anyhow!("unable to parse line {}: {}", line_number, e))?;
+        let embedding = exported.embedding.ok_or(anyhow!(
+            "line {} has no embedding for content {}, export the index with include_embedding",
+            line_number,
+            exported.content_id
+        ))?;
+        Ok(Some(internal_api::ExtractedEmbeddings {
+            content_id: exported.content_id,
+            embedding,
+            metadata: exported.metadata,
+            root_content_metadata: exported.root_content_metadata,
+            content_metadata: exported.content_metadata,
+        }))
+    }
+
+    fn snapshots_key(table_name: &str) -> String {
+        format!("{}.snapshots.json", table_name)
+    }
+
+    /// Lists the snapshots of an index, oldest first
+    #[tracing::instrument(skip(self))]
+    pub async fn list_snapshots(
+        &self,
+        namespace: &str,
+        index_name: &str,
+    ) -> Result<Vec<api::IndexSnapshot>> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.read_snapshots(&index.table_name).await
+    }
+
+    async fn read_snapshots(&self, table_name: &str) -> Result<Vec<api::IndexSnapshot>> {
+        let Some(bytes) = self
+            .blob_storage
+            .read(&Self::snapshots_key(table_name))
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+        serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow!("unable to read snapshots of {}: {}", table_name, e))
+    }
+
+    /// Writes an export of the index with embeddings to blob storage and
+    /// adds it to the snapshots of the index. The snapshot is written before
+    /// it is listed, so a failed snapshot never replaces an existing one in
+    /// the list.
+    #[tracing::instrument(skip(self))]
+    pub async fn create_snapshot(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        name: &str,
+    ) -> Result<api::IndexSnapshot> {
+        let index = self.get_index(namespace, index_name).await?;
+        let created_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let export = self.export_index(namespace, index_name, true).await?;
+        // Every line of the export is one vector
+        let mut num_vectors = 0;
+        let export = Box::pin(export).map(|line| {
+            if line.is_ok() {
+                num_vectors += 1;
+            }
+            line
+        });
+        let key = format!("{}.snapshot.{}.jsonl", index.table_name, name);
+        let put_result = self.blob_storage.put(&key, export).await?;
+        let snapshot = api::IndexSnapshot {
+            name: name.to_string(),
+            created_at,
+            size_bytes: put_result.size_bytes,
+            num_vectors,
+            url: put_result.url,
+        };
+
+        let _guard = self.snapshots_lock.lock().await;
+        let mut snapshots = self.read_snapshots(&index.table_name).await?;
+        snapshots.retain(|s| s.name != name);
+        snapshots.push(snapshot.clone());
+        let snapshots = Bytes::from(serde_json::to_vec(&snapshots)?);
+        self.blob_storage
+            .put(
+                &Self::snapshots_key(&index.table_name),
+                futures::stream::iter([Ok(snapshots)]),
+            )
+            .await?;
+        info!(
+            "created snapshot {} of index {}/{} with {} vectors",
+            name, namespace, index_name, snapshot.num_vectors
+        );
+        Ok(snapshot)
+    }
+
+    /// Replaces the vectors of an index with the vectors of a snapshot.
+    /// Vectors in the snapshot are written again and vectors which are not in
+    /// the snapshot are removed.
+    #[tracing::instrument(skip(self))]
+    pub async fn restore_snapshot(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        snapshot: &api::IndexSnapshot,
+    ) -> Result<api::RestoreSnapshotResponse> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.check_writable(&index).await?;
+        // The index is read before the snapshot is written, so that writing
+        // doesn't move the cursor of the scroll
+        let mut existing: HashSet<String> = self
+            .index_content_ids(&index.table_name)
+            .await?
+            .into_iter()
+            .collect();
+        let content_reader = ContentReader::new(self.coordinator_client.config.clone());
+        let reader = content_reader.get(&snapshot.url);
+        let body = reader.get(&snapshot.url).await?;
+        let restored = self.import_vectors(&index.table_name, body).await?;
+        for content_id in &restored {
+            existing.remove(content_id);
+        }
+        for content_id in &existing {
+            self.vector_index_manager
+                .remove_embedding(&index.table_name, content_id)
+                .await?;
+        }
+        info!(
+            "restored snapshot {} of index {}/{}, wrote {} and removed {} vectors",
+            snapshot.name,
+            namespace,
+            index_name,
+            restored.len(),
+            existing.len()
+        );
+        Ok(api::RestoreSnapshotResponse {
+            restored: restored.len() as u64,
+            removed: existing.len() as u64,
+        })
+    }
+
+    /// Returns the content ids of every vector of the index
+    async fn index_content_ids(&self, table_name: &str) -> Result<Vec<String>> {
+        let mut content_ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self
+                .vector_index_manager
+                .scroll(table_name, cursor, RECONCILE_PAGE_SIZE)
+                .await?;
+            content_ids.extend(page.chunks.into_iter().map(|chunk| chunk.content_id));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(content_ids)
+    }
+
+    /// Starts a job embedding the content of every vector of the index again
+    /// with an extractor, the extractor of the index if the request doesn't
+    /// set one. The extractor must return embeddings of the dimension of the
+    /// index. Requests to the extractor are spaced to stay under
+    /// requests_per_second.
+    pub async fn start_reembed(
+        self: &Arc<Self>,
+        namespace: &str,
+        index_name: &str,
+        request: api::ReembedRequest,
+    ) -> Result<api::ReembedJob> {
+        let index = self.get_index(namespace, index_name).await?;
+        self.check_writable(&index).await?;
+        let extractor = request.extractor.unwrap_or_else(|| index.extractor.clone());
+        let invalid_extractor = |reason: String| ReembedError::InvalidExtractor {
+            index: index_name.to_string(),
+            extractor: extractor.clone(),
+            reason,
+        };
+        let description = self
+            .list_extractors()
+            .await?
+            .into_iter()
+            .find(|description| description.name == extractor)
+            .ok_or_else(|| invalid_extractor("extractor not found".to_string()))?;
+        let api_index: api::Index = index.clone().try_into()?;
+        let output = reembed_output(
+            &description.outputs,
+            api_index.embedding_schema.dim,
+            request.output.as_deref(),
+        )
+        .map_err(invalid_extractor)?;
+        // The content of the index was extracted with the params of its
+        // extraction policy, other extractors get their default params
+        let input_params = if extractor == index.extractor {
+            self.policy_input_params(namespace, &index).await?
+        } else {
+            None
+        };
+        let total = self
+            .vector_index_manager
+            .num_vectors(&index.table_name)
+            .await?;
+        let job = api::ReembedJob {
+            id: nanoid!(16),
+            namespace: namespace.to_string(),
+            index: index_name.to_string(),
+            extractor: extractor.clone(),
+            status: api::ReembedJobStatus::Running,
+            total,
+            processed: 0,
+            skipped: 0,
+            error: None,
+            started_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            finished_at: None,
+        };
+        {
+            let mut jobs = self.reembed_jobs.write().unwrap();
+            if let Some(running) = jobs.values().find(|running| {
+                running.namespace == namespace &&
+                    running.index == index_name &&
+                    running.status == api::ReembedJobStatus::Running
+            }) {
+                return Err(ReembedError::AlreadyRunning {
+                    index: index_name.to_string(),
+                    job_id: running.id.clone(),
+                }
+                .into());
+            }
+            jobs.insert(job.id.clone(), job.clone());
+        }
+        info!(
+            "started re-embed job {} of index {}/{} with {} vectors, extractor {} output {}",
+            job.id, namespace, index_name, total, extractor, output
+        );
+        let data_manager = self.clone();
+        let job_id = job.id.clone();
+        let requests_per_second = request
+            .requests_per_second
+            .unwrap_or(DEFAULT_REEMBED_REQUESTS_PER_SECOND);
+        tokio::spawn(async move {
+            let result = data_manager
+                .run_reembed(
+                    &job_id,
+                    &index.table_name,
+                    &extractor,
+                    &output,
+                    input_params,
+                    requests_per_second,
+                )
+                .await;
+            let finished_at = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .ok();
+            data_manager.update_reembed_job(&job_id, |job| {
+                job.finished_at = finished_at;
+                match result {
+                    Ok(()) => {
+                        info!(
+                            "re-embed job {} re-embedded {} and skipped {} vectors",
+                            job.id, job.processed, job.skipped
+                        );
+                        job.status = api::ReembedJobStatus::Completed;
+                    }
+                    Err(e) => {
+                        error!("re-embed job {} failed: {}", job.id, e);
+                        job.status = api::ReembedJobStatus::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+            });
+        });
+        Ok(job)
+    }
+
+    async fn run_reembed(
+        &self,
+        job_id: &str,
+        table_name: &str,
+        extractor: &str,
+        output: &str,
+        input_params: Option<serde_json::Value>,
+        requests_per_second: f64,
+    ) -> Result<()> {
+        // The ids are read before any vector is written, so that writing
+        // doesn't move the cursor of the scroll
+        let content_ids = self.index_content_ids(table_name).await?;
+        let mut interval =
+            tokio::time::interval(Duration::from_secs_f64(1.0 / requests_per_second));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        for content_id in content_ids {
+            let Some(chunk) = self
+                .vector_index_manager
+                .get_points(table_name, vec![content_id])
+                .await?
+                .pop()
+            else {
+                // The vector was removed since the job started
+                continue;
+            };
+            interval.tick().await;
+            let reembedded = self
+                .vector_index_manager
+                .reembed(table_name, extractor, output, input_params.clone(), chunk)
+                .await?;
+            self.update_reembed_job(job_id, |job| {
+                if reembedded {
+                    job.processed += 1;
+                } else {
+                    job.skipped += 1;
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn update_reembed_job(&self, job_id: &str, update: impl FnOnce(&mut api::ReembedJob)) {
+        if let Some(job) = self.reembed_jobs.write().unwrap().get_mut(job_id) {
+            update(job);
+        }
+    }
+
+    /// Returns the re-embed job of the index with id job_id
+    pub fn get_reembed_job(
+        &self,
+        namespace: &str,
+        index_name: &str,
+        job_id: &str,
+    ) -> Option<api::ReembedJob> {
+        self.reembed_jobs
+            .read()
+            .unwrap()
+            .get(job_id)
+            .filter(|job| job.namespace == namespace && job.index == index_name)
+            .cloned()
+    }
+
     #[tracing::instrument]
     pub async fn metadata_lookup(
         &self,
@@ -1057,9 +3217,315 @@ impl DataManager {
 
 #[cfg(test)]
 mod tests {
+    use arrow_array::Array;
     use serde_json::json;
 
     use super::*;
+    use crate::{
+        server_config::IndexStoreKind,
+        test_util::db_utils::{test_mock_content_metadata, DEFAULT_TEST_NAMESPACE},
+    };
+
+    #[test]
+    fn test_index_names() {
+        assert!(check_name_part("extraction graph", "my-graph_1").is_ok());
+        assert!(check_name_part("extraction graph", "").is_err());
+        assert!(check_name_part("extraction graph", "my.graph").is_err());
+        assert!(check_name_part("extraction policy", "my policy").is_err());
+        assert!(check_name_part("namespace", "my.namespace").is_err());
+
+        let names = [
+            "default.graph.minilm.embedding",
+            "default.graph.MiniLM.embedding",
+        ];
+        assert_eq!(
+            case_collision("default.graph.minilm.embedding", names),
+            Some(names[1])
+        );
+        assert_eq!(
+            case_collision("default.Graph.minilm.embedding", names),
+            Some(names[0])
+        );
+        // Tables of other namespaces collide as well
+        assert_eq!(
+            case_collision("Default.graph.minilm.embedding", names),
+            Some(names[0])
+        );
+        assert_eq!(
+            case_collision("default.graph.openai.embedding", names),
+            None
+        );
+    }
+
+    #[test]
+    fn test_index_fields() {
+        let fields = metadata_fields([
+            &HashMap::from([("year".to_string(), json!(2020))]),
+            &HashMap::from([
+                ("year".to_string(), json!("2021")),
+                ("author".to_string(), json!("smith")),
+            ]),
+        ]);
+        assert_eq!(
+            fields,
+            IndexFields::from([
+                ("author".to_string(), BTreeSet::from(["string".to_string()])),
+                (
+                    "year".to_string(),
+                    BTreeSet::from(["number".to_string(), "string".to_string()])
+                ),
+            ])
+        );
+        let mut known = metadata_fields([&HashMap::from([("year".to_string(), json!(2020))])]);
+        assert!(has_new_fields(&known, &fields));
+        merge_fields(&mut known, fields.clone());
+        assert_eq!(known, fields);
+        assert!(!has_new_fields(&known, &fields));
+
+        let check =
+            |filter: &str| check_filter_field(&fields, &Expression::from_str(filter).unwrap());
+        assert!(check("year>2019").is_ok());
+        assert!(check("author=smith").is_ok());
+        assert!(check("not_exists(reviewer)").is_ok());
+        assert!(check("reviewer=smith").is_err());
+        assert!(check("author=true").is_err());
+        let filter = Expression::from_str("reviewer=smith").unwrap();
+        assert!(check_filter_field(&IndexFields::new(), &filter).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_distance() {
+        let model_distances = HashMap::from([("minilm".to_string(), IndexDistance::Cosine)]);
+        let schema = |distance: &str| internal_api::EmbeddingSchema {
+            dim: 384,
+            distance: distance.to_string(),
+        };
+        let resolved = resolve_distance(&model_distances, "minilm", schema("")).unwrap();
+        assert_eq!(resolved.distance, "cosine");
+
+        // The distance of the schema is kept even if it isn't the recommended one
+        let resolved = resolve_distance(&model_distances, "minilm", schema("dot")).unwrap();
+        assert_eq!(resolved.distance, "dot");
+
+        let resolved = resolve_distance(&model_distances, "other", schema("")).unwrap();
+        assert_eq!(resolved.distance, "");
+        assert!(resolve_distance(&model_distances, "minilm", schema("manhattan")).is_err());
+    }
+
+    #[test]
+    fn test_text_hash() {
+        let config = |normalization, case_fold| TextHashingConfig {
+            normalization,
+            case_fold,
+        };
+        // é as a single code point and as e followed by a combining accent
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        let none = config(None, false);
+        assert_ne!(text_hash(composed, &none), text_hash(decomposed, &none));
+        let nfc = config(Some(UnicodeNormalization::Nfc), false);
+        assert_eq!(text_hash(composed, &nfc), text_hash(decomposed, &nfc));
+        // Only NFKC folds compatibility characters such as ligatures
+        assert_ne!(text_hash("\u{fb01}le", &nfc), text_hash("file", &nfc));
+        let nfkc = config(Some(UnicodeNormalization::Nfkc), false);
+        assert_eq!(text_hash("\u{fb01}le", &nfkc), text_hash("file", &nfkc));
+        assert_eq!(text_hash(composed, &nfkc), text_hash(decomposed, &nfkc));
+
+        assert_ne!(text_hash("Cafe", &none), text_hash("cafe", &none));
+        let case_fold = config(None, true);
+        assert_eq!(text_hash("Cafe", &case_fold), text_hash("cafe", &case_fold));
+        // Without preprocessing the hash is the hash of the stored bytes
+        assert_eq!(
+            text_hash("cafe", &none),
+            format!("{:x}", Sha256::digest(b"cafe"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_hash() -> Result<()> {
+        let mut config = crate::server_config::ServerConfig::default();
+        config.index_config.index_store = IndexStoreKind::Qdrant;
+        config.index_config.qdrant_config = Some(Default::default());
+        config.blob_storage = crate::blob_storage::BlobStorageConfig {
+            s3: None,
+            disk: Some(crate::blob_storage::DiskStorageConfig {
+                path: "/tmp/indexify-test".to_string(),
+            }),
+        };
+        config.text_hashing = TextHashingConfig {
+            normalization: Some(UnicodeNormalization::Nfc),
+            case_fold: true,
+        };
+        let vector_db = crate::vectordbs::create_vectordb(config.index_config.clone()).await?;
+        let coordinator_client = Arc::new(CoordinatorClient::new(Arc::new(config.clone())));
+        let data_manager = DataManager::new(
+            Arc::new(VectorIndexManager::new(
+                coordinator_client.clone(),
+                vector_db,
+                HashMap::new(),
+            )?),
+            crate::metadata_storage::from_config(&config.metadata_storage)?,
+            crate::metadata_storage::from_config_reader(&config.metadata_storage)?,
+            Arc::new(BlobStorage::new_with_config(config.blob_storage.clone())),
+            coordinator_client,
+        );
+        let write = |text: &'static str| {
+            let stream = futures::stream::once(async move { Ok(Bytes::from(text)) });
+            data_manager.write_stream(DEFAULT_TEST_NAMESPACE, Box::pin(stream), None)
+        };
+
+        // The texts only differ by their normal form and case
+        let composed = write("Caf\u{e9}").await?;
+        let decomposed = write("cafe\u{301}").await?;
+        assert_ne!(composed.hash, decomposed.hash);
+        assert_eq!(
+            data_manager.content_hash("text/plain", &composed).await?,
+            data_manager.content_hash("text/plain", &decomposed).await?
+        );
+        // Other content is hashed as it is stored
+        assert_eq!(
+            data_manager
+                .content_hash("application/octet-stream", &composed)
+                .await?,
+            composed.hash
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_text_reason() {
+        let result = |size_bytes: u64, only_whitespace: bool| WriteStreamResult {
+            url: "url".to_string(),
+            size_bytes,
+            hash: "hash".to_string(),
+            file_name: "file".to_string(),
+            only_whitespace,
+        };
+        assert_eq!(
+            result(0, true).empty_text_reason("text/plain"),
+            Some("text is empty")
+        );
+        assert_eq!(
+            result(4, true).empty_text_reason("text/plain"),
+            Some("text only contains whitespace")
+        );
+        assert_eq!(result(5, false).empty_text_reason("text/plain"), None);
+        // Binary content is added whatever its bytes
+        assert_eq!(result(0, true).empty_text_reason("image/png"), None);
+    }
+
+    #[test]
+    fn test_utf8_validator() {
+        let validate = |action, chunks: &[&[u8]]| -> Result<Vec<u8>> {
+            let mut validator = Utf8Validator::new(action);
+            let mut output = Vec::new();
+            for chunk in chunks {
+                output.extend_from_slice(&validator.push(chunk)?);
+            }
+            output.extend_from_slice(&validator.finish()?);
+            Ok(output)
+        };
+        // A character split across chunks is valid
+        let text = "grüße".as_bytes();
+        let chunks = [&text[..3], &text[3..5], &text[5..]];
+        for action in [InvalidUtf8Action::Replace, InvalidUtf8Action::Reject] {
+            assert_eq!(validate(action, &chunks).unwrap(), text);
+        }
+
+        let chunks: [&[u8]; 2] = [b"ab\xffc", b"d\xc3"];
+        assert_eq!(
+            validate(InvalidUtf8Action::Replace, &chunks).unwrap(),
+            "ab\u{fffd}cd\u{fffd}".as_bytes()
+        );
+        let err = validate(InvalidUtf8Action::Reject, &chunks).unwrap_err();
+        assert_eq!(err.to_string(), "content is not valid UTF-8 at byte 2");
+        let chunks: [&[u8]; 2] = [b"abcd", b"\xc3"];
+        let err = validate(InvalidUtf8Action::Reject, &chunks).unwrap_err();
+        assert_eq!(err.to_string(), "content is not valid UTF-8 at byte 4");
+    }
+
+    #[test]
+    fn test_reembed_output() {
+        let embedding = |dim: usize| {
+            api::ExtractorOutputSchema::Embedding(api::EmbeddingSchema {
+                dim,
+                distance: api::IndexDistance::Cosine,
+            })
+        };
+        let mut outputs = HashMap::from([
+            ("embedding".to_string(), embedding(384)),
+            (
+                "metadata".to_string(),
+                api::ExtractorOutputSchema::Metadata(json!({})),
+            ),
+        ]);
+        assert_eq!(reembed_output(&outputs, 384, None).unwrap(), "embedding");
+        assert!(reembed_output(&outputs, 768, None).is_err());
+        assert!(reembed_output(&outputs, 384, Some("metadata")).is_err());
+
+        // An output must be selected among several outputs of the dimension
+        outputs.insert("title_embedding".to_string(), embedding(384));
+        assert!(reembed_output(&outputs, 384, None).is_err());
+        assert_eq!(
+            reembed_output(&outputs, 384, Some("title_embedding")).unwrap(),
+            "title_embedding"
+        );
+        outputs.insert("large_embedding".to_string(), embedding(768));
+        assert!(reembed_output(&outputs, 384, Some("large_embedding")).is_err());
+    }
+
+    #[test]
+    fn test_make_fragment_id() {
+        let id = DataManager::make_fragment_id("source", 0, "abc");
+        assert_eq!(id.len(), 16);
+        assert!(DataManager::is_hex_string(&id));
+        assert_eq!(id, DataManager::make_fragment_id("source", 0, "abc"));
+        assert_ne!(id, DataManager::make_fragment_id("source", 1, "abc"));
+        assert_ne!(id, DataManager::make_fragment_id("source", 0, "abd"));
+        assert_ne!(id, DataManager::make_fragment_id("other", 0, "abc"));
+    }
+
+    #[test]
+    fn test_export_arrow() {
+        let vector = |content_id: &str, embedding: Option<Vec<f32>>| api::ExportedVector {
+            content_id: content_id.to_string(),
+            embedding,
+            metadata: HashMap::from([("source".to_string(), json!("wiki"))]),
+            content_metadata: test_mock_content_metadata(content_id, "root", "graph"),
+            root_content_metadata: None,
+        };
+        let schema = export_schema();
+        let buffer = SharedBuffer::default();
+        let mut writer = StreamWriter::try_new(buffer.clone(), &schema).unwrap();
+        writer
+            .write(&export_batch(&schema, vec![vector("a", Some(vec![0.1, 0.2]))]).unwrap())
+            .unwrap();
+        let mut bytes = buffer.take().to_vec();
+        writer
+            .write(&export_batch(&schema, vec![vector("b", None)]).unwrap())
+            .unwrap();
+        writer.finish().unwrap();
+        bytes.extend_from_slice(&buffer.take());
+
+        let reader = arrow_ipc::reader::StreamReader::try_new(bytes.as_slice(), None).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 2);
+        let content_ids = batches[1]
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(content_ids.value(0), "b");
+        // Vectors exported without embedding have a null embedding
+        assert!(batches[0].column(1).is_valid(0));
+        assert!(batches[1].column(1).is_null(0));
+        let metadata = batches[0]
+            .column(2)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(metadata.value(0), r#"{"source":"wiki"}"#);
+    }
 
     #[test]
     fn test_combine_metadata() {
@@ -1098,4 +3564,57 @@ mod tests {
 
         //assert_eq!(combined, expected);
     }
+
+    #[test]
+    fn test_chunk_position_metadata() {
+        let chunk_position = ChunkPosition {
+            chunk_index: 2,
+            char_start: 100,
+            char_end: 150,
+            source_length: Some(400),
+        };
+        let mut metadata = HashMap::from([("key1".to_string(), json!("value1"))]);
+        chunk_position.add_to_metadata(&mut metadata);
+        assert_eq!(metadata.get("key1"), Some(&json!("value1")));
+        assert_eq!(metadata.get("chunk_index"), Some(&json!(2)));
+        assert_eq!(metadata.get("char_start"), Some(&json!(100)));
+        assert_eq!(metadata.get("char_end"), Some(&json!(150)));
+        assert_eq!(metadata.get("source_length"), Some(&json!(400)));
+
+        // The length of sources which aren't text is unknown
+        let mut metadata = HashMap::new();
+        ChunkPosition::default().add_to_metadata(&mut metadata);
+        assert!(!metadata.contains_key("source_length"));
+    }
+
+    #[test]
+    fn test_parse_exported_vector() {
+        let content_metadata = test_mock_content_metadata("1", "1", "graph1");
+        let exported = api::ExportedVector {
+            content_id: "1".to_string(),
+            embedding: Some(vec![0.1, 0.2]),
+            metadata: HashMap::from([("key1".to_string(), json!("value1"))]),
+            content_metadata: content_metadata.clone(),
+            root_content_metadata: None,
+        };
+        let line = serde_json::to_vec(&exported).unwrap();
+        let embeddings = DataManager::parse_exported_vector(&line, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(embeddings.content_id, "1");
+        assert_eq!(embeddings.embedding, vec![0.1, 0.2]);
+        assert_eq!(embeddings.metadata.get("key1"), Some(&json!("value1")));
+        assert_eq!(embeddings.content_metadata, content_metadata);
+
+        assert!(DataManager::parse_exported_vector(b"  \n", 2)
+            .unwrap()
+            .is_none());
+
+        let exported = api::ExportedVector {
+            embedding: None,
+            ..exported
+        };
+        let line = serde_json::to_vec(&exported).unwrap();
+        assert!(DataManager::parse_exported_vector(&line, 3).is_err());
+    }
 }
@@ -0,0 +1,214 @@
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use anyhow::Result;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Hits and misses of the cached query embeddings of a model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct EmbeddingCacheStats {
+    pub model: String,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Whether a request looks up cached embeddings and caches the embeddings it
+/// computes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CachePolicy {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            read: true,
+            write: true,
+        }
+    }
+}
+
+/// EmbeddingCache keeps the embeddings of recent search queries keyed by the
+/// model and the query text, so that repeated queries are not embedded again.
+/// The least recently used embeddings are evicted once max_entries embeddings
+/// are cached.
+pub struct EmbeddingCache {
+    cache: moka::future::Cache<(String, String), Vec<f32>>,
+    stats: Mutex<HashMap<String, EmbeddingCacheStats>>,
+}
+
+impl EmbeddingCache {
+    pub fn new(max_entries: u64) -> Self {
+        Self {
+            cache: moka::future::Cache::new(max_entries),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached embedding of text, or caches the embedding returned
+    /// by embed. Failed embeddings are not cached. The embedding is only
+    /// looked up if policy.read is set and only cached if policy.write is
+    /// set, lookups which are skipped count neither as hits nor as misses.
+    pub async fn get_or_embed<F>(
+        &self,
+        model: &str,
+        text: &str,
+        embed: F,
+        policy: CachePolicy,
+    ) -> Result<Vec<f32>>
+    where
+        F: Future<Output = Result<Vec<f32>>>,
+    {
+        let key = (model.to_string(), text.to_string());
+        if !policy.read {
+            let embedding = embed.await?;
+            if policy.write {
+                self.cache.insert(key, embedding.clone()).await;
+            }
+            return Ok(embedding);
+        }
+        let cached = self.cache.get(&key).await;
+        let hit = cached.is_some();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            let stats = stats
+                .entry(model.to_string())
+                .or_insert_with(|| EmbeddingCacheStats {
+                    model: model.to_string(),
+                    ..Default::default()
+                });
+            if hit {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+        }
+        if let Some(embedding) = cached {
+            return Ok(embedding);
+        }
+        let embedding = embed.await?;
+        if policy.write {
+            self.cache.insert(key, embedding.clone()).await;
+        }
+        Ok(embedding)
+    }
+
+    pub fn stats(&self) -> Vec<EmbeddingCacheStats> {
+        let mut stats: Vec<EmbeddingCacheStats> =
+            self.stats.lock().unwrap().values().cloned().collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_or_embed() {
+        let cache = EmbeddingCache::new(10);
+        let policy = CachePolicy::default();
+        let embedding = cache
+            .get_or_embed("model1", "hello", async { Ok(vec![1.0, 2.0]) }, policy)
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![1.0, 2.0]);
+
+        let embedding = cache
+            .get_or_embed(
+                "model1",
+                "hello",
+                async { Err(anyhow!("not cached")) },
+                policy,
+            )
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![1.0, 2.0]);
+
+        // Embeddings are cached per model and failures are not cached
+        assert!(cache
+            .get_or_embed("model2", "hello", async { Err(anyhow!("failed")) }, policy)
+            .await
+            .is_err());
+        let embedding = cache
+            .get_or_embed("model2", "hello", async { Ok(vec![3.0, 4.0]) }, policy)
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![3.0, 4.0]);
+
+        assert_eq!(
+            cache.stats(),
+            vec![
+                EmbeddingCacheStats {
+                    model: "model1".to_string(),
+                    hits: 1,
+                    misses: 1,
+                },
+                EmbeddingCacheStats {
+                    model: "model2".to_string(),
+                    hits: 0,
+                    misses: 2,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_policy() {
+        let cache = EmbeddingCache::new(10);
+        let policy = CachePolicy::default();
+        let no_read = CachePolicy {
+            read: false,
+            write: true,
+        };
+        let no_write = CachePolicy {
+            read: true,
+            write: false,
+        };
+        cache
+            .get_or_embed("model", "hello", async { Ok(vec![1.0]) }, policy)
+            .await
+            .unwrap();
+
+        // Skipping reads embeds again and replaces the cached embedding
+        let embedding = cache
+            .get_or_embed("model", "hello", async { Ok(vec![2.0]) }, no_read)
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![2.0]);
+        let embedding = cache
+            .get_or_embed(
+                "model",
+                "hello",
+                async { Err(anyhow!("not cached")) },
+                policy,
+            )
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![2.0]);
+
+        // Skipping writes still returns cached embeddings, but caches no new
+        // ones
+        cache
+            .get_or_embed("model", "world", async { Ok(vec![3.0]) }, no_write)
+            .await
+            .unwrap();
+        assert!(cache
+            .get_or_embed("model", "world", async { Err(anyhow!("failed")) }, no_write)
+            .await
+            .is_err());
+
+        assert_eq!(
+            cache.stats(),
+            vec![EmbeddingCacheStats {
+                model: "model".to_string(),
+                hits: 1,
+                misses: 3,
+            }]
+        );
+    }
+}
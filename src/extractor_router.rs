@@ -1,13 +1,17 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Result};
 use indexify_internal_api as internal_api;
 use indexify_proto::indexify_coordinator::GetExtractorCoordinatesRequest;
 use internal_api::ExtractResponse;
 
-use crate::{api::Content, coordinator_client::CoordinatorClient};
+use crate::{
+    api::Content,
+    coordinator_client::CoordinatorClient,
+    http_embedding::EmbeddingTimeoutError,
+};
 
-const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub struct ExtractorRouter {
     coordinator_client: Arc<CoordinatorClient>,
@@ -55,16 +59,32 @@ impl ExtractorRouter {
             .into_inner();
         let addresses = resp.addrs;
         if addresses.is_empty() {
-            return Err(anyhow!("no extractor found"));
+            return Err(anyhow!("no running extractor {} found", extractor_name));
         }
         let extractor_addr = addresses[0].clone();
-        let resp = self
+        let timeout_secs = self.timeout_secs(extractor_name);
+        let mut request_builder = self
             .client
             .post(&format!("http://{}/extract", extractor_addr))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| anyhow::anyhow!("unable to embed query: {}", e))?;
+            .json(&request);
+        if let Some(timeout_secs) = timeout_secs {
+            request_builder = request_builder.timeout(Duration::from_secs(timeout_secs));
+        }
+        let request_error = |e: reqwest::Error| -> anyhow::Error {
+            match timeout_secs {
+                Some(timeout_secs) if e.is_timeout() => {
+                    let provider = format!("extractor {} at {}", extractor_name, extractor_addr);
+                    tracing::warn!("request to {} timed out after {}s", provider, timeout_secs);
+                    EmbeddingTimeoutError {
+                        provider,
+                        timeout_secs,
+                    }
+                    .into()
+                }
+                _ => anyhow!("unable to embed query: {}", e),
+            }
+        };
+        let resp = request_builder.send().await.map_err(&request_error)?;
 
         if !&resp.status().is_success() {
             return Err(anyhow!(
@@ -73,10 +93,12 @@ impl ExtractorRouter {
                 resp.text().await?
             ));
         }
-        let response_body = resp
-            .text()
-            .await
-            .map_err(|e| anyhow!("unable to get response body: {}", e))?;
+        let response_body = resp.text().await.map_err(|e| {
+            if e.is_timeout() {
+                return request_error(e);
+            }
+            anyhow!("unable to get response body: {}", e)
+        })?;
 
         let extractor_response: internal_api::ExtractResponse =
             serde_json::from_str(&response_body)
@@ -84,4 +106,15 @@ impl ExtractorRouter {
 
         Ok(extractor_response)
     }
+
+    /// Timeout of requests to the extractor, overridden per extractor by
+    /// model_timeouts
+    fn timeout_secs(&self, extractor_name: &str) -> Option<u64> {
+        let config = &self.coordinator_client.config;
+        config
+            .model_timeouts
+            .get(extractor_name)
+            .copied()
+            .or(config.extractor_timeout_secs)
+    }
 }
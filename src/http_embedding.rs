@@ -0,0 +1,643 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use opentelemetry::KeyValue;
+use serde_json::json;
+use thiserror::Error;
+use tokio::sync::{oneshot, watch};
+
+use crate::{metrics::http_embedding::Metrics, server_config::HttpEmbeddingConfig};
+
+/// Returned when the embedding service doesn't respond in time. Timeouts are
+/// usually transient, so requests failing with this error can be retried.
+#[derive(Debug, Clone, Error)]
+#[error("embedding request to {provider} timed out after {timeout_secs}s, it can be retried")]
+pub struct EmbeddingTimeoutError {
+    pub provider: String,
+    pub timeout_secs: u64,
+}
+
+/// Returned when the embedding service can't be connected to, for example
+/// while it is starting. Requests failing with this error can be retried once
+/// the service is reachable.
+#[derive(Debug, Clone, Error)]
+#[error("embedding service {provider} is unavailable: {reason}")]
+pub struct EmbeddingUnavailableError {
+    pub provider: String,
+    pub reason: String,
+}
+
+/// Returns the value at path in value. Fields of objects are selected by name
+/// and elements of arrays by index, a * selects the field of every element of
+/// an array, for example data.*.embedding.
+fn resolve_path(mut value: serde_json::Value, path: &[&str]) -> Option<serde_json::Value> {
+    let Some((field, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    match (field, &mut value) {
+        (&"*", serde_json::Value::Array(elements)) => std::mem::take(elements)
+            .into_iter()
+            .map(|element| resolve_path(element, rest))
+            .collect::<Option<Vec<_>>>()
+            .map(serde_json::Value::Array),
+        (_, serde_json::Value::Array(elements)) => {
+            let index: usize = field.parse().ok()?;
+            resolve_path(elements.get_mut(index)?.take(), rest)
+        }
+        (_, serde_json::Value::Object(fields)) => resolve_path(fields.remove(*field)?, rest),
+        _ => None,
+    }
+}
+
+/// Copies the error of a coalesced request for every input of the request,
+/// so that timeouts and unavailable services can still be told apart
+fn copy_error(e: &anyhow::Error) -> anyhow::Error {
+    if let Some(e) = e.downcast_ref::<EmbeddingTimeoutError>() {
+        return e.clone().into();
+    }
+    if let Some(e) = e.downcast_ref::<EmbeddingUnavailableError>() {
+        return e.clone().into();
+    }
+    anyhow!("{}", e)
+}
+
+/// Inputs waiting to be embedded in one request, with the channels their
+/// embeddings are sent to
+#[derive(Default)]
+struct Batch {
+    id: u64,
+    inputs: Vec<String>,
+    senders: Vec<oneshot::Sender<Result<Vec<f32>>>>,
+}
+
+/// The batch of inputs which is currently collected. Only one batch collects
+/// inputs at a time, it is taken when it is full or its window ends.
+#[derive(Default)]
+struct Coalescer {
+    batch: Option<Batch>,
+    next_id: u64,
+}
+
+/// Embeds text with an http embedding service, see HttpEmbeddingConfig for
+/// the request and response format.
+#[derive(Clone)]
+pub struct HttpEmbeddingGenerator {
+    config: HttpEmbeddingConfig,
+    client: reqwest::Client,
+    coalescer: Arc<Mutex<Coalescer>>,
+    // Cleared when a request can't connect to the service and set again by
+    // the health probe
+    available: Arc<AtomicBool>,
+    metrics: Metrics,
+}
+
+impl HttpEmbeddingGenerator {
+    pub fn new(config: HttpEmbeddingConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.read_timeout_secs))
+            .build()
+            .map_err(|e| anyhow!("unable to create request client: {}", e))?;
+        let generator = Self {
+            config,
+            client,
+            coalescer: Arc::new(Mutex::new(Coalescer::default())),
+            available: Arc::new(AtomicBool::new(true)),
+            metrics: Metrics::new(),
+        };
+        if let Some(sample_response) = &generator.config.sample_response {
+            generator
+                .read_embeddings(sample_response.clone())
+                .map_err(|e| {
+                    anyhow!(
+                        "sample_response of {} doesn't match the config: {}",
+                        generator.provider(),
+                        e
+                    )
+                })?;
+        }
+        Ok(generator)
+    }
+
+    pub async fn generate_embeddings(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        // Without the health probe nothing would mark the service available
+        // again, so every request connects
+        if self.config.health_check_interval_secs.is_some() && !self.is_available() {
+            return Err(EmbeddingUnavailableError {
+                provider: self.provider(),
+                reason: "the last request couldn't connect, waiting for the health probe"
+                    .to_string(),
+            }
+            .into());
+        }
+        self.send_request(inputs).await
+    }
+
+    async fn send_request(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let num_inputs = inputs.len();
+        let resp = self
+            .client
+            .post(&self.config.url)
+            .json(&self.request_body(inputs))
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        if !resp.status().is_success() {
+            return Err(anyhow!(
+                "unable to embed with {}: status: {}, error: {}",
+                self.config.url,
+                resp.status(),
+                resp.text().await?
+            ));
+        }
+        let response: serde_json::Value = resp.json().await.map_err(|e| {
+            if e.is_timeout() {
+                return self.request_error(e);
+            }
+            anyhow!("unable to parse embedding response: {}", e)
+        })?;
+        self.parse_response(response, num_inputs)
+    }
+
+    /// Embeds one input. With coalesce_window_ms, inputs embedded at the same
+    /// time are sent to the service in one request.
+    pub async fn embed(&self, input: String) -> Result<Vec<f32>> {
+        if self.config.coalesce_window_ms == 0 {
+            return self
+                .generate_embeddings(vec![input])
+                .await?
+                .pop()
+                .ok_or(anyhow!("No embeddings were extracted"));
+        }
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut coalescer = self.coalescer.lock().unwrap();
+            if coalescer.batch.is_none() {
+                let id = coalescer.next_id;
+                coalescer.next_id += 1;
+                coalescer.batch = Some(Batch {
+                    id,
+                    ..Default::default()
+                });
+                // The batch is sent when its window ends unless it fills up
+                // first, also if the request which started it is cancelled
+                let generator = self.clone();
+                let window = Duration::from_millis(self.config.coalesce_window_ms);
+                tokio::spawn(async move {
+                    tokio::time::sleep(window).await;
+                    let batch = {
+                        let mut coalescer = generator.coalescer.lock().unwrap();
+                        match &coalescer.batch {
+                            Some(batch) if batch.id == id => coalescer.batch.take(),
+                            _ => None,
+                        }
+                    };
+                    if let Some(batch) = batch {
+                        generator.send_batch(batch).await;
+                    }
+                });
+            }
+            let batch = coalescer.batch.as_mut().unwrap();
+            batch.inputs.push(input);
+            batch.senders.push(sender);
+            if batch.inputs.len() >= self.config.max_batch_size {
+                let batch = coalescer.batch.take().unwrap();
+                let generator = self.clone();
+                tokio::spawn(async move { generator.send_batch(batch).await });
+            }
+        }
+        receiver
+            .await
+            .map_err(|_| anyhow!("coalesced request to {} was dropped", self.provider()))?
+    }
+
+    /// Embeds the inputs of a batch in one request and sends every input its
+    /// embedding, or the error of the request
+    async fn send_batch(&self, batch: Batch) {
+        let labels = [KeyValue::new("model", self.config.model.clone())];
+        self.metrics.coalesced_requests.add(1, &labels);
+        self.metrics
+            .coalesced_batch_size
+            .record(batch.inputs.len() as u64, &labels);
+        match self.generate_embeddings(batch.inputs).await {
+            Ok(embeddings) => {
+                for (sender, embedding) in batch.senders.into_iter().zip(embeddings) {
+                    let _ = sender.send(Ok(embedding));
+                }
+            }
+            Err(e) => {
+                for sender in batch.senders {
+                    let _ = sender.send(Err(copy_error(&e)));
+                }
+            }
+        }
+    }
+
+    /// Dimension of the embeddings of the model
+    pub fn dim(&self) -> usize {
+        self.config.dim
+    }
+
+    /// Name of the model and the service embedding with it
+    pub fn provider(&self) -> String {
+        format!("{} at {}", self.config.model, self.config.url)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    pub fn url(&self) -> &str {
+        &self.config.url
+    }
+
+    fn request_error(&self, e: reqwest::Error) -> anyhow::Error {
+        if e.is_timeout() {
+            tracing::warn!(
+                "embedding request to {} timed out after {}s",
+                self.provider(),
+                self.config.read_timeout_secs
+            );
+            return EmbeddingTimeoutError {
+                provider: self.provider(),
+                timeout_secs: self.config.read_timeout_secs,
+            }
+            .into();
+        }
+        if e.is_connect() {
+            if self.available.swap(false, Ordering::Relaxed) {
+                tracing::warn!(
+                    "embedding service {} is unavailable: {}",
+                    self.provider(),
+                    e
+                );
+            }
+            return EmbeddingUnavailableError {
+                provider: self.provider(),
+                reason: e.to_string(),
+            }
+            .into();
+        }
+        anyhow!("unable to embed with {}: {}", self.provider(), e)
+    }
+
+    /// Embeds a test input to check that the service is reachable, if the
+    /// service is required on startup.
+    pub async fn check_available(&self) -> Result<()> {
+        if !self.config.require_on_startup {
+            return Ok(());
+        }
+        self.probe().await
+    }
+
+    /// Embeds a test input to check that the service responds with
+    /// embeddings of the configured dimension.
+    pub async fn probe(&self) -> Result<()> {
+        self.send_request(vec!["indexify".to_string()]).await?;
+        if !self.available.swap(true, Ordering::Relaxed) {
+            tracing::info!("embedding service {} is available again", self.provider());
+        }
+        Ok(())
+    }
+
+    /// Whether the last request could connect to the service
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    /// Probes the service every health_check_interval_secs until the server
+    /// shuts down
+    pub fn start_health_probe(&self, shutdown_rx: watch::Receiver<bool>) {
+        let Some(interval_secs) = self.config.health_check_interval_secs else {
+            return;
+        };
+        let generator = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+            loop {
+                interval.tick().await;
+                if *shutdown_rx.borrow() {
+                    break;
+                }
+                if let Err(e) = generator.probe().await {
+                    tracing::debug!("health probe of {} failed: {}", generator.provider(), e);
+                }
+            }
+        });
+    }
+
+    fn request_body(&self, inputs: Vec<String>) -> serde_json::Value {
+        let mut body = json!({ "model": self.config.model });
+        // Intermediate objects of the path are created by indexing into them
+        let mut target = &mut body;
+        for field in self.config.inputs_field.split('.') {
+            target = &mut target[field];
+        }
+        *target = json!(inputs);
+        body
+    }
+
+    /// Reads the embeddings at embeddings_field from the response
+    fn read_embeddings(&self, response: serde_json::Value) -> Result<Vec<Vec<f32>>> {
+        let path: Vec<&str> = self.config.embeddings_field.split('.').collect();
+        let embeddings = resolve_path(response, &path).ok_or(anyhow!(
+            "embedding response has no embeddings at {}",
+            self.config.embeddings_field
+        ))?;
+        serde_json::from_value(embeddings).map_err(|e| {
+            anyhow!(
+                "unable to read embeddings at {} from response: {}",
+                self.config.embeddings_field,
+                e
+            )
+        })
+    }
+
+    fn parse_response(
+        &self,
+        response: serde_json::Value,
+        num_inputs: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let embeddings = self.read_embeddings(response)?;
+        if embeddings.len() != num_inputs {
+            return Err(anyhow!(
+                "expected {} embeddings in response, got {}",
+                num_inputs,
+                embeddings.len()
+            ));
+        }
+        if let Some(embedding) = embeddings.iter().find(|e| e.len() != self.config.dim) {
+            return Err(anyhow!(
+                "expected embeddings of dimension {} from model {}, got {}",
+                self.config.dim,
+                self.config.model,
+                embedding.len()
+            ));
+        }
+        Ok(embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator() -> HttpEmbeddingGenerator {
+        HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            url: "http://localhost:8080/embed".to_string(),
+            model: "minilm".to_string(),
+            dim: 2,
+            inputs_field: "texts".to_string(),
+            embeddings_field: "vectors".to_string(),
+            connect_timeout_secs: 10,
+            read_timeout_secs: 60,
+            require_on_startup: false,
+            health_check_interval_secs: None,
+            sample_response: None,
+            coalesce_window_ms: 0,
+            max_batch_size: 32,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_request_body() {
+        let body = generator().request_body(vec!["hello".to_string()]);
+        assert_eq!(body, json!({"model": "minilm", "texts": ["hello"]}));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let generator = generator();
+        let embeddings = generator
+            .parse_response(json!({"vectors": [[0.1, 0.2]]}), 1)
+            .unwrap();
+        assert_eq!(embeddings, vec![vec![0.1, 0.2]]);
+
+        assert!(generator
+            .parse_response(json!({"embeddings": [[0.1, 0.2]]}), 1)
+            .is_err());
+        assert!(generator
+            .parse_response(json!({"vectors": [[0.1, 0.2, 0.3]]}), 1)
+            .is_err());
+        assert!(generator
+            .parse_response(json!({"vectors": [[0.1, 0.2]]}), 2)
+            .is_err());
+    }
+
+    #[test]
+    fn test_field_paths() {
+        let generator = HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            inputs_field: "input.texts".to_string(),
+            embeddings_field: "data.*.embedding".to_string(),
+            ..generator().config
+        })
+        .unwrap();
+        let body = generator.request_body(vec!["hello".to_string()]);
+        assert_eq!(
+            body,
+            json!({"model": "minilm", "input": {"texts": ["hello"]}})
+        );
+        let embeddings = generator
+            .parse_response(
+                json!({"data": [{"embedding": [0.1, 0.2]}, {"embedding": [0.3, 0.4]}]}),
+                2,
+            )
+            .unwrap();
+        assert_eq!(embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+        assert!(generator
+            .parse_response(json!({"data": [{"vector": [0.1, 0.2]}]}), 1)
+            .is_err());
+
+        // A sample response which doesn't match the config fails at startup
+        assert!(HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            sample_response: Some(json!({"vectors": [[0.1, 0.2]]})),
+            ..generator.config
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        // Accept connections without ever responding
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let generator = HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            url: format!("http://{}/embed", addr),
+            model: "minilm".to_string(),
+            dim: 2,
+            inputs_field: "inputs".to_string(),
+            embeddings_field: "embeddings".to_string(),
+            connect_timeout_secs: 1,
+            read_timeout_secs: 1,
+            require_on_startup: false,
+            health_check_interval_secs: None,
+            sample_response: None,
+            coalesce_window_ms: 0,
+            max_batch_size: 32,
+        })
+        .unwrap();
+        let err = generator
+            .generate_embeddings(vec!["hello".to_string()])
+            .await
+            .unwrap_err();
+        let err = err.downcast::<EmbeddingTimeoutError>().unwrap();
+        assert_eq!(err.provider, format!("minilm at http://{}/embed", addr));
+        assert_eq!(err.timeout_secs, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable() {
+        // Bind a port and close it again so that connections are refused
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let generator = HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            url: format!("http://{}/embed", addr),
+            model: "minilm".to_string(),
+            dim: 2,
+            inputs_field: "inputs".to_string(),
+            embeddings_field: "embeddings".to_string(),
+            connect_timeout_secs: 1,
+            read_timeout_secs: 1,
+            require_on_startup: true,
+            health_check_interval_secs: None,
+            sample_response: None,
+            coalesce_window_ms: 0,
+            max_batch_size: 32,
+        })
+        .unwrap();
+        let err = generator.check_available().await.unwrap_err();
+        assert!(err.is::<EmbeddingUnavailableError>());
+    }
+
+    #[tokio::test]
+    async fn test_health_probe() {
+        use axum::{routing::post, Json, Router};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let generator = HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            url: format!("http://{}/embed", addr),
+            model: "minilm".to_string(),
+            dim: 2,
+            inputs_field: "inputs".to_string(),
+            embeddings_field: "embeddings".to_string(),
+            connect_timeout_secs: 1,
+            read_timeout_secs: 1,
+            require_on_startup: false,
+            health_check_interval_secs: Some(3600),
+            sample_response: None,
+            coalesce_window_ms: 0,
+            max_batch_size: 32,
+        })
+        .unwrap();
+        let err = generator.embed("hello".to_string()).await.unwrap_err();
+        assert!(err.is::<EmbeddingUnavailableError>());
+        assert!(!generator.is_available());
+
+        // Requests keep failing once the service is up, until it is probed
+        let app = Router::new().route(
+            "/embed",
+            post(|| async { Json(json!({ "embeddings": [[0.1, 0.2]] })) }),
+        );
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let err = generator.embed("hello".to_string()).await.unwrap_err();
+        assert!(err.is::<EmbeddingUnavailableError>());
+
+        generator.probe().await.unwrap();
+        assert!(generator.is_available());
+        assert_eq!(
+            generator.embed("hello".to_string()).await.unwrap(),
+            vec![0.1, 0.2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use axum::{routing::post, Json, Router};
+
+        // Embeds every input as [length of the input, size of its request]
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/embed",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let inputs = body["inputs"].as_array().unwrap().clone();
+                    let embeddings: Vec<Vec<f32>> = inputs
+                        .iter()
+                        .map(|input| {
+                            vec![input.as_str().unwrap().len() as f32, inputs.len() as f32]
+                        })
+                        .collect();
+                    Json(json!({ "embeddings": embeddings }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let generator = HttpEmbeddingGenerator::new(HttpEmbeddingConfig {
+            url: format!("http://{}/embed", addr),
+            model: "minilm".to_string(),
+            dim: 2,
+            inputs_field: "inputs".to_string(),
+            embeddings_field: "embeddings".to_string(),
+            connect_timeout_secs: 1,
+            read_timeout_secs: 5,
+            require_on_startup: false,
+            health_check_interval_secs: None,
+            sample_response: None,
+            coalesce_window_ms: 100,
+            max_batch_size: 3,
+        })
+        .unwrap();
+
+        // Inputs embedded within the window share one request and every
+        // input gets its own embedding back
+        let (a, b) = tokio::join!(
+            generator.embed("a".to_string()),
+            generator.embed("bb".to_string())
+        );
+        assert_eq!(a.unwrap(), vec![1.0, 2.0]);
+        assert_eq!(b.unwrap(), vec![2.0, 2.0]);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // A full batch is sent without waiting for the window
+        let embeddings = futures::future::join_all(
+            ["a", "bb", "ccc", "dddd"].map(|input| generator.embed(input.to_string())),
+        )
+        .await;
+        let embeddings: Vec<Vec<f32>> = embeddings.into_iter().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            embeddings,
+            vec![
+                vec![1.0, 3.0],
+                vec![2.0, 3.0],
+                vec![3.0, 3.0],
+                vec![4.0, 1.0]
+            ]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use anyhow::{anyhow, Result};
 use axum::extract::ws;
@@ -17,12 +17,12 @@ use sha2::{
     Sha256VarCore,
 };
 use tokio::io::AsyncWriteExt;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
     api::*,
     blob_storage::StoragePartWriter,
-    data_manager::DataManager,
+    data_manager::{ChunkPosition, DataManager},
     server::NamespaceEndpointState,
 };
 
@@ -46,6 +46,7 @@ struct Writing {
     created_at: i64,
     file_name: String,
     file_size: u64,
+    char_count: u64,
     writer: StoragePartWriter,
     hasher: CoreWrapper<
         CtVariableCoreWrapper<
@@ -68,6 +69,12 @@ struct ContentStateWriting {
     root_content_metadata: Option<indexify_internal_api::ContentMetadata>,
     extraction_policy: indexify_coordinator::ExtractionPolicy,
     frame_state: FrameState,
+    chunk_index: u64,
+    char_offset: u64,
+    // Characters of the source content, read when the first chunk is finished
+    source_length: Option<Option<u64>>,
+    // Chunks not indexed per index because of max_fragments_per_document
+    beyond_max_fragments: BTreeMap<String, u64>,
 }
 
 impl ContentStateWriting {
@@ -87,6 +94,10 @@ impl ContentStateWriting {
             root_content_metadata: root_content,
             extraction_policy,
             frame_state: FrameState::New,
+            chunk_index: 0,
+            char_offset: 0,
+            source_length: None,
+            beyond_max_fragments: BTreeMap::new(),
         })
     }
 
@@ -94,6 +105,37 @@ impl ContentStateWriting {
         self.task.content_metadata.as_ref().unwrap()
     }
 
+    /// Counts the characters of the source content if it is text and the
+    /// position of its chunks is added to the metadata of any output index.
+    async fn source_length(&mut self, state: &NamespaceEndpointState) -> Option<u64> {
+        if let Some(source_length) = self.source_length {
+            return source_length;
+        }
+        let enrich = state
+            .data_manager
+            .enrich_chunk_metadata(&self.task.output_index_mapping);
+        let source = self
+            .task
+            .content_metadata
+            .as_ref()
+            .filter(|content| content.mime.starts_with("text/"));
+        let source_length = match source {
+            Some(source) if enrich => match state.content_reader.bytes(&source.storage_url).await {
+                Ok(bytes) => Some(String::from_utf8_lossy(&bytes).chars().count() as u64),
+                Err(e) => {
+                    warn!(
+                        "unable to read source content {} to count its characters: {}",
+                        source.id, e
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
+        self.source_length = Some(source_length);
+        source_length
+    }
+
     async fn start_content(&mut self, state: &NamespaceEndpointState) -> Result<()> {
         let ts = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -110,6 +152,7 @@ impl ContentStateWriting {
             created_at: ts as i64,
             file_name,
             file_size: 0,
+            char_count: 0,
             writer,
             hasher: Sha256::new(),
         });
@@ -132,6 +175,13 @@ impl ContentStateWriting {
             )),
             FrameState::Writing(frame_state) => {
                 frame_state.file_size += payload.bytes.len() as u64;
+                // Count utf-8 characters by skipping continuation bytes so the
+                // count stays correct when a character spans two frames.
+                frame_state.char_count += payload
+                    .bytes
+                    .iter()
+                    .filter(|b| (**b & 0xC0) != 0x80)
+                    .count() as u64;
                 frame_state.hasher.update(&payload.bytes);
                 frame_state
                     .writer
@@ -155,6 +205,7 @@ impl ContentStateWriting {
     ) -> Result<String> {
         let mut labels = self.content_metadata().labels.clone();
         let parent_id = self.content_metadata().id.clone();
+        let source_length = self.source_length(state).await;
         match &mut self.frame_state {
             FrameState::New => Err(anyhow!(
                 "received finish content without any content frames"
@@ -169,7 +220,14 @@ impl ContentStateWriting {
                 labels.extend(payload_labels);
                 let hash_result = frame_state.hasher.clone().finalize();
                 let content_hash = format!("{:x}", hash_result);
-                let id = DataManager::make_id();
+                let id = if state
+                    .data_manager
+                    .deterministic_fragment_ids(&self.task.output_index_mapping)
+                {
+                    DataManager::make_fragment_id(&parent_id, self.chunk_index, &content_hash)
+                } else {
+                    DataManager::make_id()
+                };
                 let root_content_metadata = self
                     .root_content_metadata
                     .clone()
@@ -197,7 +255,19 @@ impl ContentStateWriting {
                     extraction_graph_names: vec![self.extraction_policy.graph_name.clone()],
                     extracted_metadata: serde_json::to_string(&extracted_metadata)?,
                 };
-                state
+                // Only text chunks advance the character offsets
+                let char_count = if content_metadata.mime.starts_with("text/") {
+                    frame_state.char_count
+                } else {
+                    0
+                };
+                let chunk_position = ChunkPosition {
+                    chunk_index: self.chunk_index,
+                    char_start: self.char_offset,
+                    char_end: self.char_offset + char_count,
+                    source_length,
+                };
+                let beyond_max_fragments = state
                     .data_manager
                     .create_content_and_write_features(
                         &content_metadata,
@@ -206,8 +276,14 @@ impl ContentStateWriting {
                         &self.task.extraction_graph_name,
                         payload.features,
                         &self.task.output_index_mapping,
+                        Some(&chunk_position),
                     )
                     .await?;
+                for index in beyond_max_fragments {
+                    *self.beyond_max_fragments.entry(index).or_default() += 1;
+                }
+                self.chunk_index += 1;
+                self.char_offset = chunk_position.char_end;
                 state.metrics.node_content_extracted.add(1, &[]);
                 state
                     .metrics
@@ -237,6 +313,21 @@ impl ContentStateWriting {
             )
             .await
     }
+
+    /// Warnings about the chunks of the content which were not indexed
+    fn warnings(&self) -> Vec<String> {
+        self.beyond_max_fragments
+            .iter()
+            .map(|(index, dropped)| {
+                format!(
+                    "{} chunks of content {} were not indexed in index {}, they are beyond its max_fragments_per_document",
+                    dropped,
+                    self.content_metadata().id,
+                    index
+                )
+            })
+            .collect()
+    }
 }
 
 enum ContentState {
@@ -312,15 +403,17 @@ impl IngestExtractedContentState {
         }
     }
 
-    async fn finish(&mut self) -> Result<()> {
+    /// Finishes the ingest and returns its warnings
+    async fn finish(&mut self) -> Result<Vec<String>> {
         match &mut self.content_state {
             ContentState::Writing(s) => {
                 self.state
                     .data_manager
                     .finish_extracted_content_write(s.ingest_metadata.clone())
                     .await?;
+                let warnings = s.warnings();
                 self.content_state = ContentState::Init;
-                Ok(())
+                Ok(warnings)
             }
             ContentState::Init => Err(anyhow!(
                 "received finished extraction ingest without header metadata"
@@ -360,7 +453,14 @@ impl IngestExtractedContentState {
                         IngestExtractedContent::FinishExtractedContentIngest(_) => {
                             let res = self.finish().await;
                             let msg = match res {
-                                Ok(_) => IngestExtractedContentResponse::Success,
+                                Ok(warnings) if warnings.is_empty() => {
+                                    IngestExtractedContentResponse::Success
+                                }
+                                Ok(warnings) => {
+                                    IngestExtractedContentResponse::SuccessWithWarnings {
+                                        warnings,
+                                    }
+                                }
                                 Err(e) => IngestExtractedContentResponse::Error(e.to_string()),
                             };
                             let _ = socket.send(Message::Item(msg)).await;
@@ -425,8 +525,10 @@ mod tests {
         data_manager::DataManager,
         metadata_storage::{self, MetadataReaderTS, MetadataStorageTS},
         metrics,
+        rate_limiter::RateLimiter,
         server::{NamespaceEndpointState, Server},
         server_config::{IndexStoreKind, ServerConfig},
+        slow_query_log::SlowQueryLog,
         test_util::db_utils::{
             create_metadata,
             create_test_extraction_graph,
@@ -629,10 +731,15 @@ mod tests {
     async fn new_endpoint_state() -> Result<NamespaceEndpointState> {
         let config = make_test_config();
         let vector_db = vectordbs::create_vectordb(config.index_config.clone()).await?;
+        let index_vector_dbs = vectordbs::create_index_vectordbs(&config.index_config).await?;
         let coordinator_client = Arc::new(CoordinatorClient::new(Arc::new(config.clone())));
         let vector_index_manager = Arc::new(
-            VectorIndexManager::new(coordinator_client.clone(), vector_db.clone())
-                .map_err(|e| anyhow!("unable to create vector index {}", e))?,
+            VectorIndexManager::new(
+                coordinator_client.clone(),
+                vector_db.clone(),
+                index_vector_dbs,
+            )
+            .map_err(|e| anyhow!("unable to create vector index {}", e))?,
         );
         let metadata_index_manager: MetadataStorageTS =
             metadata_storage::from_config(&config.metadata_storage)?;
@@ -652,6 +759,9 @@ mod tests {
             content_reader: Arc::new(ContentReader::new(Arc::new(config.clone()))),
             registry: Arc::new(metrics::init_provider()),
             metrics: Arc::new(metrics::server::Metrics::new()),
+            config: Arc::new(config.clone()),
+            rate_limiter: Arc::new(RateLimiter::new(config.rate_limit.clone())),
+            slow_query_log: Arc::new(SlowQueryLog::new(config.slow_query_log.clone())),
         };
         Ok(namespace_endpoint_state)
     }
@@ -663,6 +773,37 @@ mod tests {
         assert!(matches!(ingest_state.content_state, ContentState::Init));
     }
 
+    #[test]
+    fn test_warnings() {
+        let task = indexify_coordinator::Task {
+            content_metadata: Some(indexify_coordinator::ContentMetadata {
+                id: "content".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut writing = ContentStateWriting::new(
+            BeginExtractedContentIngest {
+                task_id: "test".to_string(),
+                executor_id: "test".to_string(),
+                task_outcome: TaskOutcome::Success,
+            },
+            task,
+            None,
+            Default::default(),
+        )
+        .unwrap();
+        assert!(writing.warnings().is_empty());
+
+        writing
+            .beyond_max_fragments
+            .insert("graph.policy.embedding".to_string(), 3);
+        assert_eq!(
+            writing.warnings(),
+            vec!["3 chunks of content content were not indexed in index graph.policy.embedding, they are beyond its max_fragments_per_document"]
+        );
+    }
+
     fn set_tracing() {
         let subscriber = tracing_subscriber::fmt()
             .with_max_level(tracing::Level::INFO)
@@ -1358,4 +1499,163 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_policy_input_params() -> Result<()> {
+        use std::sync::Mutex;
+
+        use axum::{routing::post, Json, Router};
+
+        set_tracing();
+
+        let state = new_endpoint_state().await.unwrap();
+        let test_coordinator = TestCoordinator::new().await;
+
+        // The extractor records the input params of every request and embeds
+        // every content as the same vector
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let app = Router::new().route(
+            "/extract",
+            post(move |Json(request): Json<internal_api::ExtractRequest>| {
+                let received = received.clone();
+                async move {
+                    received.lock().unwrap().push(request.input_params);
+                    Json(internal_api::ExtractResponse {
+                        content: vec![],
+                        features: vec![internal_api::Feature {
+                            feature_type: internal_api::FeatureType::Embedding,
+                            name: "embedding".to_string(),
+                            data: json!({"values": [1.0, 0.0], "distance": "cosine"}),
+                        }],
+                    })
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut extractor = mock_extractor();
+        extractor.name = "ParamsExtractor".to_string();
+        extractor.outputs = HashMap::from([(
+            "embedding".to_string(),
+            internal_api::OutputSchema::Embedding(internal_api::EmbeddingSchema {
+                dim: 2,
+                distance: "cosine".to_string(),
+            }),
+        )]);
+        let mut executor = mock_executor("params_executor".to_string(), vec![extractor]);
+        executor.addr = addr.to_string();
+        test_coordinator
+            .coordinator
+            .register_executor(executor)
+            .await?;
+
+        let index_name = "params_graph.small.embedding";
+        let _ = state
+            .data_manager
+            .vector_index_manager
+            .drop_index(&format!("{}.{}", DEFAULT_TEST_NAMESPACE, index_name))
+            .await;
+        let params = json!({"model": "small"});
+        let indexes = state
+            .data_manager
+            .create_extraction_graph(
+                DEFAULT_TEST_NAMESPACE,
+                crate::api::ExtractionGraphRequest {
+                    name: "params_graph".to_string(),
+                    description: None,
+                    extraction_policies: vec![crate::api::ExtractionPolicyRequest {
+                        extractor: "ParamsExtractor".to_string(),
+                        name: "small".to_string(),
+                        filter: Default::default(),
+                        input_params: Some(params.clone()),
+                        content_source: None,
+                    }],
+                },
+            )
+            .await?;
+        assert_eq!(indexes, vec![index_name.to_string()]);
+
+        // Vectors added by the server are embedded with the params of the
+        // policy of their index
+        let content_ids = state
+            .data_manager
+            .add_vectors(
+                DEFAULT_TEST_NAMESPACE,
+                index_name,
+                vec![crate::api::VectorItem {
+                    vector: vec![0.0, 1.0],
+                    text: "hello".to_string(),
+                    metadata: HashMap::new(),
+                }],
+                None,
+            )
+            .await?;
+        let job = state
+            .data_manager
+            .start_reembed(
+                DEFAULT_TEST_NAMESPACE,
+                index_name,
+                crate::api::ReembedRequest {
+                    extractor: None,
+                    output: None,
+                    requests_per_second: Some(100.0),
+                },
+            )
+            .await?;
+        let job = loop {
+            let job = state
+                .data_manager
+                .get_reembed_job(DEFAULT_TEST_NAMESPACE, index_name, &job.id)
+                .unwrap();
+            if job.status != crate::api::ReembedJobStatus::Running {
+                break job;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(job.status, crate::api::ReembedJobStatus::Completed);
+        assert_eq!(job.processed, 1);
+        assert_eq!(
+            state
+                .data_manager
+                .get_embedding(DEFAULT_TEST_NAMESPACE, index_name, &content_ids[0])
+                .await?,
+            Some(vec![1.0, 0.0])
+        );
+        assert_eq!(*requests.lock().unwrap(), vec![Some(params.clone())]);
+
+        // Queries and scored texts are embedded like the content of the index
+        let search = state
+            .data_manager
+            .search(
+                DEFAULT_TEST_NAMESPACE,
+                index_name,
+                &["hello".to_string()],
+                crate::vector_index::SearchOptions {
+                    k: 1,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert_eq!(search.results[0].content_id, content_ids[0]);
+        let scored = state
+            .data_manager
+            .score_text(
+                DEFAULT_TEST_NAMESPACE,
+                index_name,
+                "hello",
+                1,
+                Default::default(),
+                false,
+            )
+            .await?;
+        assert_eq!(scored.results[0].content_id, content_ids[0]);
+        assert_eq!(*requests.lock().unwrap(), vec![Some(params); 3]);
+
+        test_coordinator.stop().await;
+
+        Ok(())
+    }
 }
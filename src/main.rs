@@ -10,6 +10,7 @@ use opentelemetry_sdk::{
     Resource,
 };
 use rustls::crypto::CryptoProvider;
+use server_config::TracingConfig;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, Layer};
 
 pub mod coordinator_filters;
@@ -28,13 +29,18 @@ mod cmd;
 mod coordinator;
 mod coordinator_client;
 mod data_manager;
+mod embedding_cache;
 mod extractor_router;
 mod forwardable_coordinator;
 mod garbage_collector;
 mod grpc_helper;
+mod http_embedding;
 mod ingest_extracted_content;
 mod metadata_storage;
+mod rate_limiter;
 mod scheduler;
+mod search_limiter;
+mod slow_query_log;
 mod test_util;
 mod tls;
 mod tonic_streamer;
@@ -47,7 +53,6 @@ mod vectordbs;
 mod test_utils;
 
 fn setup_stdout_tracing() -> Result<()> {
-    global::set_text_map_propagator(TraceContextPropagator::new());
     let provider = opentelemetry_sdk::trace::TracerProvider::builder()
         .with_batch_exporter(
             opentelemetry_stdout::SpanExporterBuilder::default()
@@ -91,27 +96,38 @@ fn get_percent_traced() -> f64 {
     }
 }
 
-fn setup_otlp_tracing() -> Result<()> {
+fn otlp_env_config() -> Result<TracingConfig> {
     let endpoint = match std::env::var("INDEXIFY_TRACE_ENDPOINT") {
         Ok(s) => s,
         Err(_) => return Err(anyhow!("trace endpoint not configured")),
     };
+    Ok(TracingConfig {
+        endpoint,
+        service_name: "indexify".to_string(),
+        sampling_rate: get_percent_traced(),
+    })
+}
+
+fn setup_otlp_tracing(config: &TracingConfig) -> Result<()> {
+    // Spans of requests with a traceparent header are sampled if the caller
+    // sampled its trace, so that traces are not missing the indexify spans
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_rate)));
     let tracer = opentelemetry_otlp::new_pipeline()
         .tracing()
         .with_trace_config(
             opentelemetry_sdk::trace::Config::default()
                 .with_id_generator(RandomIdGenerator::default())
-                .with_sampler(Sampler::TraceIdRatioBased(get_percent_traced()))
+                .with_sampler(sampler)
                 .with_resource(Resource::new(vec![KeyValue::new(
                     "service.name",
-                    "indexify",
+                    config.service_name.clone(),
                 )])),
         )
         .with_exporter(
             opentelemetry_otlp::new_exporter()
                 .http()
                 .with_timeout(std::time::Duration::from_secs(10))
-                .with_endpoint(endpoint),
+                .with_endpoint(config.endpoint.clone()),
         )
         .with_batch_config(BatchConfig::default())
         .install_batch(runtime::Tokio)?;
@@ -146,7 +162,7 @@ fn setup_tracing(trace_type: &str) -> Result<()> {
     match trace_type {
         "stdout" => setup_stdout_tracing(),
         "datadog" => setup_datadog_tracing(),
-        "otlp" => setup_otlp_tracing(),
+        "otlp" => setup_otlp_tracing(&otlp_env_config()?),
         _ => Err(anyhow!("invalid trace type")),
     }
 }
@@ -168,12 +184,25 @@ pub(crate) fn setup_fmt_tracing() {
 struct OtelGuard;
 
 impl OtelGuard {
-    fn new() -> Self {
+    /// Sets up tracing with the type of INDEXIFY_TRACE, or with the tracing
+    /// config of the config file if it is not set.
+    fn new(config: Option<TracingConfig>) -> Self {
+        // Incoming traceparent headers are extracted with the global
+        // propagator, so that request spans join the trace of the caller
+        global::set_text_map_propagator(TraceContextPropagator::new());
         if let Ok(trace_type) = std::env::var("INDEXIFY_TRACE") {
             if let Err(e) = setup_tracing(&trace_type) {
                 eprintln!("failed to setup tracing with type {}: {}", trace_type, e);
                 setup_fmt_tracing();
             }
+        } else if let Some(config) = config {
+            if let Err(e) = setup_otlp_tracing(&config) {
+                eprintln!(
+                    "failed to setup tracing with endpoint {}: {}",
+                    config.endpoint, e
+                );
+                setup_fmt_tracing();
+            }
         } else {
             setup_fmt_tracing();
         }
@@ -209,9 +238,10 @@ pub mod prelude {
 async fn main() {
     // When this guard is dropped (at the end of this function, by default), the
     // opentelemetry tracer is automatically shut down.
-    let _otel_guard = OtelGuard::new();
+    let cli = cmd::Cli::parse();
+    let _otel_guard = OtelGuard::new(cli.tracing_config());
 
     CryptoProvider::install_default(rustls::crypto::ring::default_provider()).unwrap();
 
-    cmd::Cli::parse().run().await;
+    cli.run().await;
 }
@@ -688,7 +688,7 @@ pub mod metadata_storage {
 }
 
 pub mod vector_storage {
-    use opentelemetry::metrics::Histogram;
+    use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
 
     #[derive(Debug)]
     pub struct Metrics {
@@ -700,6 +700,9 @@ pub mod vector_storage {
         pub vector_search_db: Histogram<f64>,
         pub vector_search_retrieve_metadata: Histogram<f64>,
         pub vector_search_retrieve_blob: Histogram<f64>,
+        pub vector_search_in_flight: UpDownCounter<i64>,
+        pub vector_chunks_dropped: Counter<u64>,
+        pub vector_db_connections: Counter<u64>,
     }
 
     impl Default for Metrics {
@@ -752,6 +755,25 @@ pub mod vector_storage {
                 .with_description("Vector search retrieve content blob latencies in seconds")
                 .init();
 
+            let vector_search_in_flight = meter
+                .i64_up_down_counter("indexify.vector_search_in_flight")
+                .with_description("Vector searches in progress per index")
+                .init();
+
+            let vector_chunks_dropped = meter
+                .u64_counter("indexify.vector_chunks_dropped")
+                .with_description(
+                    "Chunks not indexed because of min_chunk_chars or max_fragments_per_document",
+                )
+                .init();
+
+            let vector_db_connections = meter
+                .u64_counter("indexify.vector_db_connections")
+                .with_description(
+                    "Connections opened to the vector database, including those replacing broken connections",
+                )
+                .init();
+
             Metrics {
                 vector_metadata_update,
                 vector_upsert,
@@ -761,6 +783,46 @@ pub mod vector_storage {
                 vector_search_db,
                 vector_search_retrieve_metadata,
                 vector_search_retrieve_blob,
+                vector_search_in_flight,
+                vector_chunks_dropped,
+                vector_db_connections,
+            }
+        }
+    }
+}
+
+pub mod http_embedding {
+    use opentelemetry::metrics::{Counter, Histogram};
+
+    #[derive(Debug, Clone)]
+    pub struct Metrics {
+        pub coalesced_requests: Counter<u64>,
+        pub coalesced_batch_size: Histogram<u64>,
+    }
+
+    impl Default for Metrics {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Metrics {
+        pub fn new() -> Metrics {
+            let meter = opentelemetry::global::meter("indexify-index");
+
+            let coalesced_requests = meter
+                .u64_counter("indexify.query_embedding_coalesced_requests")
+                .with_description("Requests sent to embedding services for coalesced queries")
+                .init();
+
+            let coalesced_batch_size = meter
+                .u64_histogram("indexify.query_embedding_coalesced_batch_size")
+                .with_description("Number of queries embedded per coalesced request")
+                .init();
+
+            Metrics {
+                coalesced_requests,
+                coalesced_batch_size,
             }
         }
     }
@@ -0,0 +1,354 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{AUTHORIZATION, RETRY_AFTER},
+        HeaderValue,
+        StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::server_config::{RateLimit, RateLimitConfig};
+
+// Anonymous buckets of the clients which sent requests the longest time ago are
+// dropped once this many clients are tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes a token from the bucket, or returns how long to wait until a
+    /// token is available.
+    fn try_acquire(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if limit.requests_per_second <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        let wait = (1.0 - self.tokens) / limit.requests_per_second;
+        Err(Duration::from_secs_f64(wait))
+    }
+}
+
+/// Anonymous buckets keyed by client address, evicting the least recently
+/// used bucket once max_clients are tracked
+#[derive(Debug)]
+struct ClientBuckets {
+    max_clients: usize,
+    buckets: HashMap<Option<IpAddr>, (TokenBucket, u64)>,
+    last_used: BTreeMap<u64, Option<IpAddr>>,
+    next_use: u64,
+}
+
+impl ClientBuckets {
+    fn new(max_clients: usize) -> Self {
+        Self {
+            max_clients,
+            buckets: HashMap::new(),
+            last_used: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    fn get_or_insert(&mut self, client: Option<IpAddr>, bucket: TokenBucket) -> &mut TokenBucket {
+        let used = self.next_use;
+        self.next_use += 1;
+        match self.buckets.get_mut(&client) {
+            Some((_, last_used)) => {
+                self.last_used.remove(&*last_used);
+                *last_used = used;
+            }
+            None => {
+                if self.buckets.len() >= self.max_clients {
+                    if let Some((_, evicted)) = self.last_used.pop_first() {
+                        self.buckets.remove(&evicted);
+                    }
+                }
+                self.buckets.insert(client, (bucket, used));
+            }
+        }
+        self.last_used.insert(used, client);
+        &mut self.buckets.get_mut(&client).unwrap().0
+    }
+}
+
+/// Current state of the bucket of an api key, or of the anonymous bucket of
+/// a client
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RateLimitStatus {
+    pub key: Option<String>,
+    pub client: Option<String>,
+    pub requests_per_second: f64,
+    pub burst: u64,
+    pub available_tokens: f64,
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<ClientBuckets>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter").finish()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(ClientBuckets::new(MAX_TRACKED_CLIENTS)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Takes a token from the bucket of the api key. Requests without an api
+    /// key, or with a key which isn't configured, share the anonymous bucket
+    /// of their client.
+    pub fn check(&self, key: Option<&str>, client: Option<IpAddr>) -> Result<(), Duration> {
+        self.check_at(key, client, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        key: Option<&str>,
+        client: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        if let Some((key, limit)) = key.and_then(|key| self.config.keys.get_key_value(key)) {
+            return self
+                .buckets
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_insert_with(|| TokenBucket::new(limit, now))
+                .try_acquire(limit, now);
+        }
+        let limit = &self.config.anonymous;
+        self.clients
+            .lock()
+            .unwrap()
+            .get_or_insert(client, TokenBucket::new(limit, now))
+            .try_acquire(limit, now)
+    }
+
+    /// Returns the limits and available tokens of every configured api key and
+    /// of the anonymous buckets of the clients that recently sent requests.
+    pub fn status(&self) -> Vec<RateLimitStatus> {
+        let now = Instant::now();
+        let mut status = Vec::new();
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut keys: Vec<_> = self.config.keys.iter().collect();
+        keys.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, limit) in keys {
+            let available_tokens = buckets
+                .get_mut(key)
+                .map(|bucket| {
+                    bucket.refill(limit, now);
+                    bucket.tokens
+                })
+                .unwrap_or(limit.burst as f64);
+            status.push(RateLimitStatus {
+                key: Some(key.clone()),
+                client: None,
+                requests_per_second: limit.requests_per_second,
+                burst: limit.burst,
+                available_tokens,
+            });
+        }
+
+        let anonymous = &self.config.anonymous;
+        let mut clients = self.clients.lock().unwrap();
+        let mut anonymous_status: Vec<_> = clients
+            .buckets
+            .iter_mut()
+            .map(|(client, (bucket, _))| {
+                bucket.refill(anonymous, now);
+                RateLimitStatus {
+                    key: None,
+                    client: client.map(|client| client.to_string()),
+                    requests_per_second: anonymous.requests_per_second,
+                    burst: anonymous.burst,
+                    available_tokens: bucket.tokens,
+                }
+            })
+            .collect();
+        anonymous_status.sort_by(|a, b| a.client.cmp(&b.client));
+        status.extend(anonymous_status);
+        status
+    }
+}
+
+fn api_key(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+// Requests are keyed by the address of the connection, not by forwarded headers
+// which clients can set to any value.
+fn client_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Middleware rejecting requests with 429 once the bucket of their api key, or
+/// the anonymous bucket of their client, is empty
+pub async fn rate_limit(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !rate_limiter.enabled() {
+        return next.run(request).await;
+    }
+    if let Err(wait) = rate_limiter.check(api_key(&request), client_ip(&request)) {
+        let retry_after = wait.as_secs_f64().ceil().min(u32::MAX as f64) as u64;
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded").into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+        return response;
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            enabled: true,
+            anonymous: RateLimit {
+                requests_per_second: 1.0,
+                burst: 2,
+            },
+            keys: HashMap::from([(
+                "key1".to_string(),
+                RateLimit {
+                    requests_per_second: 10.0,
+                    burst: 5,
+                },
+            )]),
+        })
+    }
+
+    fn client(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, last]))
+    }
+
+    #[test]
+    fn test_burst_and_refill() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        assert!(rate_limiter.check_at(None, client(1), now).is_ok());
+        assert!(rate_limiter.check_at(None, client(1), now).is_ok());
+        let wait = rate_limiter.check_at(None, client(1), now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        let later = now + Duration::from_secs(1);
+        assert!(rate_limiter.check_at(None, client(1), later).is_ok());
+        assert!(rate_limiter.check_at(None, client(1), later).is_err());
+    }
+
+    #[test]
+    fn test_keys_have_separate_buckets() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert!(rate_limiter.check_at(Some("key1"), client(1), now).is_ok());
+        }
+        assert!(rate_limiter.check_at(Some("key1"), client(1), now).is_err());
+
+        assert!(rate_limiter.check_at(None, client(1), now).is_ok());
+        assert!(rate_limiter.check_at(None, client(2), now).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_keys_share_the_anonymous_bucket() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        assert!(rate_limiter.check_at(Some("key2"), client(1), now).is_ok());
+        assert!(rate_limiter.check_at(Some("key3"), client(1), now).is_ok());
+        assert!(rate_limiter.check_at(None, client(1), now).is_err());
+        assert!(rate_limiter.check_at(Some("key4"), client(1), now).is_err());
+    }
+
+    #[test]
+    fn test_least_recently_used_clients_are_evicted() {
+        let mut clients = ClientBuckets::new(2);
+        let limit = RateLimit {
+            requests_per_second: 1.0,
+            burst: 1,
+        };
+        let now = Instant::now();
+        for last in [1, 2, 1, 3] {
+            clients
+                .get_or_insert(client(last), TokenBucket::new(&limit, now))
+                .try_acquire(&limit, now)
+                .ok();
+        }
+        assert_eq!(clients.buckets.len(), 2);
+        assert!(clients.buckets.contains_key(&client(1)));
+        assert!(!clients.buckets.contains_key(&client(2)));
+        assert!(clients.buckets.contains_key(&client(3)));
+        assert_eq!(clients.last_used.len(), 2);
+    }
+
+    #[test]
+    fn test_status() {
+        let rate_limiter = rate_limiter();
+        rate_limiter.check(Some("key2"), client(1)).unwrap();
+        let status = rate_limiter.status();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[0].key, Some("key1".to_string()));
+        assert_eq!(status[0].burst, 5);
+        assert_eq!(status[1].key, None);
+        assert_eq!(status[1].client, Some("10.0.0.1".to_string()));
+        assert!(status[1].available_tokens < 2.0);
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use opentelemetry::{metrics::UpDownCounter, KeyValue};
+use thiserror::Error;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Returned when an index already runs and queues as many searches as its
+/// settings allow.
+#[derive(Debug, Error)]
+#[error("too many concurrent searches on index {index}, retry later")]
+pub struct SearchLimitExceeded {
+    pub index: String,
+}
+
+/// SearchLimiter bounds the searches running concurrently on an index, so that
+/// a single index can't use up the connections to the vector database.
+/// Searches over the limit wait for a running search to finish, up to
+/// max_queued waiting searches.
+pub struct SearchLimiter {
+    index: String,
+    semaphore: Semaphore,
+    max_queued: usize,
+    queued: AtomicUsize,
+}
+
+/// Decrements the number of queued searches when a waiting search starts or
+/// is cancelled.
+struct QueuedSearch<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedSearch<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SearchLimiter {
+    pub fn new(index: &str, max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            index: index.to_string(),
+            semaphore: Semaphore::new(max_concurrent),
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, SearchLimitExceeded> {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            return Ok(permit);
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(SearchLimitExceeded {
+                index: self.index.clone(),
+            });
+        }
+        let _queued = QueuedSearch(&self.queued);
+        // The semaphore is never closed
+        Ok(self.semaphore.acquire().await.unwrap())
+    }
+}
+
+/// InFlightSearch counts a search in the in flight searches of its index
+/// until it is dropped, and holds the permit of the search limiter of the
+/// index if it has one.
+pub struct InFlightSearch<'a> {
+    in_flight: UpDownCounter<i64>,
+    labels: [KeyValue; 1],
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl<'a> InFlightSearch<'a> {
+    pub fn new(
+        in_flight: &UpDownCounter<i64>,
+        index: &str,
+        permit: Option<SemaphorePermit<'a>>,
+    ) -> Self {
+        let labels = [KeyValue::new("index", index.to_string())];
+        in_flight.add(1, &labels);
+        Self {
+            in_flight: in_flight.clone(),
+            labels,
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for InFlightSearch<'_> {
+    fn drop(&mut self) {
+        self.in_flight.add(-1, &self.labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_search_limiter() {
+        let limiter = SearchLimiter::new("index1", 1, 1);
+        let running = limiter.acquire().await.unwrap();
+
+        // The second search waits for the first and the third is rejected
+        let queued = limiter.acquire();
+        tokio::pin!(queued);
+        assert!(futures::poll!(&mut queued).is_pending());
+        let err = limiter.acquire().await.unwrap_err();
+        assert_eq!(err.index, "index1");
+
+        drop(running);
+        let permit = queued.await.unwrap();
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+        drop(permit);
+        assert!(limiter.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_search_leaves_queue() {
+        let limiter = SearchLimiter::new("index1", 1, 1);
+        let _running = limiter.acquire().await.unwrap();
+        {
+            let queued = limiter.acquire();
+            tokio::pin!(queued);
+            assert!(futures::poll!(&mut queued).is_pending());
+        }
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+    }
+}
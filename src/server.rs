@@ -3,7 +3,7 @@ use std::{
     net::SocketAddr,
     str::FromStr,
     sync::Arc,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{anyhow, Context, Result};
@@ -11,6 +11,7 @@ use axum::{
     body::Body,
     extract::{DefaultBodyLimit, Multipart, Path, Query, State},
     http::{HeaderMap, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Extension,
@@ -21,7 +22,10 @@ use axum_otel_metrics::HttpMetricsLayerBuilder;
 use axum_server::{tls_rustls::RustlsConfig, Handle};
 use axum_tracing_opentelemetry::middleware::OtelAxumLayer;
 use axum_typed_websockets::WebSocketUpgrade;
-use hyper::{header::CONTENT_TYPE, Method};
+use hyper::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Method,
+};
 use indexify_internal_api as internal_api;
 use indexify_proto::indexify_coordinator::{
     self,
@@ -36,6 +40,7 @@ use internal_api::ContentOffset;
 use mime::Mime;
 use prometheus::Encoder;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use tokio::{
     signal,
     sync::{mpsc, watch},
@@ -58,18 +63,42 @@ use crate::{
     blob_storage::{BlobStorage, ContentReader},
     caching::caches_extension::Caches,
     coordinator_client::CoordinatorClient,
-    data_manager::DataManager,
+    data_manager::{
+        DataManager,
+        InvalidIndexNameError,
+        MissingIndexError,
+        ReadOnlyIndexError,
+        ReembedError,
+    },
+    embedding_cache::{CachePolicy, EmbeddingCacheStats},
+    http_embedding::{EmbeddingTimeoutError, EmbeddingUnavailableError},
     ingest_extracted_content::IngestExtractedContentState,
     metadata_storage::{self, MetadataReaderTS, MetadataStorageTS},
     metrics,
-    server_config::ServerConfig,
+    rate_limiter::{self, RateLimitStatus, RateLimiter},
+    search_limiter::SearchLimitExceeded,
+    slow_query_log::{SlowQuery, SlowQueryLog},
+    server_config::{IndexStoreKind, ServerConfig},
     tls::build_mtls_config,
-    vector_index::VectorIndexManager,
-    vectordbs,
+    vector_index::{
+        DimensionMismatchError,
+        DimensionsOverrideError,
+        NonFiniteEmbeddingError,
+        ScoredText,
+        SearchCursor,
+        SearchOptions,
+        SearchResults,
+        VectorIndexManager,
+    },
+    vectordbs::{self, InvalidContentIdError, UnsupportedIndexError},
 };
 
 const DEFAULT_SEARCH_LIMIT: u64 = 5;
 
+const DEFAULT_NEGATIVE_WEIGHT: f32 = 0.5;
+
+const ARROW_STREAM_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+
 #[derive(Clone, Debug)]
 pub struct NamespaceEndpointState {
     pub data_manager: Arc<DataManager>,
@@ -77,6 +106,9 @@ pub struct NamespaceEndpointState {
     pub content_reader: Arc<ContentReader>,
     pub registry: Arc<prometheus::Registry>,
     pub metrics: Arc<metrics::server::Metrics>,
+    pub config: Arc<ServerConfig>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub slow_query_log: Arc<SlowQueryLog>,
 }
 
 #[derive(OpenApi)]
@@ -102,22 +134,55 @@ pub struct NamespaceEndpointState {
             ingest_remote_file,
             add_graph_to_content,
             list_tasks,
+            describe_index,
+            count_index,
+            validate_filter,
+            list_index_fields,
             index_search,
+            similar_by_id,
+            score_text,
+            search_by_vector,
+            update_index_metadata,
+            add_vectors,
+            get_embedding,
+            list_rate_limits,
+            list_slow_queries,
+            embedding_cache_stats,
+            embed_document,
+            embedding_providers,
+            vector_dbs,
+            reset,
+            reconcile_index,
+            version,
+            export_index,
+            import_index,
+            create_snapshot,
+            list_snapshots,
+            restore_snapshot,
+            set_index_read_only,
+            reembed_index,
+            get_reembed_job,
             get_content_tree_metadata,
             download_content,
             extraction_graph_analytics,
         ),
         components(
             schemas(IndexDistance,
-                TextAddRequest, TextAdditionResponse, Text, IndexSearchResponse,
-                DocumentFragment, ListIndexesResponse, ExtractorOutputSchema, Index, SearchRequest, ListNamespacesResponse, ListExtractorsResponse
+                TextAddRequest, TextAdditionResponse, Text, IndexSearchResponse, SearchStats, SearchMode, SearchBoost, SearchSort, SortOrder, SearchResultGroup,
+                DocumentFragment, SimilarRequest, ScoreRequest, SearchVectorRequest, UpdateIndexMetadataRequest, AddVectorsRequest, CreateIndexIfMissing, AddVectorsResponse, VectorItem, ListIndexesResponse, DescribeIndexResponse, CountIndexResponse, ValidateFilterRequest, ValidateFilterResponse, FilterError, ListIndexFieldsResponse, IndexField, ExtractorOutputSchema, Index, SearchRequest, SearchQuery, ListNamespacesResponse, ListExtractorsResponse
             , ExtractorDescription, DataNamespace, ExtractionPolicy, ExtractionPolicyRequest, ExtractionPolicyResponse, Executor,
             MetadataResponse, ExtractedMetadata, ListExecutorsResponse, EmbeddingSchema, ExtractResponse, ExtractRequest,
             Feature, FeatureType, GetContentMetadataResponse, ListTasksResponse,  Task, ExtractionGraph,
             Content, ContentMetadata, ListContentResponse, GetNamespaceResponse, ExtractionPolicyResponse, ListTasks,
             ListExtractionGraphResponse, ExtractionGraphLink, ExtractionGraphRequest, ExtractionGraphResponse,
             AddGraphToContent, NewContentStreamResponse, ExtractionGraphAnalytics, TaskAnalytics,
-            IngestRemoteFileResponse, IngestRemoteFile
+            IngestRemoteFileResponse, IngestRemoteFile, GetEmbeddingResponse,
+            RateLimitsResponse, RateLimitStatus, SlowQueriesResponse, SlowQuery, ImportIndexResponse, VersionResponse,
+            CreateSnapshotRequest, IndexSnapshot, ListSnapshotsResponse, RestoreSnapshotRequest, RestoreSnapshotResponse, IndexReadOnly,
+            ReembedRequest, ReembedJob, ReembedJobStatus,
+            ResetRequest, ResetResponse, ReconcileResponse, EmbeddingCacheResponse, EmbeddingCacheStats,
+            EmbeddingProvidersResponse, EmbeddingProvider, VectorDbsResponse, VectorDbCapabilities,
+            EmbedDocumentRequest, EmbeddedChunk, EmbedDocumentResponse
         )
         ),
         tags(
@@ -153,11 +218,18 @@ impl Server {
             false => info!("starting indexify server with TLS disabled"),
         }
         let vector_db = vectordbs::create_vectordb(self.config.index_config.clone()).await?;
+        let index_vector_dbs = vectordbs::create_index_vectordbs(&self.config.index_config).await?;
         let coordinator_client = Arc::new(CoordinatorClient::new(Arc::clone(&self.config)));
         let vector_index_manager = Arc::new(
-            VectorIndexManager::new(coordinator_client.clone(), vector_db.clone())
-                .map_err(|e| anyhow!("unable to create vector index {}", e))?,
+            VectorIndexManager::new(
+                coordinator_client.clone(),
+                vector_db.clone(),
+                index_vector_dbs,
+            )
+            .map_err(|e| anyhow!("unable to create vector index {}", e))?,
         );
+        vector_index_manager.check_query_embedders().await?;
+        vector_index_manager.start_query_embedder_probes(shutdown_rx.clone());
         let metadata_index_manager: MetadataStorageTS =
             metadata_storage::from_config(&self.config.metadata_storage)?;
         let metadata_reader: MetadataReaderTS =
@@ -172,6 +244,7 @@ impl Server {
             blob_storage.clone(),
             coordinator_client.clone(),
         ));
+        self.preload_indexes(&data_manager).await?;
         let ingestion_server_id = nanoid::nanoid!(16);
 
         self.start_gc_tasks_stream(
@@ -180,12 +253,18 @@ impl Server {
             data_manager.clone(),
             shutdown_rx.clone(),
         );
+        if let Some(interval_secs) = self.config.reconcile_interval_secs {
+            self.start_reconcile_task(data_manager.clone(), interval_secs, shutdown_rx.clone());
+        }
         let namespace_endpoint_state = NamespaceEndpointState {
             data_manager: data_manager.clone(),
             coordinator_client: coordinator_client.clone(),
             content_reader: Arc::new(ContentReader::new(self.config.clone())),
             registry,
             metrics: Arc::new(crate::metrics::server::Metrics::new()),
+            config: self.config.clone(),
+            rate_limiter: Arc::new(RateLimiter::new(self.config.rate_limit.clone())),
+            slow_query_log: Arc::new(SlowQueryLog::new(self.config.slow_query_log.clone())),
         };
         let caches = Caches::new(self.config.cache.clone());
         let cors = CorsLayer::new()
@@ -263,10 +342,118 @@ impl Server {
                 "/namespaces/:namespace/indexes",
                 get(list_indexes).with_state(namespace_endpoint_state.clone()),
             )
+            .route(
+                "/namespaces/:namespace/indexes/:index/describe",
+                get(describe_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/count",
+                get(count_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/validate_filter",
+                post(validate_filter).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/fields",
+                get(list_index_fields).with_state(namespace_endpoint_state.clone()),
+            )
             .route(
                 "/namespaces/:namespace/indexes/:index/search",
                 post(index_search).with_state(namespace_endpoint_state.clone()),
             )
+            .route(
+                "/namespaces/:namespace/indexes/:index/similar",
+                post(similar_by_id).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/score",
+                post(score_text).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/search_vector",
+                post(search_by_vector).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/update_metadata",
+                post(update_index_metadata).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/add_vectors",
+                post(add_vectors).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/embedding",
+                get(get_embedding).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/export",
+                get(export_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/import",
+                post(import_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/snapshots",
+                post(create_snapshot).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/snapshots",
+                get(list_snapshots).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/restore",
+                post(restore_snapshot).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/read_only",
+                post(set_index_read_only).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/reembed",
+                post(reembed_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/namespaces/:namespace/indexes/:index/reembed/:job_id",
+                get(get_reembed_job).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/embeddings/embed_document",
+                post(embed_document).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/version",
+                get(version).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/embedding_cache",
+                get(embedding_cache_stats).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/embedding_providers",
+                get(embedding_providers).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/vector_dbs",
+                get(vector_dbs).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/reset",
+                post(reset).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/reconcile",
+                post(reconcile_index).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/rate_limits",
+                get(list_rate_limits).with_state(namespace_endpoint_state.clone()),
+            )
+            .route(
+                "/admin/slow_queries",
+                get(list_slow_queries).with_state(namespace_endpoint_state.clone()),
+            )
             .route(
                 "/namespaces/:namespace/active_content",
                 get(active_content).with_state(namespace_endpoint_state.clone()),
@@ -335,6 +522,10 @@ impl Server {
             )
             .route("/ui", get(ui_index_handler))
             .route("/ui/*rest", get(ui_handler))
+            .layer(middleware::from_fn_with_state(
+                namespace_endpoint_state.rate_limiter.clone(),
+                rate_limiter::rate_limit,
+            ))
             .layer(OtelAxumLayer::default())
             .layer(metrics)
             .layer(Extension(caches))
@@ -375,7 +566,7 @@ impl Server {
                 let rustls_config = RustlsConfig::from_config(config);
                 axum_server::tls_rustls::bind_rustls(self.addr, rustls_config)
                     .handle(handle)
-                    .serve(app.into_make_service())
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                     .await?;
             } else {
                 return Err(anyhow!("TLS is enabled but no TLS config provided"));
@@ -384,7 +575,7 @@ impl Server {
             let handle = handle.clone();
             axum_server::bind(self.addr)
                 .handle(handle)
-                .serve(app.into_make_service())
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                 .await
                 .with_context(|| format!("addr: {}", self.addr))?;
         }
@@ -392,6 +583,68 @@ impl Server {
         Ok(())
     }
 
+    /// Loads the indexes configured to be preloaded. Failures only fail the
+    /// startup of the server with strict_preload.
+    async fn preload_indexes(&self, data_manager: &DataManager) -> Result<()> {
+        let mut indexes = self.config.preload_indexes.clone();
+        if self.config.preload_all_indexes {
+            match data_manager.list_all_indexes().await {
+                Ok(all_indexes) => indexes = all_indexes,
+                Err(e) if self.config.strict_preload => {
+                    return Err(anyhow!("unable to list indexes to preload: {}", e));
+                }
+                Err(e) => tracing::error!("unable to list indexes to preload: {}", e),
+            }
+        }
+        for index in indexes {
+            let start = std::time::Instant::now();
+            let result = match index.split_once('/') {
+                Some((namespace, index_name)) => {
+                    data_manager.preload_index(namespace, index_name).await
+                }
+                None => Err(anyhow!("index must be written as namespace/index")),
+            };
+            match result {
+                Ok(num_vectors) => info!(
+                    "preloaded index {} with {} vectors in {:?}",
+                    index,
+                    num_vectors,
+                    start.elapsed()
+                ),
+                Err(e) if self.config.strict_preload => {
+                    return Err(anyhow!("unable to preload index {}: {}", index, e));
+                }
+                Err(e) => tracing::error!("unable to preload index {}: {}", index, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Periodically removes the vectors of deleted content from every index
+    pub fn start_reconcile_task(
+        &self,
+        data_manager: Arc<DataManager>,
+        interval_secs: u64,
+        shutdown_rx: watch::Receiver<bool>,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+            // The first tick completes immediately, skip it so that indexes are
+            // not reconciled while the server starts
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if *shutdown_rx.borrow() {
+                    tracing::info!("shutting down reconcile loop");
+                    break;
+                }
+                if let Err(e) = data_manager.reconcile_all().await {
+                    tracing::error!("unable to reconcile indexes: {}", e);
+                }
+            }
+        });
+    }
+
     pub fn start_gc_tasks_stream(
         &self,
         coordinator_client: Arc<CoordinatorClient>,
@@ -501,6 +754,77 @@ impl Server {
     }
 }
 
+/// Checks that the request carries the configured admin api key as a bearer
+/// token. Admin endpoints are rejected outright if no key is configured.
+fn authorize_admin(
+    state: &NamespaceEndpointState,
+    headers: &HeaderMap,
+) -> Result<(), IndexifyAPIError> {
+    let admin_api_key =
+        state.config.admin_api_key.as_ref().ok_or_else(|| {
+            IndexifyAPIError::new(StatusCode::FORBIDDEN, "admin api is not enabled")
+        })?;
+    match bearer_token(headers) {
+        Some(token) if keys_match(token, admin_api_key) => Ok(()),
+        _ => Err(IndexifyAPIError::unauthorized("invalid admin api key")),
+    }
+}
+
+/// Compares the digests of the keys in constant time, so that the time of
+/// the comparison doesn't tell how much of the key was guessed, nor its
+/// length
+fn keys_match(token: &str, key: &str) -> bool {
+    let token = Sha256::digest(token.as_bytes());
+    let key = Sha256::digest(key.as_bytes());
+    let diff = token
+        .iter()
+        .zip(key.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    diff == 0
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Checks the number of texts of a request, and their total number of
+/// characters, against the input limits of the api key of the request.
+fn check_input_limits(
+    state: &NamespaceEndpointState,
+    headers: &HeaderMap,
+    texts: &[&str],
+) -> Result<(), IndexifyAPIError> {
+    let limits = state.config.input_limits.for_key(bearer_token(headers));
+    if let Some(max_inputs) = limits.max_inputs {
+        if texts.len() > max_inputs {
+            return Err(IndexifyAPIError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!(
+                    "request has {} inputs, the limit is {}",
+                    texts.len(),
+                    max_inputs
+                ),
+            ));
+        }
+    }
+    if let Some(max_chars) = limits.max_chars {
+        let num_chars: usize = texts.iter().map(|text| text.chars().count()).sum();
+        if num_chars > max_chars {
+            return Err(IndexifyAPIError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!(
+                    "request has {} characters, the limit is {}",
+                    num_chars, max_chars
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
 #[tracing::instrument]
 async fn root() -> &'static str {
     "Indexify Server"
@@ -648,13 +972,13 @@ async fn namespace_open_api(
     tag = "ingestion",
     responses(
         (status = 200, description = "Extraction graph added successfully", body = ExtractionGraphResponse),
+        (status = BAD_REQUEST, description = "An index of the graph has an invalid name, or a name differing only by case from another index, its dimensions_override exceeds its dimension, or its vector database doesn't support its distance or storage dtype"),
+        (status = CONFLICT, description = "An extraction graph with the same name already exists in the namespace"),
         (status = INTERNAL_SERVER_ERROR, description = "Unable to add extraction graph to namespace")
     ),
 )]
 #[axum::debug_handler]
 async fn create_extraction_graph(
-    // FIXME: this throws a 500 when the binding already exists
-    // FIXME: also throws a 500 when the index name already exists
     headers: HeaderMap,
     Path(namespace): Path<String>,
     State(state): State<NamespaceEndpointState>,
@@ -686,7 +1010,21 @@ async fn create_extraction_graph(
         .data_manager
         .create_extraction_graph(&namespace, payload)
         .await
-        .map_err(IndexifyAPIError::internal_error)?
+        .map_err(|e| {
+            if let Some(status) = e
+                .downcast_ref::<tonic::Status>()
+                .filter(|status| status.code() == tonic::Code::AlreadyExists)
+            {
+                return IndexifyAPIError::new(StatusCode::CONFLICT, status.message());
+            }
+            if e.is::<UnsupportedIndexError>() ||
+                e.is::<DimensionsOverrideError>() ||
+                e.is::<InvalidIndexNameError>()
+            {
+                return IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string());
+            }
+            IndexifyAPIError::internal_error(e)
+        })?
         .into_iter()
         .collect();
 
@@ -1223,6 +1561,24 @@ async fn upload_file_inner(
             .duration_since(SystemTime::UNIX_EPOCH)
             .map_err(|_| IndexifyAPIError::new(StatusCode::INTERNAL_SERVER_ERROR, "invalid time"))?
             .as_secs();
+        if let Some(reason) = write_result.empty_text_reason(content_mime.as_ref()) {
+            info!("skipping content {}: {}", id, reason);
+            state
+                .data_manager
+                .delete_file(&write_result.url)
+                .await
+                .map_err(IndexifyAPIError::internal_error)?;
+            url.clear();
+            return Ok(Json(UploadFileResponse {
+                content_id: id,
+                skipped: Some(reason.to_string()),
+            }));
+        }
+        let hash = state
+            .data_manager
+            .content_hash(content_mime.as_ref(), &write_result)
+            .await
+            .map_err(IndexifyAPIError::internal_error)?;
         let size_bytes = write_result.size_bytes;
         let content_metadata = indexify_coordinator::ContentMetadata {
             id: id.clone(),
@@ -1236,7 +1592,7 @@ async fn upload_file_inner(
             labels,
             source: "".to_string(),
             size_bytes: write_result.size_bytes,
-            hash: write_result.hash,
+            hash,
             extraction_policy_ids: HashMap::new(),
             extraction_graph_names: vec![extraction_graph],
             extracted_metadata: json!({}).to_string(),
@@ -1256,7 +1612,10 @@ async fn upload_file_inner(
             .metrics
             .node_content_bytes_uploaded
             .add(size_bytes, &[]);
-        Ok(Json(UploadFileResponse { content_id: id }))
+        Ok(Json(UploadFileResponse {
+            content_id: id,
+            skipped: None,
+        }))
     } else {
         Err(IndexifyAPIError::new(
             StatusCode::BAD_REQUEST,
@@ -1715,6 +2074,122 @@ async fn list_indexes(
     Ok(Json(ListIndexesResponse { indexes }))
 }
 
+/// Get the stored configuration of an index, without querying its vector
+/// database
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/describe",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Configuration of the index", body = DescribeIndexResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to describe index")
+    ),
+)]
+#[axum::debug_handler]
+async fn describe_index(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+) -> Result<Json<DescribeIndexResponse>, IndexifyAPIError> {
+    let description = state
+        .data_manager
+        .describe_index(&namespace, &index)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(description))
+}
+
+/// Count the vectors of an index, with approximate the estimate of the vector
+/// database is returned where it has one
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/count",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+        ("approximate" = Option<bool>, Query, description = "Return the estimate of the vector database instead of counting the vectors, where it has one"),
+    ),
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Number of vectors of the index", body = CountIndexResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to count the vectors of the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn count_index(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Query(query): Query<CountIndexQuery>,
+) -> Result<Json<CountIndexResponse>, IndexifyAPIError> {
+    let count = state
+        .data_manager
+        .count_index(&namespace, &index, query.approximate)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(count))
+}
+
+/// Check the filters of a search on an index without searching. Filters which
+/// don't parse, or which can never match, are returned with their error.
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/validate_filter",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    request_body = ValidateFilterRequest,
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Whether the filters are valid, and the errors of invalid filters", body = ValidateFilterResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to read the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn validate_filter(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Json(body): Json<ValidateFilterRequest>,
+) -> Result<Json<ValidateFilterResponse>, IndexifyAPIError> {
+    let response = state
+        .data_manager
+        .validate_filters(&namespace, &index, &body.filters)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(response))
+}
+
+/// List the metadata fields of the vectors of an index, with the types of
+/// their values
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/fields",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Metadata fields of the index", body = ListIndexFieldsResponse),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to read the fields of the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn list_index_fields(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+) -> Result<Json<ListIndexFieldsResponse>, IndexifyAPIError> {
+    let fields = state
+        .data_manager
+        .list_index_fields(&namespace, &index)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(fields))
+}
+
 /// Search a vector index in a namespace
 #[utoipa::path(
     post,
@@ -1722,6 +2197,12 @@ async fn list_indexes(
     tag = "retrieval",
     responses(
         (status = 200, description = "Index search results", body = IndexSearchResponse),
+        (status = BAD_REQUEST, description = "Invalid boost factor, per_group, candidates, negative_weight, dedup_threshold or cursor"),
+        (status = PAYLOAD_TOO_LARGE, description = "The queries exceed the input limits of the api key"),
+        (status = BAD_GATEWAY, description = "The query embedding has NaN or infinite values"),
+        (status = GATEWAY_TIMEOUT, description = "The query embedding service timed out, the search can be retried"),
+        (status = TOO_MANY_REQUESTS, description = "Too many concurrent searches on the index"),
+        (status = SERVICE_UNAVAILABLE, description = "The query embedding service is unreachable, the search can be retried"),
         (status = INTERNAL_SERVER_ERROR, description = "Unable to search index")
     ),
 )]
@@ -1729,34 +2210,1061 @@ async fn list_indexes(
 async fn index_search(
     Path((namespace, index)): Path<(String, String)>,
     State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
     Json(query): Json<SearchRequest>,
 ) -> Result<Json<IndexSearchResponse>, IndexifyAPIError> {
-    let results = state
+    let (queries, options) = search_options(&state, &headers, query)?;
+    let search = search_index(&state, &namespace, &index, &queries, options).await?;
+    Ok(Json(search_response(search)))
+}
+
+/// Searches an index, logging the search when it is slower than the
+/// threshold of the slow query log
+async fn search_index(
+    state: &NamespaceEndpointState,
+    namespace: &str,
+    index: &str,
+    queries: &[String],
+    options: SearchOptions,
+) -> Result<SearchResults, IndexifyAPIError> {
+    let k = options.k as u64;
+    let filter = options.filter.clone();
+    let start = Instant::now();
+    let search = state
+        .data_manager
+        .search(namespace, index, queries, options)
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("search")
+            .index(namespace, index)
+            .k(k)
+            .filters(&filter)
+            .num_inputs(queries.len())
+    });
+    search.map_err(search_error)
+}
+
+/// Reads the queries and options of a search request, checking them against
+/// the input limits of the api key
+fn search_options(
+    state: &NamespaceEndpointState,
+    headers: &HeaderMap,
+    query: SearchRequest,
+) -> Result<(Vec<String>, SearchOptions), IndexifyAPIError> {
+    let query_hash = search_request_hash(&query);
+    let queries = query.query.into_texts();
+    check_input_limits(
+        state,
+        headers,
+        &queries
+            .iter()
+            .chain(&query.negative_queries)
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    )?;
+    if let Some(boost) = query.boosts.iter().find(|boost| boost.factor <= 0.0) {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "boost factor of field {} must be positive, got {}",
+                boost.field, boost.factor
+            ),
+        ));
+    }
+    if query.per_group == Some(0) {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            "per_group must be at least 1",
+        ));
+    }
+    let negative_weight = query.negative_weight.unwrap_or(DEFAULT_NEGATIVE_WEIGHT);
+    if !negative_weight.is_finite() || negative_weight <= 0.0 {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("negative_weight must be positive, got {}", negative_weight),
+        ));
+    }
+    if let Some(threshold) = query
+        .dedup_threshold
+        .filter(|threshold| !(0.0..=1.0).contains(threshold))
+    {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("dedup_threshold must be between 0 and 1, got {}", threshold),
+        ));
+    }
+    if query.cursor.is_some() && query.group_by.is_some() {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            "cursor can't be used with group_by",
+        ));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| SearchCursor::decode(cursor, &query_hash))
+        .transpose()
+        .map_err(|e| IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string()))?;
+    let k = query.k.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    if let Some(candidates) = query.candidates.filter(|candidates| *candidates < k) {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("candidates must be at least k ({}), got {}", k, candidates),
+        ));
+    }
+    let options = SearchOptions {
+        k: k as usize,
+        filter: query.filters,
+        include_content: query.include_content.unwrap_or(true),
+        include_source: query.include_source.unwrap_or(false),
+        exact: query.search_mode == SearchMode::Exact,
+        boosts: query.boosts,
+        group_by: query.group_by,
+        per_group: query.per_group.map(|per_group| per_group as usize),
+        candidates: query.candidates.map(|candidates| candidates as usize),
+        score_details: query.score_details.unwrap_or(false),
+        sort_by: query.sort_by,
+        negative_queries: query.negative_queries,
+        negative_weight,
+        dedup_threshold: query.dedup_threshold,
+        cache_policy: CachePolicy {
+            read: !query.no_cache,
+            write: !query.no_cache_write,
+        },
+        cursor,
+        query_hash,
+    };
+    Ok((queries, options))
+}
+
+/// Hash of a search request without its cursor, which every page of the
+/// search must send unchanged
+fn search_request_hash(query: &SearchRequest) -> String {
+    let mut request = serde_json::to_value(query).unwrap_or_default();
+    if let Some(request) = request.as_object_mut() {
+        request.remove("cursor");
+    }
+    format!("{:x}", Sha256::digest(request.to_string().as_bytes()))
+}
+
+/// Search a vector index for the content most similar to an indexed content
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/similar",
+    request_body = SimilarRequest,
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Content similar to the requested content, excluding it", body = IndexSearchResponse),
+        (status = NOT_FOUND, description = "Content not found in index"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to search index")
+    ),
+)]
+#[axum::debug_handler]
+async fn similar_by_id(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Json(request): Json<SimilarRequest>,
+) -> Result<Json<IndexSearchResponse>, IndexifyAPIError> {
+    let k = request.k.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let filter = request.filters.clone();
+    let start = Instant::now();
+    let search = state
         .data_manager
-        .search(
+        .similar_by_id(
             &namespace,
             &index,
-            &query.query,
-            query.k.unwrap_or(DEFAULT_SEARCH_LIMIT),
-            query.filters,
-            query.include_content.unwrap_or(true),
+            &request.id,
+            k,
+            request.filters,
+            request.include_content.unwrap_or(true),
         )
-        .await
-        .map_err(IndexifyAPIError::internal_error)?;
-    let document_fragments: Vec<DocumentFragment> = results
-        .iter()
-        .map(|text| DocumentFragment {
-            content_id: text.content_id.clone(),
-            mime_type: text.mime_type.clone(),
-            text: text.text.clone(),
-            labels: text.labels.clone(),
-            confidence_score: text.confidence_score,
-            root_content_metadata: text.root_content_metadata.clone().map(|r| r.into()),
-            content_metadata: text.content_metadata.clone().into(),
-        })
-        .collect();
-    Ok(Json(IndexSearchResponse {
-        results: document_fragments,
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("similar")
+            .index(&namespace, &index)
+            .k(k)
+            .filters(&filter)
+    });
+    let search = search.map_err(search_error)?.ok_or_else(|| {
+        IndexifyAPIError::not_found(&format!("content {} not found in index", request.id))
+    })?;
+    Ok(Json(search_response(search)))
+}
+
+/// Score a text against the content of an index without adding it. The text
+/// is embedded like content added to the index rather than like a search
+/// query, for example to find duplicates of a document before adding it.
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/score",
+    request_body = ScoreRequest,
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "The content of the index most similar to the text", body = IndexSearchResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "The text exceeds the input limits of the api key"),
+        (status = BAD_GATEWAY, description = "The embedding of the text has NaN or infinite values"),
+        (status = TOO_MANY_REQUESTS, description = "Too many concurrent searches on the index"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to embed the text or search the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn score_text(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(request): Json<ScoreRequest>,
+) -> Result<Json<IndexSearchResponse>, IndexifyAPIError> {
+    check_input_limits(&state, &headers, &[request.text.as_str()])?;
+    let k = request.k.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let filter = request.filters.clone();
+    let start = Instant::now();
+    let search = state
+        .data_manager
+        .score_text(
+            &namespace,
+            &index,
+            &request.text,
+            k,
+            request.filters,
+            request.include_content.unwrap_or(true),
+        )
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("score")
+            .index(&namespace, &index)
+            .k(k)
+            .filters(&filter)
+            .num_inputs(1)
+    });
+    let search = search.map_err(search_error)?;
+    Ok(Json(search_response(search)))
+}
+
+/// Search a vector index with a query embedding, skipping the embedding of a
+/// query
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/search_vector",
+    request_body = SearchVectorRequest,
+    tag = "retrieval",
+    responses(
+        (status = 200, description = "Index search results", body = IndexSearchResponse),
+        (status = BAD_REQUEST, description = "The vector doesn't have the dimension of the index or has NaN or infinite values"),
+        (status = TOO_MANY_REQUESTS, description = "Too many concurrent searches on the index"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to search index")
+    ),
+)]
+#[axum::debug_handler]
+async fn search_by_vector(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Json(request): Json<SearchVectorRequest>,
+) -> Result<Json<IndexSearchResponse>, IndexifyAPIError> {
+    let k = request.k.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let filter = request.filters.clone();
+    let start = Instant::now();
+    let search = state
+        .data_manager
+        .search_by_vector(
+            &namespace,
+            &index,
+            request.vector,
+            k,
+            request.filters,
+            request.include_content.unwrap_or(true),
+        )
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("search_vector")
+            .index(&namespace, &index)
+            .k(k)
+            .filters(&filter)
+    });
+    let search = search.map_err(|e| {
+        if e.is::<DimensionMismatchError>() || e.is::<NonFiniteEmbeddingError>() {
+            return IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string());
+        }
+        search_error(e)
+    })?;
+    Ok(Json(search_response(search)))
+}
+
+/// Add precomputed embeddings and their texts to a vector index, skipping the
+/// extractor of the index
+#[tracing::instrument(skip(headers, body))]
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/add_vectors",
+    request_body = AddVectorsRequest,
+    tag = "ingestion",
+    responses(
+        (status = 200, description = "Vectors added successfully", body = AddVectorsResponse),
+        (status = BAD_REQUEST, description = "A vector doesn't have the dimension of the index or has NaN or infinite values, or the missing index can't be created"),
+        (status = FORBIDDEN, description = "The index is read only"),
+        (status = PAYLOAD_TOO_LARGE, description = "The items exceed the input limits of the api key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to add vectors")
+    ),
+)]
+#[axum::debug_handler]
+async fn add_vectors(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(body): Json<AddVectorsRequest>,
+) -> Result<Json<AddVectorsResponse>, IndexifyAPIError> {
+    check_input_limits(
+        &state,
+        &headers,
+        &body
+            .items
+            .iter()
+            .map(|item| item.text.as_str())
+            .collect::<Vec<_>>(),
+    )?;
+    let num_items = body.items.len();
+    let start = Instant::now();
+    let content_ids = state
+        .data_manager
+        .add_vectors(&namespace, &index, body.items, body.create_if_missing)
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("add_vectors")
+            .index(&namespace, &index)
+            .num_inputs(num_items)
+    });
+    let content_ids = content_ids.map_err(|e| {
+        if e.is::<DimensionMismatchError>() ||
+            e.is::<NonFiniteEmbeddingError>() ||
+            e.is::<MissingIndexError>() ||
+            e.is::<InvalidContentIdError>()
+        {
+            return IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string());
+        }
+        if e.is::<ReadOnlyIndexError>() {
+            return IndexifyAPIError::new(StatusCode::FORBIDDEN, &e.to_string());
+        }
+        IndexifyAPIError::internal_error(e)
+    })?;
+    Ok(Json(AddVectorsResponse { content_ids }))
+}
+
+/// Update the metadata stored with a vector of an index without re-embedding
+/// the content
+#[tracing::instrument]
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/update_metadata",
+    request_body = UpdateIndexMetadataRequest,
+    tag = "indexify",
+    responses(
+        (status = 200, description = "Metadata updated successfully"),
+        (status = BAD_REQUEST, description = "The content id can't be stored in the vector database of the index"),
+        (status = FORBIDDEN, description = "The index is read only"),
+        (status = NOT_FOUND, description = "Content not found in index"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to update metadata")
+    ),
+)]
+#[axum::debug_handler]
+async fn update_index_metadata(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Json(body): Json<UpdateIndexMetadataRequest>,
+) -> Result<(), IndexifyAPIError> {
+    let updated = state
+        .data_manager
+        .update_index_metadata(&namespace, &index, &body.id, body.metadata, body.merge)
+        .await
+        .map_err(read_only_error)?;
+    if !updated {
+        return Err(IndexifyAPIError::not_found(&format!(
+            "content {} not found in index",
+            body.id
+        )));
+    }
+    Ok(())
+}
+
+fn search_error(e: anyhow::Error) -> IndexifyAPIError {
+    if e.is::<EmbeddingTimeoutError>() {
+        return IndexifyAPIError::new(StatusCode::GATEWAY_TIMEOUT, &e.to_string());
+    }
+    if e.is::<EmbeddingUnavailableError>() {
+        return IndexifyAPIError::new(StatusCode::SERVICE_UNAVAILABLE, &e.to_string());
+    }
+    if e.is::<SearchLimitExceeded>() {
+        return IndexifyAPIError::new(StatusCode::TOO_MANY_REQUESTS, &e.to_string());
+    }
+    if e.is::<NonFiniteEmbeddingError>() {
+        return IndexifyAPIError::new(StatusCode::BAD_GATEWAY, &e.to_string());
+    }
+    if e.is::<InvalidContentIdError>() {
+        return IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string());
+    }
+    IndexifyAPIError::internal_error(e)
+}
+
+fn read_only_error(e: anyhow::Error) -> IndexifyAPIError {
+    if e.is::<ReadOnlyIndexError>() {
+        return IndexifyAPIError::new(StatusCode::FORBIDDEN, &e.to_string());
+    }
+    if e.is::<InvalidContentIdError>() {
+        return IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string());
+    }
+    IndexifyAPIError::internal_error(e)
+}
+
+fn document_fragment(text: ScoredText) -> DocumentFragment {
+    DocumentFragment {
+        content_id: text.content_id,
+        mime_type: text.mime_type,
+        text: text.text,
+        labels: text.labels,
+        confidence_score: text.confidence_score,
+        source_text: text.source_text,
+        root_content_metadata: text.root_content_metadata.map(|r| r.into()),
+        content_metadata: text.content_metadata.into(),
+        raw_distance: text.score_details.map(|details| details.raw_distance),
+        normalized_score: text.score_details.map(|details| details.normalized_score),
+    }
+}
+
+fn search_response(search: SearchResults) -> IndexSearchResponse {
+    let groups = search
+        .groups
+        .into_iter()
+        .map(|group| SearchResultGroup {
+            value: group.value,
+            results: group.results.into_iter().map(document_fragment).collect(),
+        })
+        .collect();
+    IndexSearchResponse {
+        results: search.results.into_iter().map(document_fragment).collect(),
+        stats: SearchStats {
+            took_ms: search.took.as_millis() as u64,
+            candidates_considered: search.candidates_considered as u64,
+        },
+        groups,
+        next_cursor: search.next_cursor.map(|cursor| cursor.encode()),
+    }
+}
+
+/// Get the raw embedding stored for a content id in a vector index
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/embedding",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+        ("id" = String, Query, description = "ID of the content whose embedding is returned"),
+    ),
+    tag = "operations",
+    responses(
+        (status = 200, description = "Embedding stored for the content", body = GetEmbeddingResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = NOT_FOUND, description = "No embedding found for the content id")
+    ),
+)]
+#[axum::debug_handler]
+async fn get_embedding(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Query(query): Query<GetEmbeddingQuery>,
+) -> Result<Json<GetEmbeddingResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let embedding = state
+        .data_manager
+        .get_embedding(&namespace, &index, &query.id)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?
+        .ok_or_else(|| {
+            IndexifyAPIError::not_found(&format!("embedding for content {} not found", query.id))
+        })?;
+    Ok(Json(GetEmbeddingResponse {
+        content_id: query.id,
+        dim: embedding.len(),
+        embedding,
+    }))
+}
+
+/// Export every vector of an index as JSON lines, or as an Arrow IPC stream
+/// if requested with the Accept header
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/export",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+        ("include_embedding" = Option<bool>, Query, description = "Include the embedding of every vector, required to import the export. Requires the admin api key."),
+    ),
+    tag = "operations",
+    responses(
+        (status = 200, description = "One JSON object per line with the content id, metadata and embedding of a vector, or an Arrow IPC stream with one row per vector and its metadata serialized as JSON strings", content_type = ["application/x-ndjson", "application/vnd.apache.arrow.stream"]),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to export index")
+    ),
+)]
+#[axum::debug_handler]
+async fn export_index(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    Query(query): Query<ExportIndexQuery>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, IndexifyAPIError> {
+    // Raw embeddings are only returned to admins, like by get_embedding
+    if query.include_embedding {
+        authorize_admin(&state, &headers)?;
+    }
+    let arrow = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(ARROW_STREAM_CONTENT_TYPE));
+    let (content_type, body) = if arrow {
+        let stream = state
+            .data_manager
+            .export_index_arrow(&namespace, &index, query.include_embedding)
+            .await
+            .map_err(IndexifyAPIError::internal_error)?;
+        (ARROW_STREAM_CONTENT_TYPE, Body::from_stream(stream))
+    } else {
+        let stream = state
+            .data_manager
+            .export_index(&namespace, &index, query.include_embedding)
+            .await
+            .map_err(IndexifyAPIError::internal_error)?;
+        ("application/x-ndjson", Body::from_stream(stream))
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(body)
+        .map_err(|e| IndexifyAPIError::new(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
+}
+
+/// Import an index export, writing the exported embeddings into the index
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/import",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index to import into"),
+    ),
+    request_body(content_type = "application/x-ndjson", content = String, description = "Index export created with include_embedding"),
+    tag = "operations",
+    responses(
+        (status = 200, description = "Number of imported vectors", body = ImportIndexResponse),
+        (status = BAD_REQUEST, description = "Unable to import index"),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = FORBIDDEN, description = "The index is read only")
+    ),
+)]
+#[axum::debug_handler]
+async fn import_index(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<ImportIndexResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let stream = body.into_data_stream().map(|r| r.map_err(|e| anyhow!(e)));
+    let imported = state
+        .data_manager
+        .import_index(&namespace, &index, stream)
+        .await
+        .map_err(|e| {
+            if e.is::<ReadOnlyIndexError>() {
+                return IndexifyAPIError::new(StatusCode::FORBIDDEN, &e.to_string());
+            }
+            IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string())
+        })?;
+    Ok(Json(ImportIndexResponse { imported }))
+}
+
+/// Create a named snapshot of an index in blob storage
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/snapshots",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    request_body = CreateSnapshotRequest,
+    tag = "operations",
+    responses(
+        (status = 200, description = "Created snapshot", body = IndexSnapshot),
+        (status = BAD_REQUEST, description = "Invalid snapshot name"),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to create snapshot")
+    ),
+)]
+#[axum::debug_handler]
+async fn create_snapshot(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateSnapshotRequest>,
+) -> Result<Json<IndexSnapshot>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    // The name is part of the blob storage key of the snapshot
+    if payload.name.is_empty() ||
+        !payload
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            "snapshot name must be made of letters, digits, - and _",
+        ));
+    }
+    let snapshot = state
+        .data_manager
+        .create_snapshot(&namespace, &index, &payload.name)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(snapshot))
+}
+
+/// List the snapshots of an index, oldest first
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/snapshots",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    tag = "operations",
+    responses(
+        (status = 200, description = "Snapshots of the index", body = ListSnapshotsResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to list snapshots")
+    ),
+)]
+#[axum::debug_handler]
+async fn list_snapshots(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<ListSnapshotsResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let snapshots = state
+        .data_manager
+        .list_snapshots(&namespace, &index)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(ListSnapshotsResponse { snapshots }))
+}
+
+/// Restore an index to a snapshot, vectors which are not in the snapshot are
+/// removed
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/restore",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    request_body = RestoreSnapshotRequest,
+    tag = "operations",
+    responses(
+        (status = 200, description = "Number of restored and removed vectors", body = RestoreSnapshotResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = FORBIDDEN, description = "The index is read only"),
+        (status = NOT_FOUND, description = "Snapshot not found"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to restore snapshot")
+    ),
+)]
+#[axum::debug_handler]
+async fn restore_snapshot(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(payload): Json<RestoreSnapshotRequest>,
+) -> Result<Json<RestoreSnapshotResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let snapshot = state
+        .data_manager
+        .list_snapshots(&namespace, &index)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?
+        .into_iter()
+        .find(|snapshot| snapshot.name == payload.name)
+        .ok_or_else(|| {
+            IndexifyAPIError::not_found(&format!("snapshot {} not found", payload.name))
+        })?;
+    let response = state
+        .data_manager
+        .restore_snapshot(&namespace, &index, &snapshot)
+        .await
+        .map_err(read_only_error)?;
+    Ok(Json(response))
+}
+
+/// Make an index read only, or writable again. Writes addressed to a read only
+/// index are rejected until it is made writable.
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/read_only",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    request_body = IndexReadOnly,
+    tag = "operations",
+    responses(
+        (status = 200, description = "Read only state of the index", body = IndexReadOnly),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to update the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn set_index_read_only(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(payload): Json<IndexReadOnly>,
+) -> Result<Json<IndexReadOnly>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let read_only = state
+        .data_manager
+        .set_read_only(&namespace, &index, payload.read_only)
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(read_only))
+}
+
+/// Start a background job embedding the content of every vector of an index
+/// again, for example after the model of its extractor changed
+#[utoipa::path(
+    post,
+    path = "/namespaces/{namespace}/indexes/{index}/reembed",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+    ),
+    request_body = ReembedRequest,
+    tag = "operations",
+    responses(
+        (status = ACCEPTED, description = "Re-embed job started", body = ReembedJob),
+        (status = BAD_REQUEST, description = "The extractor can't embed the content of the index"),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = FORBIDDEN, description = "The index is read only"),
+        (status = CONFLICT, description = "A re-embed job of the index is already running"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to start re-embed job")
+    ),
+)]
+#[axum::debug_handler]
+async fn reembed_index(
+    Path((namespace, index)): Path<(String, String)>,
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(payload): Json<ReembedRequest>,
+) -> Result<(StatusCode, Json<ReembedJob>), IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    // Requests are spaced by the inverse of the rate
+    if let Some(requests_per_second) = payload.requests_per_second {
+        if !(requests_per_second.is_finite() && requests_per_second >= 0.001) {
+            return Err(IndexifyAPIError::new(
+                StatusCode::BAD_REQUEST,
+                "requests_per_second must be at least 0.001",
+            ));
+        }
+    }
+    let job = state
+        .data_manager
+        .start_reembed(&namespace, &index, payload)
+        .await
+        .map_err(|e| match e.downcast_ref::<ReembedError>() {
+            Some(ReembedError::InvalidExtractor { .. }) => {
+                IndexifyAPIError::new(StatusCode::BAD_REQUEST, &e.to_string())
+            }
+            Some(ReembedError::AlreadyRunning { .. }) => {
+                IndexifyAPIError::new(StatusCode::CONFLICT, &e.to_string())
+            }
+            None => read_only_error(e),
+        })?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Get the progress of a re-embed job of an index
+#[utoipa::path(
+    get,
+    path = "/namespaces/{namespace}/indexes/{index}/reembed/{job_id}",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the index"),
+        ("index" = String, Path, description = "Name of the index"),
+        ("job_id" = String, Path, description = "Id of the re-embed job"),
+    ),
+    tag = "operations",
+    responses(
+        (status = 200, description = "Progress of the re-embed job", body = ReembedJob),
+        (status = NOT_FOUND, description = "Re-embed job not found")
+    ),
+)]
+#[axum::debug_handler]
+async fn get_reembed_job(
+    Path((namespace, index, job_id)): Path<(String, String, String)>,
+    State(state): State<NamespaceEndpointState>,
+) -> Result<Json<ReembedJob>, IndexifyAPIError> {
+    state
+        .data_manager
+        .get_reembed_job(&namespace, &index, &job_id)
+        .map(Json)
+        .ok_or_else(|| IndexifyAPIError::not_found(&format!("re-embed job {} not found", job_id)))
+}
+
+/// Get the hits and misses of the query embedding cache of every model
+#[utoipa::path(
+    get,
+    path = "/admin/embedding_cache",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Query embedding cache statistics", body = EmbeddingCacheResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key")
+    ),
+)]
+#[axum::debug_handler]
+async fn embedding_cache_stats(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<EmbeddingCacheResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let stats = state
+        .data_manager
+        .vector_index_manager
+        .query_embedding_cache_stats();
+    Ok(Json(EmbeddingCacheResponse {
+        enabled: stats.is_some(),
+        models: stats.unwrap_or_default(),
+    }))
+}
+
+/// List the http services embedding search queries and probe whether they
+/// are healthy
+#[utoipa::path(
+    get,
+    path = "/admin/embedding_providers",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Query embedding services of every index", body = EmbeddingProvidersResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key")
+    ),
+)]
+#[axum::debug_handler]
+async fn embedding_providers(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<EmbeddingProvidersResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let providers = state
+        .data_manager
+        .vector_index_manager
+        .embedding_providers()
+        .await;
+    Ok(Json(EmbeddingProvidersResponse { providers }))
+}
+
+/// List the vector databases of the server with the distances, storage dtypes
+/// and search features they support
+#[utoipa::path(
+    get,
+    path = "/admin/vector_dbs",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Capabilities of the default vector database and of the vector databases of indexes", body = VectorDbsResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key")
+    ),
+)]
+#[axum::debug_handler]
+async fn vector_dbs(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<VectorDbsResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let vector_dbs = state
+        .data_manager
+        .vector_index_manager
+        .vector_db_capabilities();
+    Ok(Json(VectorDbsResponse { vector_dbs }))
+}
+
+/// Split a document into chunks and embed every chunk, returning the chunks
+/// with their position in the document and their embedding. Nothing is
+/// stored.
+#[tracing::instrument(skip(headers, body))]
+#[utoipa::path(
+    post,
+    path = "/embeddings/embed_document",
+    request_body = EmbedDocumentRequest,
+    tag = "operations",
+    responses(
+        (status = 200, description = "Chunks of the document with their embeddings", body = EmbedDocumentResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "The text exceeds the input limits of the api key"),
+        (status = GATEWAY_TIMEOUT, description = "An extractor didn't respond in time"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to split or embed the document")
+    ),
+)]
+#[axum::debug_handler]
+async fn embed_document(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(body): Json<EmbedDocumentRequest>,
+) -> Result<Json<EmbedDocumentResponse>, IndexifyAPIError> {
+    check_input_limits(&state, &headers, &[body.text.as_str()])?;
+    let start = Instant::now();
+    let chunks = state
+        .data_manager
+        .vector_index_manager
+        .embed_document(body)
+        .await;
+    state.slow_query_log.observe(start.elapsed(), || {
+        SlowQuery::new("embed_document").num_inputs(1)
+    });
+    let chunks = chunks.map_err(|e| {
+        if e.is::<EmbeddingTimeoutError>() {
+            return IndexifyAPIError::new(StatusCode::GATEWAY_TIMEOUT, &e.to_string());
+        }
+        IndexifyAPIError::internal_error(e)
+    })?;
+    Ok(Json(EmbedDocumentResponse { chunks }))
+}
+
+const RESET_CONFIRMATION: &str = "delete all indexes";
+
+/// Delete every extraction graph and index, for resetting test environments
+#[utoipa::path(
+    post,
+    path = "/admin/reset",
+    request_body = ResetRequest,
+    tag = "operations",
+    responses(
+        (status = 200, description = "Extraction graphs and indexes deleted", body = ResetResponse),
+        (status = BAD_REQUEST, description = "Missing confirmation"),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = FORBIDDEN, description = "Reset is disabled by the server config")
+    ),
+)]
+#[axum::debug_handler]
+async fn reset(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Json(request): Json<ResetRequest>,
+) -> Result<Json<ResetResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    if !state.config.allow_admin_reset {
+        return Err(IndexifyAPIError::new(
+            StatusCode::FORBIDDEN,
+            "reset is disabled, set allow_admin_reset to enable it",
+        ));
+    }
+    if request.confirm != RESET_CONFIRMATION {
+        return Err(IndexifyAPIError::new(
+            StatusCode::BAD_REQUEST,
+            &format!("confirm must be set to \"{}\"", RESET_CONFIRMATION),
+        ));
+    }
+    let deleted_indexes = state
+        .data_manager
+        .reset()
+        .await
+        .map_err(IndexifyAPIError::internal_error)?;
+    Ok(Json(ResetResponse { deleted_indexes }))
+}
+
+/// Remove the vectors of an index whose content no longer exists
+#[utoipa::path(
+    post,
+    path = "/admin/reconcile",
+    params(ReconcileQuery),
+    tag = "operations",
+    responses(
+        (status = 200, description = "Orphaned vectors and missing content of the index", body = ReconcileResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key"),
+        (status = FORBIDDEN, description = "The index is read only"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unable to reconcile the index")
+    ),
+)]
+#[axum::debug_handler]
+async fn reconcile_index(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+    Query(query): Query<ReconcileQuery>,
+) -> Result<Json<ReconcileResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    let response = state
+        .data_manager
+        .reconcile_index(&query.namespace, &query.index, query.dry_run)
+        .await
+        .map_err(read_only_error)?;
+    Ok(Json(response))
+}
+
+/// Get the version of the server and the backends it was built with
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Version of the server", body = VersionResponse),
+    ),
+)]
+#[axum::debug_handler]
+async fn version(State(state): State<NamespaceEndpointState>) -> Json<VersionResponse> {
+    let index_stores = [
+        IndexStoreKind::Lancedb,
+        IndexStoreKind::PgVector,
+        IndexStoreKind::Qdrant,
+    ];
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("VERGEN_GIT_SHA").to_string(),
+        git_branch: env!("VERGEN_GIT_BRANCH").to_string(),
+        index_store: state.config.index_config.index_store.to_string(),
+        index_stores: index_stores.iter().map(|kind| kind.to_string()).collect(),
+        query_embedding_providers: vec!["extractor".to_string(), "http".to_string()],
+    })
+}
+
+/// List the rate limits and available requests of every api key
+#[utoipa::path(
+    get,
+    path = "/admin/rate_limits",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Rate limits of the api keys and of the anonymous buckets of clients", body = RateLimitsResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key")
+    ),
+)]
+#[axum::debug_handler]
+async fn list_rate_limits(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<RateLimitsResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    Ok(Json(RateLimitsResponse {
+        limits: state.rate_limiter.status(),
+    }))
+}
+
+/// List the most recent searches, additions and embeddings slower than the
+/// threshold of the slow query log, the most recent first
+#[utoipa::path(
+    get,
+    path = "/admin/slow_queries",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Threshold of the slow query log and the most recent slow queries", body = SlowQueriesResponse),
+        (status = UNAUTHORIZED, description = "Missing or invalid admin api key")
+    ),
+)]
+#[axum::debug_handler]
+async fn list_slow_queries(
+    State(state): State<NamespaceEndpointState>,
+    headers: HeaderMap,
+) -> Result<Json<SlowQueriesResponse>, IndexifyAPIError> {
+    authorize_admin(&state, &headers)?;
+    Ok(Json(SlowQueriesResponse {
+        threshold_ms: state.slow_query_log.threshold_ms(),
+        queries: state.slow_query_log.recent(),
     }))
 }
 
@@ -0,0 +1,178 @@
+use std::{collections::VecDeque, fmt, sync::Mutex, time::Duration};
+
+use filter::LabelsFilter;
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{server_config::SlowQueryLogConfig, utils};
+
+/// A search, addition or embedding which took longer than the threshold of
+/// the slow query log
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SlowQuery {
+    pub operation: String,
+    pub namespace: Option<String>,
+    pub index: Option<String>,
+    pub k: Option<u64>,
+    pub filters: Vec<String>,
+    pub num_inputs: usize,
+    pub elapsed_ms: u64,
+    pub timestamp: u64,
+}
+
+impl SlowQuery {
+    pub fn new(operation: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            namespace: None,
+            index: None,
+            k: None,
+            filters: Vec::new(),
+            num_inputs: 0,
+            elapsed_ms: 0,
+            timestamp: 0,
+        }
+    }
+
+    pub fn index(mut self, namespace: &str, index: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self.index = Some(index.to_string());
+        self
+    }
+
+    pub fn k(mut self, k: u64) -> Self {
+        self.k = Some(k);
+        self
+    }
+
+    pub fn filters(mut self, filters: &LabelsFilter) -> Self {
+        self.filters = filters
+            .expressions()
+            .iter()
+            .map(|expression| expression.to_string())
+            .collect();
+        self
+    }
+
+    pub fn num_inputs(mut self, num_inputs: usize) -> Self {
+        self.num_inputs = num_inputs;
+        self
+    }
+}
+
+pub struct SlowQueryLog {
+    threshold: Option<Duration>,
+    buffer_size: usize,
+    queries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowQueryLog").finish()
+    }
+}
+
+impl SlowQueryLog {
+    pub fn new(config: SlowQueryLogConfig) -> Self {
+        Self {
+            threshold: config.threshold_ms.map(Duration::from_millis),
+            buffer_size: config.buffer_size,
+            queries: Mutex::new(VecDeque::with_capacity(config.buffer_size)),
+        }
+    }
+
+    pub fn threshold_ms(&self) -> Option<u64> {
+        self.threshold.map(|threshold| threshold.as_millis() as u64)
+    }
+
+    /// Logs the query built by `query` if it took longer than the threshold.
+    /// Queries are only built when they are slow.
+    pub fn observe(&self, elapsed: Duration, query: impl FnOnce() -> SlowQuery) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if elapsed <= threshold {
+            return;
+        }
+        let mut query = query();
+        query.elapsed_ms = elapsed.as_millis() as u64;
+        query.timestamp = utils::timestamp_secs();
+        warn!(
+            "slow {} on index {}/{} took {}ms, k: {:?}, filters: {:?}, inputs: {}",
+            query.operation,
+            query.namespace.as_deref().unwrap_or("-"),
+            query.index.as_deref().unwrap_or("-"),
+            query.elapsed_ms,
+            query.k,
+            query.filters,
+            query.num_inputs
+        );
+        if self.buffer_size == 0 {
+            return;
+        }
+        let mut queries = self.queries.lock().unwrap();
+        if queries.len() == self.buffer_size {
+            queries.pop_back();
+        }
+        queries.push_front(query);
+    }
+
+    /// Returns the most recent slow queries, the most recent first
+    pub fn recent(&self) -> Vec<SlowQuery> {
+        self.queries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow_query_log(buffer_size: usize) -> SlowQueryLog {
+        SlowQueryLog::new(SlowQueryLogConfig {
+            threshold_ms: Some(100),
+            buffer_size,
+        })
+    }
+
+    #[test]
+    fn test_threshold() {
+        let log = slow_query_log(10);
+        log.observe(Duration::from_millis(50), || {
+            panic!("fast queries must not be built")
+        });
+        log.observe(Duration::from_millis(150), || {
+            SlowQuery::new("search").index("default", "idx").k(5)
+        });
+        let queries = log.recent();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].operation, "search");
+        assert_eq!(queries[0].index.as_deref(), Some("idx"));
+        assert_eq!(queries[0].k, Some(5));
+        assert_eq!(queries[0].elapsed_ms, 150);
+
+        let disabled = SlowQueryLog::new(SlowQueryLogConfig::default());
+        disabled.observe(Duration::from_secs(60), || {
+            panic!("queries must not be built when the log is disabled")
+        });
+        assert!(disabled.recent().is_empty());
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let log = slow_query_log(2);
+        for operation in ["search", "add_vectors", "embed_document"] {
+            log.observe(Duration::from_secs(1), || SlowQuery::new(operation));
+        }
+        let operations: Vec<_> = log
+            .recent()
+            .into_iter()
+            .map(|query| query.operation)
+            .collect();
+        assert_eq!(operations, vec!["embed_document", "add_vectors"]);
+
+        let unbuffered = slow_query_log(0);
+        unbuffered.observe(Duration::from_secs(1), || SlowQuery::new("search"));
+        assert!(unbuffered.recent().is_empty());
+    }
+}
@@ -51,6 +51,7 @@ use store::{
     Response,
     TaskId,
 };
+use thiserror::Error;
 use tokio::{
     sync::{
         broadcast,
@@ -158,6 +159,18 @@ pub struct App {
     pub garbage_collector: Arc<GarbageCollector>,
     pub registry: Arc<prometheus::Registry>,
     pub metrics: Metrics,
+    // Serializes the existence check and the write of extraction graphs,
+    // so that concurrent creations of a graph don't overwrite each other
+    extraction_graph_creation: Mutex<()>,
+}
+
+/// Returned when an extraction graph is created with the name of an existing
+/// graph of the namespace
+#[derive(Debug, Error)]
+#[error("extraction graph {name} already exists in namespace {namespace}")]
+pub struct ExtractionGraphExistsError {
+    pub namespace: String,
+    pub name: String,
 }
 
 #[derive(Clone)]
@@ -291,6 +304,7 @@ impl App {
             garbage_collector,
             registry,
             metrics,
+            extraction_graph_creation: Mutex::new(()),
         });
 
         let raft_clone = app.forwardable_raft.clone();
@@ -768,16 +782,17 @@ impl App {
         structured_data_schema: StructuredDataSchema,
         indexes: Vec<internal_api::Index>,
     ) -> Result<()> {
+        let _guard = self.extraction_graph_creation.lock().await;
         let existing_graph = self.state_machine.get_from_cf::<ExtractionGraph, _>(
             StateMachineColumns::ExtractionGraphs,
             &extraction_graph.key(),
         )?;
         if existing_graph.is_some() {
-            return Err(anyhow!(
-                "extraction graph {} already exists in namespace {}",
-                extraction_graph.name,
-                extraction_graph.namespace
-            ));
+            return Err(ExtractionGraphExistsError {
+                namespace: extraction_graph.namespace,
+                name: extraction_graph.name,
+            }
+            .into());
         }
         let req = StateMachineUpdateRequest {
             payload: RequestPayload::CreateExtractionGraph {
@@ -1053,11 +1068,25 @@ impl App {
                         content: existing_content,
                         previous_parent: None,
                     });
-                } else {
+                    continue;
+                }
+                // Extracted content with deterministic ids gets the id it had under the
+                // previous version of its parent, it is matched by hash against the
+                // children of the previous version below.
+                let reparented = match &incoming_content.parent_id {
+                    Some(parent_id) => {
+                        self.state_machine
+                            .get_latest_version_of_content(&parent_id.id)?
+                            .map(|parent| parent.id) !=
+                            existing_content.parent_id
+                    }
+                    None => false,
+                };
+                if !reparented {
                     tracing::warn!("Content with the same id and hash has been received");
                     statuses.push(CreateContentStatus::Duplicate);
+                    continue;
                 }
-                continue;
             }
             let incoming_content_parent_id = match incoming_content.parent_id.clone() {
                 None => {
@@ -1596,6 +1625,7 @@ mod tests {
                 TaskId,
             },
             App,
+            ExtractionGraphExistsError,
         },
         test_util::db_utils::{
             create_test_extraction_graph,
@@ -1996,6 +2026,39 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_concurrent_extraction_graph_creation() -> Result<(), anyhow::Error> {
+        let cluster = RaftTestCluster::new(1, None).await?;
+        cluster.initialize(Duration::from_secs(2)).await?;
+        let node = cluster.get_raft_node(0)?;
+
+        //  Create the same graph from many tasks at once, only one of them
+        // creates it
+        let eg = create_test_extraction_graph("graph1", vec!["policy1"]);
+        let creations = (0..10).map(|_| {
+            let node = node.clone();
+            let eg = eg.clone();
+            tokio::spawn(async move {
+                node.create_extraction_graph(eg, StructuredDataSchema::default(), vec![])
+                    .await
+            })
+        });
+        let mut created = 0;
+        for result in futures::future::join_all(creations).await {
+            match result? {
+                Ok(()) => created += 1,
+                Err(e) => assert!(e.is::<ExtractionGraphExistsError>()),
+            }
+        }
+        assert_eq!(created, 1);
+
+        let graphs = node.get_extraction_graphs_by_name(&eg.namespace, &[&eg.name])?;
+        let graph = graphs.first().cloned().flatten().unwrap();
+        assert_eq!(graph.extraction_policies, eg.extraction_policies);
+        Ok(())
+    }
+
     #[tokio::test]
     #[tracing_test::traced_test]
     async fn test_create_and_read_namespaces() -> Result<(), anyhow::Error> {
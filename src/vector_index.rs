@@ -1,27 +1,491 @@
-use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use bytes::Bytes;
 use filter::LabelsFilter;
 use futures::future::join_all;
 use indexify_internal_api as internal_api;
 use indexify_proto::indexify_coordinator::Index;
 use internal_api::ExtractedEmbeddings;
-use tracing::info;
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::{info, warn};
 
 use crate::{
     api,
     blob_storage::ContentReader,
     coordinator_client::CoordinatorClient,
+    embedding_cache::{CachePolicy, EmbeddingCache, EmbeddingCacheStats},
     extractor_router::ExtractorRouter,
+    http_embedding::{EmbeddingTimeoutError, HttpEmbeddingGenerator},
     metrics::{vector_storage::Metrics, Timer},
-    vectordbs::{CreateIndexParams, IndexDistance, SearchResult, VectorChunk, VectorDBTS},
+    search_limiter::{InFlightSearch, SearchLimiter},
+    server_config::{HttpEmbeddingConfig, IndexNameCase, IndexSettings},
+    vectordbs::{
+        CreateIndexParams,
+        IndexDistance,
+        ScoreDetails,
+        ScrollPage,
+        SearchResult,
+        VectorChunk,
+        VectorDBTS,
+    },
 };
 
+/// Searches which rank or group their results after retrieval retrieve this
+/// many times the results they return as candidates, so that boosted results
+/// beyond the k nearest neighbors can be ranked into the results, and enough
+/// groups are found
+const RERANK_CANDIDATES_FACTOR: usize = 4;
+
+/// Searches with filters which vector databases can't evaluate retrieve this
+/// many times the results they return, and apply the filters to them
+const POST_FILTER_CANDIDATES_FACTOR: usize = 10;
+
+/// Returned when the dimensions_override of an index exceeds the dimension of
+/// the embeddings of its model, which can't be truncated to it.
+#[derive(Debug, Error)]
+#[error("index {index} has dimensions_override {dimensions_override}, which exceeds the dimension {dim} of its embeddings")]
+pub struct DimensionsOverrideError {
+    pub index: String,
+    pub dimensions_override: usize,
+    pub dim: usize,
+}
+
+/// Returned when an embedding doesn't have the dimension of the vectors of
+/// the index it is written to.
+#[derive(Debug, Error)]
+#[error("embedding for content {content_id} has dimension {dim}, but index {index} has dimension {index_dim}")]
+pub struct DimensionMismatchError {
+    pub content_id: String,
+    pub dim: usize,
+    pub index: String,
+    pub index_dim: u64,
+}
+
+/// Checks that every embedding of a batch has the dimension of the first one.
+/// Indexes without vectors accept any dimension, so a batch mixing dimensions
+/// would otherwise be written to them.
+fn check_batch_dim(index: &str, embeddings: &[ExtractedEmbeddings]) -> Result<()> {
+    let Some((first, rest)) = embeddings.split_first() else {
+        return Ok(());
+    };
+    match rest
+        .iter()
+        .find(|embedding| embedding.embedding.len() != first.embedding.len())
+    {
+        Some(embedding) => Err(DimensionMismatchError {
+            content_id: embedding.content_id.clone(),
+            dim: embedding.embedding.len(),
+            index: index.to_string(),
+            index_dim: first.embedding.len() as u64,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Returned when an embedding contains NaN or infinite values, which would
+/// corrupt the distances computed with it. input_index is the position of the
+/// input in its request or batch.
+#[derive(Debug, Error)]
+#[error("embedding of input {input_index} ({input}) has the non-finite value {value} at position {position}")]
+pub struct NonFiniteEmbeddingError {
+    pub input_index: usize,
+    pub input: String,
+    pub value: f32,
+    pub position: usize,
+}
+
+/// Checks that every value of the embedding is finite
+fn check_finite(
+    input_index: usize,
+    input: &str,
+    embedding: &[f32],
+) -> Result<(), NonFiniteEmbeddingError> {
+    match embedding.iter().position(|v| !v.is_finite()) {
+        Some(position) => Err(NonFiniteEmbeddingError {
+            input_index,
+            input: input.to_string(),
+            value: embedding[position],
+            position,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the name of the extractor output whose embeddings are written to
+/// the index, indexes are named <graph>.<policy>.<output>
+fn index_output(index: &Index) -> &str {
+    index.table_name.rsplit('.').next().unwrap_or_default()
+}
+
+/// Returns the model caching the query embeddings of an index embedding its
+/// queries with its extractor. Indexes of an extractor with different outputs
+/// or input params embed queries differently and don't share embeddings.
+fn extractor_model(index: &Index, input_params: Option<&serde_json::Value>) -> String {
+    match input_params {
+        Some(params) => format!("{}:{} {}", index.extractor, index_output(index), params),
+        None => format!("{}:{}", index.extractor, index_output(index)),
+    }
+}
+
+/// Returns the prefix of text with at most max_chars characters
+fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => &text[..idx],
+        None => text,
+    }
+}
+
+// Embeddings whose norm differs from 1 by less than this are considered
+// normalized.
+const NORM_TOLERANCE: f32 = 1e-3;
+
+/// Scales embedding to unit length. Returns whether the embedding had to be
+/// normalized, embeddings with a norm of zero can't be normalized.
+fn normalize(embedding: &mut [f32]) -> Result<bool> {
+    let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if !norm.is_normal() {
+        return Err(anyhow!("embedding with norm {} can't be normalized", norm));
+    }
+    if (norm - 1.0).abs() < NORM_TOLERANCE {
+        return Ok(false);
+    }
+    embedding.iter_mut().for_each(|v| *v /= norm);
+    Ok(true)
+}
+
+/// Truncates the embedding to dim dimensions, normalizing the truncated
+/// embedding if normalize_truncated is set. Embeddings with at most dim
+/// dimensions are unchanged.
+fn truncate_dimensions(
+    embedding: &mut Vec<f32>,
+    dim: usize,
+    normalize_truncated: bool,
+) -> Result<()> {
+    if embedding.len() <= dim {
+        return Ok(());
+    }
+    embedding.truncate(dim);
+    if normalize_truncated {
+        normalize(embedding)?;
+    }
+    Ok(())
+}
+
+fn cosine_similarity(lhs: &[f32], rhs: &[f32]) -> f32 {
+    let dot: f32 = lhs.iter().zip(rhs).map(|(a, b)| a * b).sum();
+    let norm = |embedding: &[f32]| embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    dot / (norm(lhs) * norm(rhs))
+}
+
+/// Drops the items whose embedding has a cosine similarity above threshold
+/// with the embedding of an earlier item which was kept. Items without
+/// embedding are kept.
+fn dedup_by_similarity<T>(
+    items: Vec<T>,
+    embedding: impl Fn(&T) -> Option<&[f32]>,
+    threshold: f32,
+) -> Vec<T> {
+    let mut kept: Vec<T> = Vec::new();
+    for item in items {
+        let duplicate = embedding(&item).is_some_and(|item_embedding| {
+            kept.iter()
+                .filter_map(&embedding)
+                .any(|kept_embedding| cosine_similarity(item_embedding, kept_embedding) > threshold)
+        });
+        if !duplicate {
+            kept.push(item);
+        }
+    }
+    kept
+}
+
+/// Maximum number of results before the page a search cursor continues at,
+/// since every page retrieves the results of the previous pages again
+pub const MAX_CURSOR_OFFSET: usize = 10_000;
+
+/// Position of a page of search results in the ranked results of its query,
+/// from which the next page continues
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    /// Number of results ranked before the next page
+    pub offset: usize,
+    /// Content id of the last result of the page. The other results of the
+    /// previous pages are not recorded.
+    pub content_id: String,
+    /// Hash of the search request the cursor was returned for
+    #[serde(default)]
+    pub query_hash: String,
+}
+
+impl SearchCursor {
+    /// Encodes the cursor as an opaque token for the search response
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Decodes a cursor sent with the search request whose hash is
+    /// query_hash. Cursors of other requests, and cursors deeper than
+    /// MAX_CURSOR_OFFSET results, are rejected.
+    pub fn decode(token: &str, query_hash: &str) -> Result<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|e| anyhow!("invalid search cursor: {}", e))?;
+        let cursor: Self =
+            serde_json::from_slice(&bytes).map_err(|e| anyhow!("invalid search cursor: {}", e))?;
+        if cursor.query_hash != query_hash {
+            return Err(anyhow!(
+                "invalid search cursor: it was returned for another search request"
+            ));
+        }
+        if cursor.offset > MAX_CURSOR_OFFSET {
+            return Err(anyhow!(
+                "invalid search cursor: pages can't start beyond {} results, got {}",
+                MAX_CURSOR_OFFSET,
+                cursor.offset
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+/// Position of the first item of the page following the cursor. The page
+/// starts after the last item of the previous page if it is still ranked
+/// among the items, so that items added or deleted before it don't shift
+/// the page, and at the offset of the cursor otherwise.
+fn cursor_start<T>(items: &[T], id: impl Fn(&T) -> &str, cursor: &SearchCursor) -> usize {
+    items
+        .iter()
+        .position(|item| id(item) == cursor.content_id)
+        .map(|position| position + 1)
+        .unwrap_or(cursor.offset.min(items.len()))
+}
+
+/// Returns the distinct inputs, with the position of their first occurrence,
+/// and for every input the index of its distinct input.
+fn dedup_inputs(inputs: &[String]) -> (Vec<(usize, &str)>, Vec<usize>) {
+    let mut unique_inputs = Vec::new();
+    let mut unique_positions = HashMap::new();
+    let positions = inputs
+        .iter()
+        .enumerate()
+        .map(|(input_index, input)| {
+            *unique_positions.entry(input.as_str()).or_insert_with(|| {
+                unique_inputs.push((input_index, input.as_str()));
+                unique_inputs.len() - 1
+            })
+        })
+        .collect();
+    (unique_inputs, positions)
+}
+
+/// Adds the default metadata of an index to the metadata of an embedding.
+/// Keys already present in the metadata of the embedding are kept.
+fn apply_default_metadata(
+    metadata: &mut HashMap<String, serde_json::Value>,
+    default_metadata: &HashMap<String, serde_json::Value>,
+) {
+    for (key, value) in default_metadata {
+        metadata.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
+
+/// Multiplies the confidence score of every result by the factors of the
+/// boosts its metadata matches, and sorts the results by their new score.
+/// Distances and negative similarities are divided by the factors instead, so
+/// that boosted results move to the front as well.
+fn apply_boosts(results: &mut [SearchResult], boosts: &[api::SearchBoost], distances: bool) {
+    for result in results.iter_mut() {
+        for boost in boosts {
+            if result.metadata.get(&boost.field) == Some(&boost.value) {
+                if distances || result.confidence_score < 0.0 {
+                    result.confidence_score /= boost.factor;
+                } else {
+                    result.confidence_score *= boost.factor;
+                }
+            }
+        }
+    }
+    if distances {
+        results.sort_by(|a, b| a.confidence_score.total_cmp(&b.confidence_score));
+    } else {
+        results.sort_by(|a, b| b.confidence_score.total_cmp(&a.confidence_score));
+    }
+}
+
+/// Returns the value of field in the metadata of a search result, or in the
+/// labels of its content if the metadata doesn't have it, like the labels of
+/// the search results returned.
+fn result_value(
+    metadata: &HashMap<String, serde_json::Value>,
+    labels: &HashMap<String, serde_json::Value>,
+    field: &str,
+) -> serde_json::Value {
+    metadata
+        .get(field)
+        .or_else(|| labels.get(field))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Position of the type of a metadata value in the order of sorted results,
+/// values which can't be sorted by have none
+fn sort_rank(value: &serde_json::Value) -> Option<u8> {
+    match value {
+        serde_json::Value::Bool(_) => Some(0),
+        serde_json::Value::Number(_) => Some(1),
+        serde_json::Value::String(_) => Some(2),
+        _ => None,
+    }
+}
+
+fn compare_values(a: &serde_json::Value, b: &serde_json::Value) -> Ordering {
+    match (a, b) {
+        (serde_json::Value::Bool(a), serde_json::Value::Bool(b)) => a.cmp(b),
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (serde_json::Value::String(a), serde_json::Value::String(b)) => a.cmp(b),
+        _ => sort_rank(a).cmp(&sort_rank(b)),
+    }
+}
+
+/// Sorts items by the value of a metadata field in the given order. Items
+/// without the field, or with a null, list or object value, come last in
+/// either order. The sort is stable, so that items with equal values keep
+/// their order of relevance.
+fn sort_by_value<T>(
+    items: &mut [T],
+    value: impl Fn(&T) -> Option<&serde_json::Value>,
+    order: api::SortOrder,
+) {
+    items.sort_by(|a, b| {
+        let a = value(a).filter(|value| sort_rank(value).is_some());
+        let b = value(b).filter(|value| sort_rank(value).is_some());
+        match (a, b) {
+            (Some(a), Some(b)) => match order {
+                api::SortOrder::Asc => compare_values(a, b),
+                api::SortOrder::Desc => compare_values(b, a),
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    });
+}
+
+/// Groups items by the value of key, keeping the first per_group items of the
+/// first max_groups groups. Items are expected in the order of their rank, so
+/// that groups are ordered by their best item.
+fn group_by_value<T>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> serde_json::Value,
+    max_groups: usize,
+    per_group: usize,
+) -> Vec<(serde_json::Value, Vec<T>)> {
+    let mut groups: Vec<(serde_json::Value, Vec<T>)> = Vec::new();
+    for item in items {
+        let value = key(&item);
+        let position = groups.iter().position(|(group, _)| *group == value);
+        match position {
+            Some(i) if groups[i].1.len() < per_group => groups[i].1.push(item),
+            Some(_) => {}
+            None if groups.len() < max_groups => groups.push((value, vec![item])),
+            None => {}
+        }
+    }
+    groups
+}
+
+/// Returns the mean of the embeddings of several phrasings of a query
+fn mean_pool(mut embeddings: Vec<Vec<f32>>) -> Result<Vec<f32>> {
+    if embeddings.len() <= 1 {
+        return embeddings.pop().ok_or(anyhow!("search query is empty"));
+    }
+    let dim = embeddings[0].len();
+    let mut mean = vec![0.0; dim];
+    for embedding in &embeddings {
+        if embedding.len() != dim {
+            return Err(anyhow!(
+                "query embeddings have different dimensions {} and {}",
+                dim,
+                embedding.len()
+            ));
+        }
+        for (sum, value) in mean.iter_mut().zip(embedding) {
+            *sum += value;
+        }
+    }
+    let count = embeddings.len() as f32;
+    mean.iter_mut().for_each(|v| *v /= count);
+    Ok(mean)
+}
+
+/// Moves the embedding of a query away from the embedding of its negative
+/// queries by subtracting it, scaled by weight
+fn subtract_embedding(mut embedding: Vec<f32>, negative: &[f32], weight: f32) -> Result<Vec<f32>> {
+    if embedding.len() != negative.len() {
+        return Err(anyhow!(
+            "query embedding has dimension {}, negative query embedding {}",
+            embedding.len(),
+            negative.len()
+        ));
+    }
+    for (value, negative) in embedding.iter_mut().zip(negative) {
+        *value -= weight * negative;
+    }
+    Ok(embedding)
+}
+
+/// Returns the character offsets of every chunk in the text it was split
+/// from. Every chunk is searched from the start of the previous chunk, so that
+/// overlapping chunks are found. Chunks which aren't found in the text, for
+/// example because the splitter changed their whitespace, have no offsets.
+fn chunk_offsets(text: &str, chunks: &[String]) -> Vec<Option<(u64, u64)>> {
+    let mut cursor = 0;
+    let mut cursor_chars = 0;
+    chunks
+        .iter()
+        .map(|chunk| {
+            let start = cursor + text[cursor..].find(chunk.as_str())?;
+            let char_start = cursor_chars + text[cursor..start].chars().count() as u64;
+            let char_end = char_start + chunk.chars().count() as u64;
+            // The next chunk is searched after the first character of this one
+            let first_char = chunk.chars().next().map_or(0, char::len_utf8);
+            cursor = start + first_char;
+            cursor_chars = char_start + u64::from(first_char > 0);
+            Some((char_start, char_end))
+        })
+        .collect()
+}
+
 pub struct VectorIndexManager {
     vector_db: VectorDBTS,
+    index_vector_dbs: HashMap<String, VectorDBTS>,
     extractor_router: ExtractorRouter,
     content_reader: Arc<ContentReader>,
+    index_settings: HashMap<String, IndexSettings>,
+    index_dims: RwLock<HashMap<String, u64>>,
+    query_embedders: HashMap<String, HttpEmbeddingGenerator>,
+    // Embedders tried in order when the query embedder of an index fails
+    query_embedding_fallbacks: HashMap<String, Vec<HttpEmbeddingGenerator>>,
+    query_embedding_cache: Option<EmbeddingCache>,
+    search_limiters: HashMap<String, SearchLimiter>,
+    // Indexes for which a non unit embedding has been logged
+    unnormalized_indexes: Mutex<HashSet<String>>,
     metrics: Metrics,
 }
 
@@ -37,48 +501,500 @@ pub struct ScoredText {
     pub mime_type: String,
     pub labels: HashMap<String, serde_json::Value>,
     pub confidence_score: f32,
+    /// Text of the root content the fragment was extracted from
+    pub source_text: Option<String>,
     pub root_content_metadata: Option<internal_api::ContentMetadata>,
     pub content_metadata: internal_api::ContentMetadata,
+    /// Distance and normalized score of the result, if requested
+    pub score_details: Option<ScoreDetails>,
+}
+
+/// Options of the search of an index
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub k: usize,
+    pub filter: LabelsFilter,
+    pub include_content: bool,
+    /// Include the text of the root content of every result
+    pub include_source: bool,
+    /// Compare the query with every vector of the index instead of searching
+    /// its approximate nearest neighbor index
+    pub exact: bool,
+    pub boosts: Vec<api::SearchBoost>,
+    /// Metadata field to group the results by, k is then the number of groups
+    pub group_by: Option<String>,
+    /// Number of results per group, 1 if not set
+    pub per_group: Option<usize>,
+    /// Number of nearest neighbors retrieved before boosts and grouping are
+    /// applied, a multiple of k is retrieved with them if not set
+    pub candidates: Option<usize>,
+    /// Add the distance and normalized score to every result
+    pub score_details: bool,
+    /// Sort the results by a metadata field after they are truncated to k
+    pub sort_by: Option<api::SearchSort>,
+    /// Queries whose mean embedding is subtracted from the query embedding,
+    /// scaled by negative_weight
+    pub negative_queries: Vec<String>,
+    pub negative_weight: f32,
+    /// Drop candidates whose embedding has a cosine similarity above this
+    /// with a better result
+    pub dedup_threshold: Option<f32>,
+    /// Whether the query embeddings are looked up in and added to the query
+    /// embedding cache
+    pub cache_policy: CachePolicy,
+    /// Continue after the page of results the cursor was returned with. The
+    /// query is searched again with the results of the previous pages as
+    /// additional candidates, which are skipped.
+    pub cursor: Option<SearchCursor>,
+    /// Hash of the search request, which the cursor of the next page is
+    /// bound to
+    pub query_hash: String,
+}
+
+/// Results of a search whose group_by field has the same value
+pub struct ScoredTextGroup {
+    pub value: serde_json::Value,
+    pub results: Vec<ScoredText>,
+}
+
+/// Results of a search along with the statistics of the search
+pub struct SearchResults {
+    pub results: Vec<ScoredText>,
+    /// Number of results returned by the vector database, before results
+    /// without content are dropped and results are truncated to k
+    pub candidates_considered: usize,
+    /// Results grouped by the group_by field of the search, in which case
+    /// results is empty
+    pub groups: Vec<ScoredTextGroup>,
+    pub took: Duration,
+    /// Cursor of the next page, if the search returned a full page of
+    /// results without group_by
+    pub next_cursor: Option<SearchCursor>,
 }
 
 impl VectorIndexManager {
-    pub fn new(coordinator_client: Arc<CoordinatorClient>, vector_db: VectorDBTS) -> Result<Self> {
+    /// Creates the manager of the vector indexes. Indexes are stored in
+    /// vector_db, except the indexes in index_vector_dbs whose settings select
+    /// another index store.
+    pub fn new(
+        coordinator_client: Arc<CoordinatorClient>,
+        vector_db: VectorDBTS,
+        index_vector_dbs: HashMap<String, VectorDBTS>,
+    ) -> Result<Self> {
         let extractor_router = ExtractorRouter::new(coordinator_client.clone())?;
         let content_reader = Arc::new(ContentReader::new(coordinator_client.config.clone()));
+        let index_config = &coordinator_client.config.index_config;
+        let index_settings = index_config.indexes.clone();
+        let mut query_embedders = HashMap::new();
+        let mut query_embedding_fallbacks = HashMap::new();
+        let mut search_limiters = HashMap::new();
+        for (index_name, settings) in &index_settings {
+            settings.validate(index_name, &index_config.index_store)?;
+            match &settings.index_store {
+                Some(kind) if *kind != index_config.index_store => {
+                    if !index_vector_dbs.contains_key(index_name) {
+                        return Err(anyhow!(
+                            "index {} uses index store {}, which is not created",
+                            index_name,
+                            kind
+                        ));
+                    }
+                }
+                _ => {}
+            }
+            if let Some(max_concurrent) = settings.max_concurrent_searches {
+                search_limiters.insert(
+                    index_name.clone(),
+                    SearchLimiter::new(index_name, max_concurrent, settings.max_queued_searches),
+                );
+            }
+            let http_embedder = |config: &HttpEmbeddingConfig| {
+                let mut config = config.clone();
+                if let Some(timeout) = coordinator_client.config.model_timeouts.get(&config.model) {
+                    config.read_timeout_secs = *timeout;
+                }
+                HttpEmbeddingGenerator::new(config)
+            };
+            if let Some(config) = &settings.query_embedding {
+                query_embedders.insert(index_name.clone(), http_embedder(config)?);
+            }
+            if !settings.query_embedding_fallbacks.is_empty() {
+                let fallbacks = settings
+                    .query_embedding_fallbacks
+                    .iter()
+                    .map(http_embedder)
+                    .collect::<Result<Vec<_>>>()?;
+                query_embedding_fallbacks.insert(index_name.clone(), fallbacks);
+            }
+        }
+        if index_config.index_name_case == IndexNameCase::Sensitive {
+            for vector_db in index_vector_dbs.values().chain([&vector_db]) {
+                if !vector_db.capabilities().case_sensitive_names {
+                    return Err(anyhow!(
+                        "index_name_case is sensitive, but {} doesn't store index names differing only by case as distinct tables",
+                        vector_db.name()
+                    ));
+                }
+            }
+        }
+        let query_embedding_cache = match index_config.query_embedding_cache_size {
+            0 => None,
+            max_entries => Some(EmbeddingCache::new(max_entries)),
+        };
+        let metrics = Metrics::new();
+        for vector_db in index_vector_dbs.values().chain([&vector_db]) {
+            vector_db.register_metrics(&metrics);
+        }
         Ok(Self {
             vector_db,
+            index_vector_dbs,
             extractor_router,
             content_reader,
-            metrics: Metrics::new(),
+            index_settings,
+            index_dims: RwLock::new(HashMap::new()),
+            query_embedders,
+            query_embedding_fallbacks,
+            query_embedding_cache,
+            search_limiters,
+            unnormalized_indexes: Mutex::new(HashSet::new()),
+            metrics,
         })
     }
 
-    pub async fn create_index(
+    /// Checks that the query embedding services required on startup are
+    /// reachable.
+    pub async fn check_query_embedders(&self) -> Result<()> {
+        let fallbacks = self
+            .query_embedding_fallbacks
+            .iter()
+            .flat_map(|(index_name, fallbacks)| fallbacks.iter().map(move |f| (index_name, f)));
+        for (index_name, query_embedder) in self.query_embedders.iter().chain(fallbacks) {
+            query_embedder.check_available().await.map_err(|e| {
+                anyhow!(
+                    "query embedding for index {} is unavailable: {}",
+                    index_name,
+                    e
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Starts the background health probes of the http services embedding
+    /// search queries.
+    pub fn start_query_embedder_probes(&self, shutdown_rx: watch::Receiver<bool>) {
+        let fallbacks = self.query_embedding_fallbacks.values().flatten();
+        for query_embedder in self.query_embedders.values().chain(fallbacks) {
+            query_embedder.start_health_probe(shutdown_rx.clone());
+        }
+    }
+
+    /// Probes every http service embedding search queries, returning them
+    /// ordered by index with the fallbacks of an index after its query
+    /// embedder.
+    pub async fn embedding_providers(&self) -> Vec<api::EmbeddingProvider> {
+        let mut embedders = Vec::new();
+        for (index_name, query_embedder) in &self.query_embedders {
+            embedders.push((index_name, None, query_embedder));
+        }
+        for (index_name, fallbacks) in &self.query_embedding_fallbacks {
+            for (position, fallback) in fallbacks.iter().enumerate() {
+                embedders.push((index_name, Some(position), fallback));
+            }
+        }
+        // The query embedder of an index, without position, sorts first
+        embedders.sort_by_key(|(index_name, position, _)| (*index_name, *position));
+        let probes = join_all(embedders.iter().map(|(_, _, embedder)| embedder.probe())).await;
+        let mut providers = Vec::new();
+        for ((index_name, position, embedder), probe) in embedders.into_iter().zip(probes) {
+            providers.push(api::EmbeddingProvider {
+                index: index_name.clone(),
+                role: match position {
+                    Some(_) => "query_embedding_fallback".to_string(),
+                    None => "query_embedding".to_string(),
+                },
+                fallback_position: position,
+                model: embedder.model().to_string(),
+                url: embedder.url().to_string(),
+                dim: embedder.dim(),
+                healthy: probe.is_ok(),
+                error: probe.err().map(|e| e.to_string()),
+            });
+        }
+        providers
+    }
+
+    /// Whether the queries of the index are embedded by its extractor rather
+    /// than by an http query embedder
+    pub fn embeds_queries_with_extractor(&self, index_name: &str) -> bool {
+        !self.query_embedders.contains_key(index_name)
+    }
+
+    /// Returns the vector database storing the index
+    fn vector_db(&self, index_name: &str) -> &VectorDBTS {
+        self.index_vector_dbs
+            .get(index_name)
+            .unwrap_or(&self.vector_db)
+    }
+
+    /// Returns the settings configured for the index, falling back to the
+    /// defaults for indexes without an entry in the server config.
+    pub fn index_settings(&self, index_name: &str) -> IndexSettings {
+        self.index_settings
+            .get(index_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn create_index_params(
         &self,
         index_name: &str,
-        schema: internal_api::EmbeddingSchema,
-    ) -> Result<String> {
-        let create_index_params = CreateIndexParams {
+        schema: &internal_api::EmbeddingSchema,
+    ) -> Result<CreateIndexParams> {
+        let settings = self.index_settings(index_name);
+        let vector_dim = match settings.dimensions_override {
+            Some(dim) if dim > schema.dim => {
+                return Err(DimensionsOverrideError {
+                    index: index_name.to_string(),
+                    dimensions_override: dim,
+                    dim: schema.dim,
+                }
+                .into());
+            }
+            Some(dim) => dim,
+            None => schema.dim,
+        };
+        Ok(CreateIndexParams {
             vectordb_index_name: index_name.to_string(),
-            vector_dim: schema.dim as u64,
+            vector_dim: vector_dim as u64,
             distance: IndexDistance::from_str(schema.distance.as_str())?,
             unique_params: None,
-        };
+            storage_dtype: settings.storage_dtype,
+        })
+    }
+
+    /// Checks that the vector database of the index supports its distance
+    /// and storage dtype, and that its dimensions_override doesn't exceed the
+    /// dimension of its embeddings. Indexes which already exist in the vector
+    /// database aren't checked, so that indexes created before their settings
+    /// were checked keep working.
+    pub async fn check_index(
+        &self,
+        index_name: &str,
+        schema: &internal_api::EmbeddingSchema,
+    ) -> Result<()> {
+        let vector_db = self.vector_db(index_name);
+        if vector_db.index_dim(index_name).await?.is_none() {
+            let create_index_params = self.create_index_params(index_name, schema)?;
+            vector_db
+                .capabilities()
+                .check(&vector_db.name(), &create_index_params)?;
+        }
+        Ok(())
+    }
+
+    /// Capabilities of the vector database of every index with its own
+    /// vector database, and of the default vector database without an index
+    pub fn vector_db_capabilities(&self) -> Vec<api::VectorDbCapabilities> {
+        let mut vector_dbs: Vec<(Option<&String>, &VectorDBTS)> = self
+            .index_vector_dbs
+            .iter()
+            .map(|(index_name, vector_db)| (Some(index_name), vector_db))
+            .collect();
+        vector_dbs.push((None, &self.vector_db));
+        vector_dbs.sort_by_key(|(index_name, _)| *index_name);
+        vector_dbs
+            .into_iter()
+            .map(|(index_name, vector_db)| {
+                let capabilities = vector_db.capabilities();
+                api::VectorDbCapabilities {
+                    index: index_name.cloned(),
+                    name: vector_db.name(),
+                    distances: capabilities
+                        .distances
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                    storage_dtypes: capabilities
+                        .storage_dtypes
+                        .iter()
+                        .map(|dtype| dtype.name().to_string())
+                        .collect(),
+                    exact_search: capabilities.exact_search,
+                    approximate_count: capabilities.approximate_count,
+                    case_sensitive_names: capabilities.case_sensitive_names,
+                }
+            })
+            .collect()
+    }
+
+    pub async fn create_index(
+        &self,
+        index_name: &str,
+        schema: internal_api::EmbeddingSchema,
+    ) -> Result<String> {
+        self.check_index(index_name, &schema).await?;
+        let create_index_params = self.create_index_params(index_name, &schema)?;
+        if let Some(existing_dim) = self.vector_db(index_name).index_dim(index_name).await? {
+            if existing_dim != create_index_params.vector_dim {
+                return Err(anyhow!(
+                    "index {} already exists in {} with dimension {}, but the embedding schema has dimension {}",
+                    index_name,
+                    self.vector_db(index_name).name(),
+                    existing_dim,
+                    create_index_params.vector_dim
+                ));
+            }
+        }
         info!("Creating index: {:?}", create_index_params);
-        self.vector_db.create_index(create_index_params).await?;
+        let vector_dim = create_index_params.vector_dim;
+        self.vector_db(index_name)
+            .create_index(create_index_params)
+            .await?;
+        self.index_dims
+            .write()
+            .unwrap()
+            .insert(index_name.to_string(), vector_dim);
         Ok(index_name.to_string())
     }
 
     pub async fn drop_index(&self, index_name: &str) -> Result<()> {
-        self.vector_db.drop_index(index_name).await
+        self.vector_db(index_name).drop_index(index_name).await?;
+        self.index_dims.write().unwrap().remove(index_name);
+        Ok(())
+    }
+
+    /// Returns the dimension of the vectors stored in the index. The dimension
+    /// is read from the vector database the first time an index is used.
+    async fn index_dim(&self, index_name: &str) -> Result<Option<u64>> {
+        if let Some(dim) = self.index_dims.read().unwrap().get(index_name) {
+            return Ok(Some(*dim));
+        }
+        let dim = self.vector_db(index_name).index_dim(index_name).await?;
+        if let Some(dim) = dim {
+            self.index_dims
+                .write()
+                .unwrap()
+                .insert(index_name.to_string(), dim);
+        }
+        Ok(dim)
+    }
+
+    /// Opens the index in its vector database and caches its dimension.
+    /// Returns the number of vectors of the index.
+    pub async fn preload(&self, index_name: &str) -> Result<u64> {
+        if self.index_dim(index_name).await?.is_none() {
+            return Err(anyhow!(
+                "index {} does not exist in {}",
+                index_name,
+                self.vector_db(index_name).name()
+            ));
+        }
+        self.num_vectors(index_name).await
+    }
+
+    pub async fn num_vectors(&self, index_name: &str) -> Result<u64> {
+        self.vector_db(index_name).num_vectors(index_name).await
+    }
+
+    /// Returns the number of vectors of the index and whether it is an
+    /// estimate. The estimate of the vector database is used if approximate
+    /// is set and the vector database has one, an exact count otherwise.
+    pub async fn count_vectors(&self, index_name: &str, approximate: bool) -> Result<(u64, bool)> {
+        let vector_db = self.vector_db(index_name);
+        if approximate {
+            if let Some(estimate) = vector_db.estimate_num_vectors(index_name).await? {
+                return Ok((estimate, true));
+            }
+        }
+        Ok((vector_db.num_vectors(index_name).await?, false))
+    }
+
+    /// Returns true if no vector was written to the index yet
+    async fn is_empty(&self, index_name: &str) -> Result<bool> {
+        if self.index_dim(index_name).await?.is_none() {
+            return Ok(true);
+        }
+        Ok(self.num_vectors(index_name).await? == 0)
+    }
+
+    /// Checks that the embedding has the dimension of the vectors of the
+    /// index. Indexes without vectors accept any dimension.
+    pub async fn check_dim(
+        &self,
+        vector_index_name: &str,
+        content_id: &str,
+        embedding: &[f32],
+    ) -> Result<()> {
+        let Some(index_dim) = self.index_dim(vector_index_name).await? else {
+            return Ok(());
+        };
+        if embedding.len() as u64 != index_dim {
+            return Err(DimensionMismatchError {
+                content_id: content_id.to_string(),
+                dim: embedding.len(),
+                index: vector_index_name.to_string(),
+                index_dim,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Truncates embeddings computed by the model of the index to its
+    /// dimensions_override. Vectors sent by clients are never truncated, they
+    /// must match the dimension of the index.
+    pub fn truncate_embeddings(
+        &self,
+        vector_index_name: &str,
+        embeddings: &mut [ExtractedEmbeddings],
+    ) -> Result<()> {
+        let settings = self.index_settings(vector_index_name);
+        let Some(dim) = settings.dimensions_override else {
+            return Ok(());
+        };
+        for embedding in embeddings {
+            truncate_dimensions(&mut embedding.embedding, dim, settings.normalize_truncated)
+                .map_err(|e| {
+                    anyhow!(
+                        "unable to truncate embedding for content {}: {}",
+                        embedding.content_id,
+                        e
+                    )
+                })?;
+        }
+        Ok(())
     }
 
     pub async fn add_embedding(
         &self,
         vector_index_name: &str,
-        embeddings: Vec<ExtractedEmbeddings>,
+        mut embeddings: Vec<ExtractedEmbeddings>,
     ) -> Result<()> {
         let _timer = Timer::start(&self.metrics.vector_upsert);
+        for (input_index, embedding) in embeddings.iter().enumerate() {
+            check_finite(
+                input_index,
+                &format!("content {}", embedding.content_id),
+                &embedding.embedding,
+            )?;
+        }
+        let settings = self.index_settings(vector_index_name);
+        if settings.normalize_inputs {
+            self.normalize_embeddings(vector_index_name, &mut embeddings)?;
+        }
+        for embedding in &mut embeddings {
+            apply_default_metadata(&mut embedding.metadata, &settings.default_metadata);
+        }
+        check_batch_dim(vector_index_name, &embeddings)?;
+        for embedding in &embeddings {
+            self.check_dim(
+                vector_index_name,
+                &embedding.content_id,
+                &embedding.embedding,
+            )
+            .await?;
+        }
         let mut vector_chunks = Vec::new();
         embeddings.iter().for_each(|embedding| {
             let vector_chunk = VectorChunk::new(
@@ -90,15 +1006,54 @@ impl VectorIndexManager {
             );
             vector_chunks.push(vector_chunk);
         });
-        self.vector_db
+        self.vector_db(vector_index_name)
             .add_embedding(vector_index_name, vector_chunks)
             .await?;
         Ok(())
     }
 
+    /// Counts a chunk which wasn't added to the index because it is too short
+    pub fn record_dropped_chunk(&self, vector_index_name: &str) {
+        self.metrics
+            .vector_chunks_dropped
+            .add(1, &[KeyValue::new("index", vector_index_name.to_string())]);
+    }
+
+    fn normalize_embeddings(
+        &self,
+        vector_index_name: &str,
+        embeddings: &mut [ExtractedEmbeddings],
+    ) -> Result<()> {
+        let mut normalized = false;
+        for embedding in embeddings.iter_mut() {
+            normalized |= normalize(&mut embedding.embedding).map_err(|e| {
+                anyhow!(
+                    "unable to normalize embedding for content {}: {}",
+                    embedding.content_id,
+                    e
+                )
+            })?;
+        }
+        if !normalized {
+            return Ok(());
+        }
+        let first_seen = self
+            .unnormalized_indexes
+            .lock()
+            .unwrap()
+            .insert(vector_index_name.to_string());
+        if first_seen {
+            warn!(
+                "index {} received embeddings which are not unit length, they are normalized before they are stored",
+                vector_index_name
+            );
+        }
+        Ok(())
+    }
+
     pub async fn remove_embedding(&self, vector_index_name: &str, content_id: &str) -> Result<()> {
         let _timer = Timer::start(&self.metrics.vector_delete);
-        self.vector_db
+        self.vector_db(vector_index_name)
             .remove_embedding(vector_index_name, content_id)
             .await?;
         Ok(())
@@ -109,7 +1064,20 @@ impl VectorIndexManager {
         index: &str,
         content_ids: Vec<String>,
     ) -> Result<Vec<VectorChunk>> {
-        self.vector_db.get_points(index, content_ids).await
+        self.vector_db(index).get_points(index, content_ids).await
+    }
+
+    pub async fn get_vector(&self, index: &str, content_id: &str) -> Result<Option<Vec<f32>>> {
+        self.vector_db(index).get_vector(index, content_id).await
+    }
+
+    pub async fn scroll(
+        &self,
+        index: &str,
+        cursor: Option<String>,
+        limit: u64,
+    ) -> Result<ScrollPage> {
+        self.vector_db(index).scroll(index, cursor, limit).await
     }
 
     pub async fn update_metadata(
@@ -117,40 +1085,639 @@ impl VectorIndexManager {
         index: &str,
         content_id: String,
         metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
     ) -> Result<()> {
         let _timer = Timer::start(&self.metrics.vector_metadata_update);
-        self.vector_db
-            .update_metadata(index, content_id, metadata)
+        self.vector_db(index)
+            .update_metadata(index, content_id, metadata, merge)
             .await
     }
 
+    /// Replaces the metadata stored with the vector of content_id, or merges
+    /// metadata into it, without changing the vector. Returns false if the
+    /// index has no vector for content_id.
+    pub async fn update_content_metadata(
+        &self,
+        index: &str,
+        content_id: &str,
+        metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
+    ) -> Result<bool> {
+        let exists = !self
+            .vector_db(index)
+            .get_points(index, vec![content_id.to_string()])
+            .await?
+            .is_empty();
+        if !exists {
+            return Ok(false);
+        }
+        // The vector database updates the metadata in place, so concurrent
+        // updates of different keys with merge don't overwrite each other
+        self.update_metadata(index, content_id.to_string(), metadata, merge)
+            .await?;
+        Ok(true)
+    }
+
+    /// Embeds the content with the embedding output of an extractor
+    pub async fn extract_embedding(
+        &self,
+        extractor: &str,
+        output: &str,
+        input_params: Option<serde_json::Value>,
+        content: api::Content,
+    ) -> Result<Vec<f32>> {
+        let feature = self
+            .extractor_router
+            .extract_content(extractor, content, input_params)
+            .await?
+            .features
+            .into_iter()
+            .find(|feature| {
+                feature.name == output &&
+                    matches!(feature.feature_type, internal_api::FeatureType::Embedding)
+            })
+            .ok_or(anyhow!(
+                "extractor {} returned no embedding {}",
+                extractor,
+                output
+            ))?;
+        let embedding: internal_api::Embedding = serde_json::from_value(feature.data)?;
+        Ok(embedding.values)
+    }
+
+    /// Embeds the content of a vector of the index again with the output of
+    /// an extractor and replaces the vector, or adds it if the index has no
+    /// vector of the content, keeping its metadata. Returns false if the
+    /// content of the vector can't be read.
+    pub async fn reembed(
+        &self,
+        index_name: &str,
+        extractor: &str,
+        output: &str,
+        input_params: Option<serde_json::Value>,
+        chunk: VectorChunk,
+    ) -> Result<bool> {
+        let bytes = match self
+            .content_reader
+            .bytes(&chunk.content_metadata.storage_url)
+            .await
+        {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!(
+                    "unable to read content {} to re-embed it in index {}: {}",
+                    chunk.content_id, index_name, e
+                );
+                return Ok(false);
+            }
+        };
+        let content = api::Content {
+            content_type: chunk.content_metadata.content_type.clone(),
+            bytes: bytes.to_vec(),
+            features: vec![],
+            labels: HashMap::new(),
+        };
+        let embedding = self
+            .extract_embedding(extractor, output, input_params, content)
+            .await
+            .map_err(|e| anyhow!("unable to re-embed content {}: {}", chunk.content_id, e))?;
+        let mut embeddings = vec![ExtractedEmbeddings {
+            content_id: chunk.content_id,
+            embedding,
+            metadata: chunk.metadata,
+            root_content_metadata: chunk.root_content_metadata,
+            content_metadata: chunk.content_metadata,
+        }];
+        self.truncate_embeddings(index_name, &mut embeddings)?;
+        self.add_embedding(index_name, embeddings).await?;
+        Ok(true)
+    }
+
+    /// Splits the text with the splitter extractor and embeds every chunk with
+    /// the embedding extractor, without storing anything.
+    pub async fn embed_document(
+        &self,
+        request: api::EmbedDocumentRequest,
+    ) -> Result<Vec<api::EmbeddedChunk>> {
+        let content = api::Content {
+            content_type: mime::TEXT_PLAIN.to_string(),
+            bytes: request.text.as_bytes().to_vec(),
+            features: vec![],
+            labels: HashMap::new(),
+        };
+        let chunks = self
+            .extractor_router
+            .extract_content(&request.splitter, content, request.splitter_params)
+            .await?
+            .content
+            .into_iter()
+            .map(|chunk| {
+                String::from_utf8(chunk.bytes).map_err(|e| {
+                    anyhow!(
+                        "splitter {} returned a chunk which is not text: {}",
+                        request.splitter,
+                        e
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let offsets = chunk_offsets(&request.text, &chunks);
+        let mut embedded_chunks = Vec::with_capacity(chunks.len());
+        for (chunk_text, offsets) in chunks.into_iter().zip(offsets) {
+            let content = api::Content {
+                content_type: mime::TEXT_PLAIN.to_string(),
+                bytes: chunk_text.as_bytes().to_vec(),
+                features: vec![],
+                labels: HashMap::new(),
+            };
+            let feature = self
+                .extractor_router
+                .extract_content(
+                    &request.extractor,
+                    content,
+                    request.extractor_params.clone(),
+                )
+                .await?
+                .features
+                .into_iter()
+                .find(|feature| {
+                    matches!(feature.feature_type, internal_api::FeatureType::Embedding)
+                })
+                .ok_or(anyhow!(
+                    "extractor {} returned no embedding for a chunk",
+                    request.extractor
+                ))?;
+            let embedding: internal_api::Embedding = serde_json::from_value(feature.data)?;
+            embedded_chunks.push(api::EmbeddedChunk {
+                chunk_text,
+                char_start: offsets.map(|(start, _)| start),
+                char_end: offsets.map(|(_, end)| end),
+                embedding: embedding.values,
+            });
+        }
+        Ok(embedded_chunks)
+    }
+
+    /// Searches the index with the embedding of the query. Several phrasings
+    /// of a query are embedded separately and searched with the mean of their
+    /// embeddings. input_params are the input params of the extraction policy
+    /// of the index, which embeds queries without a query embedder.
     pub async fn search(
         &self,
         index: Index,
+        input_params: Option<serde_json::Value>,
+        queries: &[String],
+        options: SearchOptions,
+    ) -> Result<SearchResults> {
+        let start = Instant::now();
+        let _in_flight = self.start_search(&index.table_name).await?;
+        let _timer = Timer::start(&self.metrics.vector_search);
+        // Some vector databases fail to search tables without vectors, and
+        // there is no need to embed the query
+        if self.is_empty(&index.table_name).await? {
+            return Ok(SearchResults {
+                results: Vec::new(),
+                candidates_considered: 0,
+                groups: Vec::new(),
+                took: start.elapsed(),
+                next_cursor: None,
+            });
+        }
+        let embeddings = if self.index_settings(&index.table_name).dedup_queries {
+            let (unique_queries, positions) = dedup_inputs(queries);
+            let mut unique_embeddings = Vec::new();
+            for (query_index, query) in unique_queries {
+                unique_embeddings.push(
+                    self.query_embedding(
+                        &index,
+                        input_params.as_ref(),
+                        query_index,
+                        query,
+                        options.cache_policy,
+                    )
+                    .await?,
+                );
+            }
+            positions
+                .into_iter()
+                .map(|position| unique_embeddings[position].clone())
+                .collect()
+        } else {
+            let mut embeddings = Vec::new();
+            for (query_index, query) in queries.iter().enumerate() {
+                embeddings.push(
+                    self.query_embedding(
+                        &index,
+                        input_params.as_ref(),
+                        query_index,
+                        query,
+                        options.cache_policy,
+                    )
+                    .await?,
+                );
+            }
+            embeddings
+        };
+        let mut embedding = mean_pool(embeddings)?;
+        if !options.negative_queries.is_empty() {
+            let mut negative_embeddings = Vec::new();
+            for (i, query) in options.negative_queries.iter().enumerate() {
+                let query_index = queries.len() + i;
+                negative_embeddings.push(
+                    self.query_embedding(
+                        &index,
+                        input_params.as_ref(),
+                        query_index,
+                        query,
+                        options.cache_policy,
+                    )
+                    .await?,
+                );
+            }
+            embedding = subtract_embedding(
+                embedding,
+                &mean_pool(negative_embeddings)?,
+                options.negative_weight,
+            )?;
+        }
+        let per_group = options.per_group.unwrap_or(1);
+        let num_candidates = if let Some(candidates) = options.candidates {
+            candidates
+        } else if options.group_by.is_some() {
+            options.k * per_group * RERANK_CANDIDATES_FACTOR
+        } else if !options.boosts.is_empty() || options.dedup_threshold.is_some() {
+            options.k * RERANK_CANDIDATES_FACTOR
+        } else {
+            options.k
+        };
+        // The results of the previous pages are retrieved again to be skipped
+        let offset = options.cursor.as_ref().map_or(0, |cursor| cursor.offset);
+        let mut search_result = self
+            .search_vector_db(
+                index.table_name.clone(),
+                embedding,
+                num_candidates.saturating_add(offset) as u64,
+                options.filter,
+                options.exact,
+            )
+            .await?;
+        let candidates_considered = search_result.len();
+        let distance = || -> Result<IndexDistance> {
+            let schema: internal_api::EmbeddingSchema = serde_json::from_str(&index.schema)
+                .map_err(|e| anyhow!("unable to read schema of index {}: {}", index.name, e))?;
+            Ok(IndexDistance::from_str(&schema.distance)?)
+        };
+        if !options.boosts.is_empty() {
+            let distances = self
+                .vector_db(&index.table_name)
+                .scores_are_distances_for(&distance()?);
+            apply_boosts(&mut search_result, &options.boosts, distances);
+        }
+        if let Some(threshold) = options.dedup_threshold {
+            search_result = self
+                .dedup_results(&index.table_name, search_result, threshold)
+                .await?;
+        }
+        let mut page_start = 0;
+        if let Some(cursor) = &options.cursor {
+            page_start = cursor_start(&search_result, |result| &result.content_id, cursor);
+            search_result.drain(..page_start);
+        }
+        match &options.group_by {
+            Some(field) => {
+                let groups = group_by_value(
+                    search_result,
+                    |result| result_value(&result.metadata, &result.content_metadata.labels, field),
+                    options.k,
+                    per_group,
+                );
+                search_result = groups.into_iter().flat_map(|(_, group)| group).collect();
+            }
+            None => search_result.truncate(options.k),
+        }
+        let next_cursor = match search_result.last() {
+            Some(last) if options.group_by.is_none() && search_result.len() == options.k => {
+                Some(SearchCursor {
+                    offset: page_start + search_result.len(),
+                    content_id: last.content_id.clone(),
+                    query_hash: options.query_hash.clone(),
+                })
+            }
+            _ => None,
+        };
+        let mut results = self
+            .scored_texts(
+                search_result,
+                options.include_content,
+                options.include_source,
+            )
+            .await?;
+        if options.score_details {
+            let distance = distance()?;
+            let vector_db = self.vector_db(&index.table_name);
+            for result in &mut results {
+                result.score_details =
+                    Some(vector_db.score_details(result.confidence_score, &distance));
+            }
+        }
+        let mut groups = Vec::new();
+        if let Some(field) = &options.group_by {
+            // The results are already selected and ordered by group
+            groups = group_by_value(
+                std::mem::take(&mut results),
+                |text| text.labels.get(field).cloned().unwrap_or_default(),
+                usize::MAX,
+                usize::MAX,
+            )
+            .into_iter()
+            .map(|(value, results)| ScoredTextGroup { value, results })
+            .collect();
+        }
+        if let Some(sort_by) = &options.sort_by {
+            let field = &sort_by.field;
+            sort_by_value(&mut results, |text| text.labels.get(field), sort_by.order);
+            for group in &mut groups {
+                sort_by_value(
+                    &mut group.results,
+                    |text| text.labels.get(field),
+                    sort_by.order,
+                );
+            }
+        }
+        Ok(SearchResults {
+            results,
+            candidates_considered,
+            groups,
+            took: start.elapsed(),
+            next_cursor,
+        })
+    }
+
+    /// Drops the search results which are near duplicates of a better result,
+    /// comparing the embeddings stored in the index
+    async fn dedup_results(
+        &self,
+        index_name: &str,
+        search_result: Vec<SearchResult>,
+        threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        let content_ids = search_result
+            .iter()
+            .map(|result| result.content_id.clone())
+            .collect();
+        let embeddings: HashMap<String, Vec<f32>> = self
+            .get_points(index_name, content_ids)
+            .await?
+            .into_iter()
+            .map(|chunk| (chunk.content_id, chunk.embedding))
+            .collect();
+        Ok(dedup_by_similarity(
+            search_result,
+            |result| embeddings.get(&result.content_id).map(Vec::as_slice),
+            threshold,
+        ))
+    }
+
+    /// Waits until the search limiter of the index allows another search.
+    /// The search is counted as in flight until the returned guard is dropped.
+    async fn start_search(&self, index_name: &str) -> Result<InFlightSearch<'_>> {
+        let permit = match self.search_limiters.get(index_name) {
+            Some(limiter) => Some(limiter.acquire().await?),
+            None => None,
+        };
+        Ok(InFlightSearch::new(
+            &self.metrics.vector_search_in_flight,
+            index_name,
+            permit,
+        ))
+    }
+
+    async fn query_embedding(
+        &self,
+        index: &Index,
+        input_params: Option<&serde_json::Value>,
+        query_index: usize,
+        query: &str,
+        cache_policy: CachePolicy,
+    ) -> Result<Vec<f32>> {
+        let settings = self.index_settings(&index.table_name);
+        let query = match settings.truncate_query_chars {
+            Some(max_chars) => {
+                let truncated = truncate_chars(query, max_chars);
+                if truncated.len() < query.len() {
+                    info!(
+                        "truncated search query for index {} to {} characters",
+                        index.table_name, max_chars
+                    );
+                }
+                truncated
+            }
+            None => query,
+        };
+        let query_embedder = self.query_embedders.get(&index.table_name);
+        // Embeddings are checked before they are cached, so that a model
+        // returning NaN once doesn't fail the query until it is evicted
+        let embed = async {
+            let embedding = match query_embedder {
+                Some(query_embedder) => self.generate_http_embedding(query_embedder, query).await?,
+                None => {
+                    let content = api::Content {
+                        content_type: mime::TEXT_PLAIN.to_string(),
+                        bytes: query.as_bytes().into(),
+                        features: vec![],
+                        labels: HashMap::new(),
+                    };
+                    self.generate_embedding(index, input_params.cloned(), content)
+                        .await?
+                }
+            };
+            check_finite(query_index, "query", &embedding)?;
+            Ok::<_, anyhow::Error>(embedding)
+        };
+        let embedding = match &self.query_embedding_cache {
+            Some(cache) => {
+                let model = match query_embedder {
+                    Some(query_embedder) => query_embedder.provider(),
+                    None => extractor_model(index, input_params),
+                };
+                cache.get_or_embed(&model, query, embed, cache_policy).await
+            }
+            None => embed.await,
+        };
+        let mut embedding = match embedding {
+            Ok(embedding) => embedding,
+            Err(e) => {
+                self.fallback_query_embedding(&index.table_name, query_index, query, e)
+                    .await?
+            }
+        };
+        if let Some(dim) = settings.dimensions_override {
+            truncate_dimensions(&mut embedding, dim, settings.normalize_truncated)?;
+        }
+        Ok(embedding)
+    }
+
+    /// Embeds the query with the fallbacks of the index, in order, after its
+    /// query embedder failed with error. Fallbacks whose dimension, after
+    /// dimensions_override, doesn't match the index are skipped. Embeddings
+    /// of fallbacks are not cached, so that they are not returned once the
+    /// query embedder is available again. The error of the last fallback is
+    /// returned if all of them fail.
+    async fn fallback_query_embedding(
+        &self,
+        index_name: &str,
+        query_index: usize,
         query: &str,
+        mut error: anyhow::Error,
+    ) -> Result<Vec<f32>> {
+        let Some(fallbacks) = self.query_embedding_fallbacks.get(index_name) else {
+            return Err(error);
+        };
+        let index_dim = self.index_dim(index_name).await?;
+        let dimensions_override = self.index_settings(index_name).dimensions_override;
+        for fallback in fallbacks {
+            let fallback_dim =
+                dimensions_override.map_or(fallback.dim(), |dim| dim.min(fallback.dim()));
+            if index_dim.is_some_and(|dim| dim != fallback_dim as u64) {
+                warn!(
+                    "skipping query embedding fallback {} for index {}, its dimension {} doesn't match the index",
+                    fallback.provider(),
+                    index_name,
+                    fallback.dim()
+                );
+                continue;
+            }
+            warn!(
+                "query embedding for index {} failed, falling back to {}: {}",
+                index_name,
+                fallback.provider(),
+                error
+            );
+            let embedding = self
+                .generate_http_embedding(fallback, query)
+                .await
+                .and_then(|embedding| {
+                    check_finite(query_index, "query", &embedding)?;
+                    Ok(embedding)
+                });
+            match embedding {
+                Ok(embedding) => {
+                    info!(
+                        "query embedding for index {} served by fallback {}",
+                        index_name,
+                        fallback.provider()
+                    );
+                    return Ok(embedding);
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Returns the hits and misses of the query embedding cache per model, or
+    /// None if query embeddings are not cached.
+    pub fn query_embedding_cache_stats(&self) -> Option<Vec<EmbeddingCacheStats>> {
+        self.query_embedding_cache
+            .as_ref()
+            .map(|cache| cache.stats())
+    }
+
+    /// Searches the index for the neighbors of the vector stored for
+    /// content_id. The content itself is not included in the results, None is
+    /// returned if the index has no vector for content_id.
+    pub async fn similar_by_id(
+        &self,
+        index: Index,
+        content_id: &str,
         k: usize,
         filter: filter::LabelsFilter,
         include_content: bool,
-    ) -> Result<Vec<ScoredText>> {
+    ) -> Result<Option<SearchResults>> {
+        let start = Instant::now();
+        let _in_flight = self.start_search(&index.table_name).await?;
         let _timer = Timer::start(&self.metrics.vector_search);
-
-        let content = api::Content {
-            content_type: mime::TEXT_PLAIN.to_string(),
-            bytes: query.as_bytes().into(),
-            features: vec![],
-            labels: HashMap::new(),
+        let Some(embedding) = self
+            .vector_db(&index.table_name)
+            .get_vector(&index.table_name, content_id)
+            .await?
+        else {
+            return Ok(None);
         };
+        // Ask for one more result since the content is its own nearest neighbor
+        let mut search_result = self
+            .search_vector_db(index.table_name, embedding, k as u64 + 1, filter, false)
+            .await?;
+        search_result.retain(|result| result.content_id != content_id);
+        let candidates_considered = search_result.len();
+        search_result.truncate(k);
+        let results = self
+            .scored_texts(search_result, include_content, false)
+            .await?;
+        Ok(Some(SearchResults {
+            results,
+            candidates_considered,
+            groups: Vec::new(),
+            took: start.elapsed(),
+            next_cursor: None,
+        }))
+    }
 
-        let embedding = self.generate_embedding(&index.extractor, content).await?;
-
+    /// Searches the index with a query embedding computed by the client,
+    /// without embedding a query
+    pub async fn search_by_vector(
+        &self,
+        index: Index,
+        embedding: Vec<f32>,
+        k: usize,
+        filter: filter::LabelsFilter,
+        include_content: bool,
+    ) -> Result<SearchResults> {
+        let start = Instant::now();
+        let _in_flight = self.start_search(&index.table_name).await?;
+        let _timer = Timer::start(&self.metrics.vector_search);
+        check_finite(0, "query vector", &embedding)?;
+        self.check_dim(&index.table_name, "query", &embedding)
+            .await?;
+        if self.is_empty(&index.table_name).await? {
+            return Ok(SearchResults {
+                results: Vec::new(),
+                candidates_considered: 0,
+                groups: Vec::new(),
+                took: start.elapsed(),
+                next_cursor: None,
+            });
+        }
         let search_result = self
-            .search_vector_db(index.table_name, embedding.values, k as u64, filter)
+            .search_vector_db(index.table_name, embedding, k as u64, filter, false)
+            .await?;
+        let candidates_considered = search_result.len();
+        let results = self
+            .scored_texts(search_result, include_content, false)
             .await?;
+        Ok(SearchResults {
+            results,
+            candidates_considered,
+            groups: Vec::new(),
+            took: start.elapsed(),
+            next_cursor: None,
+        })
+    }
 
+    async fn scored_texts(
+        &self,
+        search_result: Vec<SearchResult>,
+        include_content: bool,
+        include_source: bool,
+    ) -> Result<Vec<ScoredText>> {
         let mut content_byte_map = HashMap::new();
         if include_content {
             content_byte_map = self.retrieve_content_blob(&search_result).await?;
         }
+        let mut source_texts = HashMap::new();
+        if include_source {
+            source_texts = self.retrieve_source_texts(&search_result).await?;
+        }
 
         let mut index_search_results = Vec::new();
         for result in search_result {
@@ -161,8 +1728,8 @@ impl VectorIndexManager {
             }
             let text =
                 if result.content_metadata.content_type.starts_with("text/") && content.is_some() {
-                    let content = content.unwrap().clone();
-                    String::from_utf8(content.to_vec()).unwrap()
+                    // Content stored before invalid_utf8 was set can be invalid
+                    String::from_utf8_lossy(content.unwrap()).into_owned()
                 } else {
                     String::from("")
                 };
@@ -177,33 +1744,46 @@ impl VectorIndexManager {
                 mime_type: result.content_metadata.content_type.clone(),
                 labels,
                 confidence_score: result.confidence_score,
+                source_text: result
+                    .root_content_metadata
+                    .as_ref()
+                    .and_then(|root| source_texts.get(&root.id.id).cloned()),
                 root_content_metadata: result.root_content_metadata,
                 content_metadata: result.content_metadata.clone(),
+                score_details: None,
             };
             index_search_results.push(search_result);
         }
         Ok(index_search_results)
     }
 
+    /// Embeds a search query like the content of the index, with the input
+    /// params of its extraction policy and the output of its extractor the
+    /// index is named after
     async fn generate_embedding(
         &self,
-        extractor: &str,
+        index: &Index,
+        input_params: Option<serde_json::Value>,
         content: api::Content,
-    ) -> Result<internal_api::Embedding> {
+    ) -> Result<Vec<f32>> {
         let _timer = Timer::start(&self.metrics.vector_search_extract_embeddings);
-        let feature = self
-            .extractor_router
-            .extract_content(extractor, content, None)
+        self.extract_embedding(&index.extractor, index_output(index), input_params, content)
             .await
-            .map_err(|e| anyhow!("unable to extract embedding: {}", e.to_string()))?
-            .features
-            .pop()
-            .ok_or(anyhow!("No embeddings were extracted"))?;
-
-        let embedding =
-            serde_json::from_value(feature.data.clone()).map_err(|e| anyhow!(e.to_string()))?;
+            .map_err(|e| {
+                if e.is::<EmbeddingTimeoutError>() {
+                    return e;
+                }
+                anyhow!("unable to extract embedding: {}", e.to_string())
+            })
+    }
 
-        Ok(embedding)
+    async fn generate_http_embedding(
+        &self,
+        query_embedder: &HttpEmbeddingGenerator,
+        query: &str,
+    ) -> Result<Vec<f32>> {
+        let _timer = Timer::start(&self.metrics.vector_search_extract_embeddings);
+        query_embedder.embed(query.to_string()).await
     }
 
     async fn search_vector_db(
@@ -211,10 +1791,49 @@ impl VectorIndexManager {
         index: String,
         embedding: Vec<f32>,
         k: u64,
-        filter: LabelsFilter,
+        mut filter: LabelsFilter,
+        exact: bool,
     ) -> Result<Vec<SearchResult>> {
         let _timer = Timer::start(&self.metrics.vector_search_db);
-        let search_result = self.vector_db.search(index, embedding, k, filter).await?;
+        let settings = self.index_settings(&index);
+        filter = filter.and(&settings.default_filters);
+        // Vector databases can't evaluate post filters, more candidates are
+        // retrieved so that k of them are left once they are applied
+        let (filter, mut post_filter) = filter.split_post_filters();
+        // Vector databases compare strings by case, so with case insensitive
+        // filters strings are compared with the search results instead
+        let filter = if settings.case_insensitive_filters {
+            let (filter, string_filter) = filter.split_string_filters();
+            post_filter = post_filter.and(&string_filter);
+            filter
+        } else {
+            filter
+        };
+        let limit = if post_filter.is_empty() {
+            k
+        } else {
+            k * POST_FILTER_CANDIDATES_FACTOR as u64
+        };
+        let vector_db = self.vector_db(&index);
+        let mut search_result = if exact {
+            vector_db
+                .search_exact(index.clone(), embedding, limit, filter)
+                .await?
+        } else {
+            vector_db
+                .search(index.clone(), embedding, limit, filter)
+                .await?
+        };
+        if !post_filter.is_empty() {
+            search_result.retain(|result| {
+                if settings.case_insensitive_filters {
+                    post_filter.matches_ignore_case(&result.metadata)
+                } else {
+                    post_filter.matches(&result.metadata)
+                }
+            });
+            search_result.truncate(k as usize);
+        }
         Ok(search_result)
     }
 
@@ -247,4 +1866,613 @@ impl VectorIndexManager {
 
         Ok(content_byte_map)
     }
+
+    /// Reads the root content of the search results with a text mime type,
+    /// keyed by the id of the root content. Root contents shared by several
+    /// results are read once.
+    async fn retrieve_source_texts(
+        &self,
+        search_results: &[SearchResult],
+    ) -> Result<HashMap<String, String>> {
+        let _timer = Timer::start(&self.metrics.vector_search_retrieve_blob);
+        let mut roots = HashMap::new();
+        for root in search_results
+            .iter()
+            .filter_map(|result| result.root_content_metadata.as_ref())
+            .filter(|root| root.content_type.starts_with("text/"))
+        {
+            roots.entry(root.id.id.clone()).or_insert(root);
+        }
+        let root_ids: Vec<String> = roots.keys().cloned().collect();
+        let bytes = join_all(
+            root_ids
+                .iter()
+                .map(|id| self.content_reader.bytes(&roots[id].storage_url)),
+        )
+        .await;
+        let mut source_texts = HashMap::new();
+        for (id, content) in root_ids.into_iter().zip(bytes) {
+            let content = content
+                .map_err(|e| anyhow!("unable to read source content for id: {}, {}", id, e))?;
+            source_texts.insert(id, String::from_utf8_lossy(&content).into_owned());
+        }
+        Ok(source_texts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use indexify_internal_api as internal_api;
+    use indexify_proto::indexify_coordinator::Index;
+    use serde_json::json;
+
+    use super::{
+        apply_boosts,
+        apply_default_metadata,
+        check_batch_dim,
+        check_finite,
+        chunk_offsets,
+        cursor_start,
+        dedup_by_similarity,
+        dedup_inputs,
+        extractor_model,
+        group_by_value,
+        mean_pool,
+        normalize,
+        sort_by_value,
+        subtract_embedding,
+        truncate_chars,
+        truncate_dimensions,
+        DimensionMismatchError,
+        NonFiniteEmbeddingError,
+        SearchCursor,
+        SearchOptions,
+        VectorIndexManager,
+    };
+    use crate::{
+        api::{self, SearchBoost},
+        coordinator_client::CoordinatorClient,
+        server_config::{HttpEmbeddingConfig, IndexSettings, LancedbConfig, ServerConfig},
+        test_util::db_utils::test_mock_content_metadata,
+        vectordbs::{lancedb::LanceDb, SearchResult, VectorDBTS},
+    };
+
+    #[test]
+    fn test_extractor_model() {
+        let index = |table_name: &str| Index {
+            table_name: table_name.to_string(),
+            extractor: "tensorlake/openai".to_string(),
+            ..Default::default()
+        };
+        let small = json!({"model": "text-embedding-3-small"});
+        let large = json!({"model": "text-embedding-3-large"});
+        assert_eq!(
+            extractor_model(&index("default.graph.policy.embedding"), None),
+            "tensorlake/openai:embedding"
+        );
+        assert_ne!(
+            extractor_model(&index("default.graph.small.embedding"), Some(&small)),
+            extractor_model(&index("default.graph.large.embedding"), Some(&large))
+        );
+        assert_ne!(
+            extractor_model(&index("default.graph.policy.text"), None),
+            extractor_model(&index("default.graph.policy.title"), None)
+        );
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("hello world", 5), "hello");
+        assert_eq!(truncate_chars("hello", 10), "hello");
+        assert_eq!(truncate_chars("héllo", 2), "hé");
+        assert_eq!(truncate_chars("", 2), "");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut embedding = vec![3.0, 4.0];
+        assert!(normalize(&mut embedding).unwrap());
+        assert_eq!(embedding, vec![0.6, 0.8]);
+
+        assert!(!normalize(&mut embedding).unwrap());
+        assert_eq!(embedding, vec![0.6, 0.8]);
+
+        assert!(normalize(&mut [0.0, 0.0]).is_err());
+    }
+
+    #[test]
+    fn test_check_finite() {
+        assert!(check_finite(0, "query", &[0.1, -0.2]).is_ok());
+
+        let err = check_finite(2, "query", &[0.1, f32::NAN]).unwrap_err();
+        assert_eq!(err.input_index, 2);
+        assert_eq!(err.position, 1);
+        assert!(err.value.is_nan());
+
+        let err = check_finite(0, "query", &[f32::NEG_INFINITY, 0.1]).unwrap_err();
+        assert_eq!(err.position, 0);
+        assert_eq!(err.value, f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_apply_default_metadata() {
+        let mut metadata = HashMap::from([("source".to_string(), json!("upload"))]);
+        let default_metadata = HashMap::from([
+            ("source".to_string(), json!("wiki")),
+            ("lang".to_string(), json!("en")),
+        ]);
+        apply_default_metadata(&mut metadata, &default_metadata);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["source"], json!("upload"));
+        assert_eq!(metadata["lang"], json!("en"));
+    }
+
+    #[test]
+    fn test_dedup_inputs() {
+        let inputs = ["a", "b", "a", "c", "b"].map(String::from);
+        let (unique_inputs, positions) = dedup_inputs(&inputs);
+        assert_eq!(unique_inputs, vec![(0, "a"), (1, "b"), (3, "c")]);
+        assert_eq!(positions, vec![0, 1, 0, 2, 1]);
+
+        let (unique_inputs, positions) = dedup_inputs(&[]);
+        assert!(unique_inputs.is_empty());
+        assert!(positions.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let chunks = ["hello ", "world", "missing", "wörld"].map(String::from);
+        let offsets = chunk_offsets("hello world, wörld", &chunks);
+        assert_eq!(
+            offsets,
+            vec![Some((0, 6)), Some((6, 11)), None, Some((13, 18))]
+        );
+
+        // Overlapping chunks start within the previous chunk
+        let chunks = ["abcd", "cdef", "efgh"].map(String::from);
+        let offsets = chunk_offsets("abcdefgh", &chunks);
+        assert_eq!(offsets, vec![Some((0, 4)), Some((2, 6)), Some((4, 8))]);
+    }
+
+    #[test]
+    fn test_sort_by_value() {
+        let items = vec![
+            ("a", Some(json!(3))),
+            ("b", None),
+            ("c", Some(json!(10))),
+            ("d", Some(json!(null))),
+            ("e", Some(json!(3.5))),
+            ("f", Some(json!(3))),
+        ];
+        let sorted = |order| {
+            let mut items = items.clone();
+            sort_by_value(&mut items, |item| item.1.as_ref(), order);
+            items.into_iter().map(|item| item.0).collect::<Vec<_>>()
+        };
+        // Numbers are compared numerically, equal values keep their order
+        assert_eq!(
+            sorted(api::SortOrder::Asc),
+            vec!["a", "f", "e", "c", "b", "d"]
+        );
+        assert_eq!(
+            sorted(api::SortOrder::Desc),
+            vec!["c", "e", "a", "f", "b", "d"]
+        );
+
+        let mut items = vec![
+            json!("2024-02-01"),
+            json!("2023-12-31"),
+            json!("2024-01-15"),
+        ];
+        sort_by_value(&mut items, Some, api::SortOrder::Desc);
+        assert_eq!(
+            items,
+            vec![
+                json!("2024-02-01"),
+                json!("2024-01-15"),
+                json!("2023-12-31")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup_by_similarity() {
+        let items = vec![
+            ("a", Some(vec![1.0, 0.0])),
+            ("b", Some(vec![0.99, 0.1])),
+            ("c", Some(vec![0.0, 1.0])),
+            ("d", None),
+            ("e", Some(vec![2.0, 0.0])),
+        ];
+        let kept = |threshold| {
+            dedup_by_similarity(items.clone(), |item| item.1.as_deref(), threshold)
+                .into_iter()
+                .map(|item| item.0)
+                .collect::<Vec<_>>()
+        };
+        // e points in the direction of a, whatever its length
+        assert_eq!(kept(0.95), vec!["a", "c", "d"]);
+        assert_eq!(kept(0.999), vec!["a", "b", "c", "d"]);
+        assert_eq!(kept(1.0), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_subtract_embedding() {
+        let embedding = subtract_embedding(vec![1.0, 2.0], &[1.0, -2.0], 0.5).unwrap();
+        assert_eq!(embedding, vec![0.5, 3.0]);
+        assert!(subtract_embedding(vec![1.0, 2.0], &[1.0], 0.5).is_err());
+    }
+
+    #[test]
+    fn test_truncate_dimensions() {
+        let mut embedding = vec![3.0, 4.0, 12.0];
+        truncate_dimensions(&mut embedding, 2, false).unwrap();
+        assert_eq!(embedding, vec![3.0, 4.0]);
+
+        let mut embedding = vec![3.0, 4.0, 12.0];
+        truncate_dimensions(&mut embedding, 2, true).unwrap();
+        assert_eq!(embedding, vec![0.6, 0.8]);
+
+        // Embeddings which are short enough are not normalized either
+        let mut embedding = vec![3.0, 4.0];
+        truncate_dimensions(&mut embedding, 2, true).unwrap();
+        assert_eq!(embedding, vec![3.0, 4.0]);
+
+        let mut embedding = vec![0.0, 0.0, 1.0];
+        assert!(truncate_dimensions(&mut embedding, 2, true).is_err());
+    }
+
+    #[test]
+    fn test_cursor_start() {
+        let cursor = SearchCursor {
+            offset: 2,
+            content_id: "b".to_string(),
+            query_hash: "query".to_string(),
+        };
+        assert_eq!(
+            SearchCursor::decode(&cursor.encode(), "query").unwrap(),
+            cursor
+        );
+        assert!(SearchCursor::decode("not a cursor", "query").is_err());
+        // Cursors are bound to their request and to a maximum depth
+        assert!(SearchCursor::decode(&cursor.encode(), "other query").is_err());
+        let deep = SearchCursor {
+            offset: usize::MAX,
+            ..cursor.clone()
+        };
+        assert!(SearchCursor::decode(&deep.encode(), "query").is_err());
+
+        fn id<'a>(item: &'a &str) -> &'a str {
+            item
+        }
+        assert_eq!(cursor_start(&["a", "b", "c"], id, &cursor), 2);
+        // Results added or deleted before the last result of the page don't
+        // shift the next page
+        assert_eq!(cursor_start(&["x", "a", "b", "c"], id, &cursor), 3);
+        assert_eq!(cursor_start(&["b", "c"], id, &cursor), 1);
+        // Without the last result the page starts at the offset
+        assert_eq!(cursor_start(&["a", "c", "d"], id, &cursor), 2);
+        assert_eq!(cursor_start(&["a"], id, &cursor), 1);
+    }
+
+    #[test]
+    fn test_mean_pool() {
+        let embedding = mean_pool(vec![vec![1.0, 2.0]]).unwrap();
+        assert_eq!(embedding, vec![1.0, 2.0]);
+
+        let embedding = mean_pool(vec![vec![1.0, 2.0], vec![3.0, 0.0]]).unwrap();
+        assert_eq!(embedding, vec![2.0, 1.0]);
+
+        assert!(mean_pool(vec![]).is_err());
+        assert!(mean_pool(vec![vec![1.0, 2.0], vec![1.0]]).is_err());
+    }
+
+    #[test]
+    fn test_apply_boosts() {
+        let result = |content_id: &str, confidence_score: f32, source: &str| SearchResult {
+            content_id: content_id.to_string(),
+            confidence_score,
+            metadata: HashMap::from([("source".to_string(), json!(source))]),
+            ..Default::default()
+        };
+        let boosts = vec![SearchBoost {
+            field: "source".to_string(),
+            value: json!("recent"),
+            factor: 2.0,
+        }];
+        let ids = |results: &[SearchResult]| -> Vec<String> {
+            results.iter().map(|r| r.content_id.clone()).collect()
+        };
+
+        let mut results = vec![result("a", 0.9, "old"), result("b", 0.6, "recent")];
+        apply_boosts(&mut results, &boosts, false);
+        assert_eq!(ids(&results), vec!["b", "a"]);
+        assert_eq!(results[0].confidence_score, 1.2);
+
+        // Boosted distances are lowered instead
+        let mut results = vec![result("a", 0.2, "old"), result("b", 0.3, "recent")];
+        apply_boosts(&mut results, &boosts, true);
+        assert_eq!(ids(&results), vec!["b", "a"]);
+        assert_eq!(results[0].confidence_score, 0.15);
+
+        // Boosted negative similarities are raised towards zero
+        let mut results = vec![result("a", -0.3, "old"), result("b", -0.4, "recent")];
+        apply_boosts(&mut results, &boosts, false);
+        assert_eq!(ids(&results), vec!["b", "a"]);
+        assert_eq!(results[0].confidence_score, -0.2);
+    }
+
+    #[test]
+    fn test_group_by_value() {
+        let items = vec![("a", 1), ("b", 2), ("a", 3), ("c", 4), ("a", 5), ("b", 6)];
+        let groups = group_by_value(items, |(source, _)| json!(source), 2, 2);
+        let groups: Vec<_> = groups
+            .into_iter()
+            .map(|(value, items)| (value, items.into_iter().map(|(_, i)| i).collect::<Vec<_>>()))
+            .collect();
+        // Groups are ordered by their first item, c is beyond the 2 groups
+        assert_eq!(
+            groups,
+            vec![(json!("a"), vec![1, 3]), (json!("b"), vec![2, 6])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_empty_index() {
+        let _ = std::fs::remove_dir_all("/tmp/lance-empty-index.db/");
+        let vector_db: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance-empty-index.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        let coordinator_client =
+            Arc::new(CoordinatorClient::new(Arc::new(ServerConfig::default())));
+        let manager =
+            VectorIndexManager::new(coordinator_client, vector_db, HashMap::new()).unwrap();
+        let table_name = manager
+            .create_index(
+                "empty-index",
+                internal_api::EmbeddingSchema {
+                    dim: 2,
+                    distance: "cosine".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        // The query is not embedded, so no extractor is needed
+        let search = manager
+            .search(
+                Index {
+                    table_name,
+                    ..Default::default()
+                },
+                None,
+                &["hello".to_string()],
+                SearchOptions {
+                    k: 10,
+                    include_content: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(search.results.is_empty());
+        assert_eq!(search.candidates_considered, 0);
+    }
+
+    #[test]
+    fn test_check_batch_dim() {
+        let embedding = |content_id: &str, embedding: Vec<f32>| internal_api::ExtractedEmbeddings {
+            content_id: content_id.to_string(),
+            embedding,
+            metadata: HashMap::new(),
+            content_metadata: test_mock_content_metadata(content_id, "", ""),
+            root_content_metadata: None,
+        };
+        assert!(check_batch_dim("idx", &[]).is_ok());
+        assert!(check_batch_dim(
+            "idx",
+            &[
+                embedding("a", vec![0.1, 0.2]),
+                embedding("b", vec![0.3, 0.4])
+            ]
+        )
+        .is_ok());
+        let err = check_batch_dim(
+            "idx",
+            &[
+                embedding("a", vec![0.1, 0.2]),
+                embedding("b", vec![0.3, 0.4, 0.5]),
+            ],
+        )
+        .unwrap_err();
+        let err = err.downcast::<DimensionMismatchError>().unwrap();
+        assert_eq!(err.content_id, "b");
+        assert_eq!(err.dim, 3);
+        assert_eq!(err.index_dim, 2);
+    }
+
+    #[tokio::test]
+    async fn test_add_non_finite_embedding() {
+        let _ = std::fs::remove_dir_all("/tmp/lance-non-finite.db/");
+        let vector_db: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance-non-finite.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        let coordinator_client =
+            Arc::new(CoordinatorClient::new(Arc::new(ServerConfig::default())));
+        let manager =
+            VectorIndexManager::new(coordinator_client, vector_db, HashMap::new()).unwrap();
+        let table_name = manager
+            .create_index(
+                "non-finite",
+                internal_api::EmbeddingSchema {
+                    dim: 2,
+                    distance: "cosine".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let embedding = |content_id: &str, embedding: Vec<f32>| internal_api::ExtractedEmbeddings {
+            content_id: content_id.to_string(),
+            embedding,
+            metadata: HashMap::new(),
+            content_metadata: test_mock_content_metadata(content_id, "", ""),
+            root_content_metadata: None,
+        };
+        let err = manager
+            .add_embedding(
+                &table_name,
+                vec![
+                    embedding("a", vec![0.1, 0.2]),
+                    embedding("b", vec![0.1, f32::INFINITY]),
+                ],
+            )
+            .await
+            .unwrap_err();
+        let err = err.downcast::<NonFiniteEmbeddingError>().unwrap();
+        assert_eq!(err.input_index, 1);
+        assert_eq!(err.input, "content b");
+    }
+
+    #[tokio::test]
+    async fn test_query_embedding_providers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use axum::{routing::post, Json, Router};
+
+        // Starts an embedding service embedding every input as embedding,
+        // returning its address and the number of requests it received
+        async fn provider(embedding: Vec<f32>) -> (std::net::SocketAddr, Arc<AtomicUsize>) {
+            let requests = Arc::new(AtomicUsize::new(0));
+            let counter = requests.clone();
+            let app = Router::new().route(
+                "/embed",
+                post(move |Json(body): Json<serde_json::Value>| {
+                    let counter = counter.clone();
+                    let embedding = embedding.clone();
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let inputs = body["inputs"].as_array().unwrap().len();
+                        Json(json!({ "embeddings": vec![embedding; inputs] }))
+                    }
+                }),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (addr, requests)
+        }
+        let query_embedding = |addr: std::net::SocketAddr, model: &str| IndexSettings {
+            query_embedding: Some(HttpEmbeddingConfig {
+                url: format!("http://{}/embed", addr),
+                model: model.to_string(),
+                dim: 2,
+                inputs_field: "inputs".to_string(),
+                embeddings_field: "embeddings".to_string(),
+                connect_timeout_secs: 1,
+                read_timeout_secs: 5,
+                require_on_startup: false,
+                health_check_interval_secs: None,
+                sample_response: None,
+                coalesce_window_ms: 0,
+                max_batch_size: 32,
+            }),
+            ..Default::default()
+        };
+
+        // Both indexes have the same dimension, but their queries are
+        // embedded by different providers
+        let (local_addr, local_requests) = provider(vec![1.0, 0.0]).await;
+        let (remote_addr, remote_requests) = provider(vec![0.0, 1.0]).await;
+        let local_index = "default.graph.local.embedding";
+        let remote_index = "default.graph.remote.embedding";
+        let mut config = ServerConfig::default();
+        config.index_config.indexes = HashMap::from([
+            (
+                local_index.to_string(),
+                query_embedding(local_addr, "minilm"),
+            ),
+            (
+                remote_index.to_string(),
+                query_embedding(remote_addr, "text-embedding-3-small"),
+            ),
+        ]);
+        let _ = std::fs::remove_dir_all("/tmp/lance-providers.db/");
+        let vector_db: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance-providers.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        let coordinator_client = Arc::new(CoordinatorClient::new(Arc::new(config)));
+        let manager =
+            VectorIndexManager::new(coordinator_client, vector_db, HashMap::new()).unwrap();
+
+        let embedding = |content_id: &str, embedding: Vec<f32>| internal_api::ExtractedEmbeddings {
+            content_id: content_id.to_string(),
+            embedding,
+            metadata: HashMap::new(),
+            content_metadata: test_mock_content_metadata(content_id, "", ""),
+            root_content_metadata: None,
+        };
+        for index_name in [local_index, remote_index] {
+            let table_name = manager
+                .create_index(
+                    index_name,
+                    internal_api::EmbeddingSchema {
+                        dim: 2,
+                        distance: "cosine".to_string(),
+                    },
+                )
+                .await
+                .unwrap();
+            manager
+                .add_embedding(
+                    &table_name,
+                    vec![
+                        embedding("first", vec![1.0, 0.0]),
+                        embedding("second", vec![0.0, 1.0]),
+                    ],
+                )
+                .await
+                .unwrap();
+            assert!(!manager.embeds_queries_with_extractor(&table_name));
+        }
+
+        let queries = vec!["hello".to_string()];
+        let search = |table_name: &str| {
+            manager.search(
+                Index {
+                    table_name: table_name.to_string(),
+                    ..Default::default()
+                },
+                None,
+                &queries,
+                SearchOptions {
+                    k: 1,
+                    ..Default::default()
+                },
+            )
+        };
+        let local_search = search(local_index).await.unwrap();
+        assert_eq!(local_search.results[0].content_id, "first");
+        assert_eq!(local_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(remote_requests.load(Ordering::SeqCst), 0);
+
+        let remote_search = search(remote_index).await.unwrap();
+        assert_eq!(remote_search.results[0].content_id, "second");
+        assert_eq!(local_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(remote_requests.load(Ordering::SeqCst), 1);
+    }
 }
@@ -1,13 +1,15 @@
 use std::{
-    collections::HashMap,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
     fmt::{self, Debug},
+    path::Path,
     sync::Arc,
 };
 
 use anyhow::{anyhow, Result};
 use arrow_array::{
     cast::{as_boolean_array, as_string_array},
-    types::{self, Float32Type},
+    types::{self, Float16Type, Float32Type},
     Array,
     BooleanArray,
     FixedSizeListArray,
@@ -30,15 +32,29 @@ use lancedb::{
 };
 use tracing;
 
-use super::{CreateIndexParams, SearchResult, VectorChunk, VectorDb};
-use crate::server_config::LancedbConfig;
+use super::{
+    from_f16,
+    to_f16,
+    CreateIndexParams,
+    IndexDistance,
+    ScrollPage,
+    SearchResult,
+    VectorChunk,
+    VectorDb,
+    VectorDbCapabilities,
+};
+use crate::server_config::{LancedbConfig, StorageDtype};
 
-fn from_filter_to_str(filter: &filter::LabelsFilter) -> String {
+/// Metadata keys are columns of the table. A key which was never written has
+/// no column, existence filters on it are resolved without the column since
+/// the query would fail on it.
+fn from_filter_to_str(filter: &filter::LabelsFilter, schema: &Schema) -> String {
     filter
         .expressions()
         .iter()
         .map(|f| {
             let value = from_filter_value_to_str(&f.value);
+            let has_column = schema.field_with_name(&f.key).is_ok();
             match f.operator {
                 Operator::Eq => format!("{} = {}", f.key, value),
                 Operator::Neq => format!("{} != {}", f.key, value),
@@ -46,15 +62,40 @@ fn from_filter_to_str(filter: &filter::LabelsFilter) -> String {
                 Operator::Lt => format!("{} < {}", f.key, value),
                 Operator::GtEq => format!("{} >= {}", f.key, value),
                 Operator::LtEq => format!("{} <= {}", f.key, value),
+                Operator::Exists if !has_column => "FALSE".to_string(),
+                Operator::NotExists if !has_column => "TRUE".to_string(),
+                Operator::Exists => format!("{} IS NOT NULL", f.key),
+                Operator::NotExists => format!("{} IS NULL", f.key),
+                // Fuzzy filters are applied to the results of the search
+                Operator::Fuzzy { .. } => "TRUE".to_string(),
             }
         })
         .collect::<Vec<_>>()
         .join(" AND ")
 }
 
+// Columns of every table which aren't metadata
+const RESERVED_COLUMNS: [&str; 4] = ["id", "vector", "content_metadata", "root_content_metadata"];
+
+fn sql_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+        serde_json::Value::String(value) => sql_string(value),
+        _ => sql_string(&value.to_string()),
+    }
+}
+
+/// Quotes a string as a SQL literal. Quotes in the string are doubled, so
+/// that content ids and filter values from requests can't end the literal and
+/// rewrite the predicate.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
 fn from_filter_value_to_str(value: &serde_json::Value) -> String {
     match value {
-        serde_json::Value::String(s) => format!("'{s}'"),
+        serde_json::Value::String(s) => sql_string(s),
         serde_json::Value::Number(n) => n.to_string(),
         serde_json::Value::Bool(b) => b.to_string(),
         _ => "".to_string(),
@@ -63,6 +104,7 @@ fn from_filter_value_to_str(value: &serde_json::Value) -> String {
 
 pub struct LanceDb {
     conn: Arc<Connection>,
+    case_sensitive_names: bool,
 }
 
 impl Debug for LanceDb {
@@ -89,8 +131,10 @@ async fn vector_chunk_from_batch(
                 .map(|x| x.unwrap().to_string())
                 .collect();
         } else if field_name == "vector" {
-            // Looks like we are not using the embedding
-            // so we are just doing empty Vec to make the structs happy
+            // Search results are not required to include the vector column
+            if let Some(column) = batch.column_by_name(field_name) {
+                embeddings = embeddings_from_column(column)?;
+            }
         } else if field_name == "content_metadata" {
             for row in as_string_array(batch.column_by_name(field_name).unwrap()) {
                 let row = row.map(|s| s.to_string()).unwrap_or_default();
@@ -116,8 +160,6 @@ async fn vector_chunk_from_batch(
             }
         }
     }
-    // Looks like we are not using the embedding
-    // so we are just doing empty Vec to make the structs happy
     if embeddings.is_empty() {
         embeddings = vec![Vec::new(); ids.len()];
     }
@@ -156,10 +198,105 @@ impl LanceDb {
             .map_err(|e| anyhow!("unable to create db: {}", e))?;
         Ok(LanceDb {
             conn: Arc::new(conn),
+            case_sensitive_names: case_sensitive_path(&config.path),
         })
     }
 }
 
+/// Whether directories under path whose names differ only by case are
+/// distinct. Every table is a directory under the path of the database,
+/// object stores are case sensitive and local file systems are probed.
+fn case_sensitive_path(path: &str) -> bool {
+    if path.contains("://") {
+        return true;
+    }
+    let probe = Path::new(path).join(format!(".Case-Probe-{}", nanoid::nanoid!()));
+    if let Err(e) = std::fs::create_dir_all(&probe) {
+        tracing::warn!("unable to probe the case sensitivity of {}: {}", path, e);
+        return true;
+    }
+    let lowercase = probe.with_file_name(
+        probe
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase(),
+    );
+    let case_sensitive = !lowercase.exists();
+    let _ = std::fs::remove_dir(&probe);
+    case_sensitive
+}
+
+// Reads the embeddings of the rows of a vector column as f32
+/// Vector chunk ordered by its content id
+struct ByContentId(VectorChunk);
+
+impl PartialEq for ByContentId {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.content_id == other.0.content_id
+    }
+}
+
+impl Eq for ByContentId {}
+
+impl PartialOrd for ByContentId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByContentId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.content_id.cmp(&other.0.content_id)
+    }
+}
+
+/// Adds the chunk to the max-heap of the limit chunks with the smallest
+/// content ids, dropping the chunk with the largest id once it is full
+fn push_smallest(page: &mut BinaryHeap<ByContentId>, chunk: VectorChunk, limit: usize) {
+    if page.len() < limit {
+        page.push(ByContentId(chunk));
+        return;
+    }
+    if let Some(mut largest) = page.peek_mut() {
+        if chunk.content_id < largest.0.content_id {
+            *largest = ByContentId(chunk);
+        }
+    }
+}
+
+fn embeddings_from_column(column: &Arc<dyn Array>) -> Result<Vec<Vec<f32>>> {
+    let vectors = column
+        .as_any()
+        .downcast_ref::<FixedSizeListArray>()
+        .ok_or(anyhow!("unexpected type for vector column"))?;
+    let mut embeddings = Vec::with_capacity(vectors.len());
+    for i in 0..vectors.len() {
+        let values = vectors.value(i);
+        if let Some(values) = values
+            .as_any()
+            .downcast_ref::<PrimitiveArray<Float16Type>>()
+        {
+            embeddings.push(from_f16(values.values()));
+            continue;
+        }
+        let values = values
+            .as_any()
+            .downcast_ref::<PrimitiveArray<Float32Type>>()
+            .ok_or(anyhow!("unexpected type for vector values"))?;
+        embeddings.push(values.values().to_vec());
+    }
+    Ok(embeddings)
+}
+
+// Returns the type of the components of the vector column
+fn vector_item_type(schema: &Schema) -> Result<DataType> {
+    match schema.field_with_name("vector")?.data_type() {
+        DataType::FixedSizeList(item, _) => Ok(item.data_type().clone()),
+        data_type => Err(anyhow!("unexpected vector column type {}", data_type)),
+    }
+}
+
 // Update the schema of the table with the missing fields from the metadata keys
 async fn update_schema_with_missing_fields(
     tbl: &Table,
@@ -231,14 +368,36 @@ impl VectorDb for LanceDb {
         "lancedb".into()
     }
 
+    /// Searches are always ranked by cosine distance, and Lance has no exact
+    /// search or cheaper count
+    fn capabilities(&self) -> VectorDbCapabilities {
+        VectorDbCapabilities {
+            distances: vec![IndexDistance::Cosine],
+            storage_dtypes: vec![StorageDtype::F32, StorageDtype::F16],
+            exact_search: false,
+            approximate_count: false,
+            case_sensitive_names: self.case_sensitive_names,
+        }
+    }
+
+    /// Lance returns the cosine distance of every result
+    fn scores_are_distances(&self) -> bool {
+        true
+    }
+
     #[tracing::instrument]
     async fn create_index(&self, index: CreateIndexParams) -> Result<()> {
+        // Lance stores and searches half precision vectors natively
+        let item_type = match index.storage_dtype {
+            StorageDtype::F32 => DataType::Float32,
+            StorageDtype::F16 => DataType::Float16,
+        };
         let schema = Arc::new(Schema::new(vec![
             Field::new("id", DataType::Utf8, false),
             Field::new(
                 "vector",
                 DataType::FixedSizeList(
-                    Arc::new(Field::new("item", DataType::Float32, true)),
+                    Arc::new(Field::new("item", item_type, true)),
                     index.vector_dim as i32,
                 ),
                 true,
@@ -266,8 +425,13 @@ impl VectorDb for LanceDb {
         Ok(())
     }
 
-    #[tracing::instrument]
+    #[tracing::instrument(skip(chunks))]
     async fn add_embedding(&self, index: &str, chunks: Vec<VectorChunk>) -> Result<()> {
+        tracing::debug!(
+            "lancedb upsert: table: {}, vectors: {}",
+            index,
+            chunks.len()
+        );
         let tbl = self
             .conn
             .open_table(index)
@@ -276,12 +440,20 @@ impl VectorDb for LanceDb {
             .map_err(|e| anyhow!("unable to open table: {}", e))?;
         let ids = StringArray::from_iter_values(chunks.iter().map(|c| c.content_id.clone()));
         let vector_dim = chunks[0].embedding.len() as i32;
-        let vectors = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
-            chunks
-                .iter()
-                .map(|c| Some(c.embedding.iter().map(|e| Some(*e)))),
-            vector_dim,
-        );
+        let vectors = match vector_item_type(&tbl.schema().await?)? {
+            DataType::Float16 => FixedSizeListArray::from_iter_primitive::<Float16Type, _, _>(
+                chunks
+                    .iter()
+                    .map(|c| Some(to_f16(&c.embedding).into_iter().map(Some))),
+                vector_dim,
+            ),
+            _ => FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+                chunks
+                    .iter()
+                    .map(|c| Some(c.embedding.iter().map(|e| Some(*e)))),
+                vector_dim,
+            ),
+        };
         let metadata = chunks
             .first()
             .map(|c| c.metadata.clone())
@@ -362,7 +534,7 @@ impl VectorDb for LanceDb {
 
         let ids_str = ids
             .into_iter()
-            .map(|id| format!("'{}'", id))
+            .map(|id| sql_string(&id))
             .collect::<Vec<_>>()
             .join(", ");
         let condition = format!("id IN ({})", ids_str);
@@ -382,11 +554,84 @@ impl VectorDb for LanceDb {
         Ok(results)
     }
 
+    async fn get_vector(&self, index: &str, content_id: &str) -> Result<Option<Vec<f32>>> {
+        let tbl = self
+            .conn
+            .open_table(index)
+            .execute()
+            .await
+            .map_err(|e| anyhow!("unable to open table: {}", e))?;
+        let mut stream = tbl
+            .query()
+            .only_if(format!("id = {}", sql_string(content_id)))
+            .limit(1)
+            .execute()
+            .await
+            .map_err(|e| anyhow!("unable to select records: {}", e))?;
+        while let Some(batch) = stream.next().await {
+            let batch = batch.map_err(|e| anyhow!("unable to read records: {}", e))?;
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            let column = batch
+                .column_by_name("vector")
+                .ok_or(anyhow!("vector column not found in table {}", index))?;
+            return Ok(embeddings_from_column(column)?.into_iter().next());
+        }
+        Ok(None)
+    }
+
+    #[tracing::instrument]
+    async fn scroll(&self, index: &str, cursor: Option<String>, limit: u64) -> Result<ScrollPage> {
+        let tbl = self
+            .conn
+            .open_table(index)
+            .execute()
+            .await
+            .map_err(|e| anyhow!("unable to open table: {}", e))?;
+        let schema = tbl.schema().await?;
+        // Lance does not order query results, so pages are the rows with the
+        // smallest ids after the cursor, which is the last id of the previous
+        // page. Every page still reads all the rows after the cursor, so
+        // scrolling through a table of n rows reads about n² / (2 * limit)
+        // rows, while only limit rows are kept in memory.
+        let mut query = tbl.query();
+        if let Some(cursor) = &cursor {
+            query = query.only_if(format!("id > {}", sql_string(cursor)));
+        }
+        let mut stream = query
+            .execute()
+            .await
+            .map_err(|e| anyhow!("unable to select records: {}", e))?;
+        let mut page = BinaryHeap::new();
+        while let Some(batch) = stream.next().await {
+            let batch = batch.map_err(|e| anyhow!("unable to read records: {}", e))?;
+            for chunk in vector_chunk_from_batch(batch, schema.clone()).await? {
+                push_smallest(&mut page, chunk, limit as usize);
+            }
+        }
+        let chunks: Vec<VectorChunk> = page
+            .into_sorted_vec()
+            .into_iter()
+            .map(|chunk| chunk.0)
+            .collect();
+        let next_cursor = if chunks.len() as u64 == limit {
+            chunks.last().map(|chunk| chunk.content_id.clone())
+        } else {
+            None
+        };
+        Ok(ScrollPage {
+            chunks,
+            next_cursor,
+        })
+    }
+
     async fn update_metadata(
         &self,
         index: &str,
         content_id: String,
         metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
     ) -> Result<()> {
         let tbl = self
             .conn
@@ -395,11 +640,23 @@ impl VectorDb for LanceDb {
             .await
             .map_err(|e| anyhow!("unable to open table: {}", e))?;
 
-        update_schema_with_missing_fields(&tbl, metadata.clone()).await?;
+        let schema = update_schema_with_missing_fields(&tbl, metadata.clone()).await?;
 
-        let mut update_op = tbl.update().only_if(format!("id = '{}'", content_id));
+        let mut update_op = tbl
+            .update()
+            .only_if(format!("id = {}", sql_string(&content_id)));
+        if !merge {
+            // Every metadata key is a column, keys which aren't replaced are
+            // cleared
+            for field in schema.fields() {
+                let name = field.name();
+                if !RESERVED_COLUMNS.contains(&name.as_str()) && !metadata.contains_key(name) {
+                    update_op = update_op.column(name, "NULL");
+                }
+            }
+        }
         for (key, value) in metadata {
-            update_op = update_op.column(key, value.to_string());
+            update_op = update_op.column(key, sql_value(&value));
         }
         update_op
             .execute()
@@ -419,7 +676,12 @@ impl VectorDb for LanceDb {
             .map_err(|e| anyhow!("unable to open table: {}", e))?;
 
         // Delete the rows where content_id is the key
-        tbl.delete(&format!("id = '{}'", content_id))
+        tracing::debug!(
+            "lancedb delete: table: {}, content_id: {}",
+            index,
+            content_id
+        );
+        tbl.delete(&format!("id = {}", sql_string(content_id)))
             .await
             .map_err(|e| {
                 anyhow!(
@@ -430,7 +692,7 @@ impl VectorDb for LanceDb {
             })
     }
 
-    #[tracing::instrument]
+    #[tracing::instrument(skip(query_embedding))]
     async fn search(
         &self,
         index: String,
@@ -447,7 +709,11 @@ impl VectorDb for LanceDb {
             .map_err(|e| anyhow!("unable to create vector search query: {}", e))?
             .distance_type(lancedb::DistanceType::Cosine);
         if !filter.is_empty() {
-            query = query.only_if(from_filter_to_str(&filter));
+            let schema = tbl
+                .schema()
+                .await
+                .map_err(|e| anyhow!("unable to get schema of table: {}", e))?;
+            query = query.only_if(from_filter_to_str(&filter, &schema));
         }
         let res = query
             .column("vector")
@@ -486,6 +752,13 @@ impl VectorDb for LanceDb {
                 });
             }
         }
+        tracing::debug!(
+            "lancedb search: table: {}, metric: cosine, k: {}, filter: {:?}, candidates: {}",
+            index,
+            k,
+            filter,
+            results.len()
+        );
         Ok(results)
     }
 
@@ -508,6 +781,29 @@ impl VectorDb for LanceDb {
         let rows = table.count_rows(None).await?;
         Ok(rows as u64)
     }
+
+    #[tracing::instrument]
+    async fn index_dim(&self, index: &str) -> Result<Option<u64>> {
+        let table_names = self.conn.table_names().execute().await?;
+        if !table_names.iter().any(|name| name == index) {
+            return Ok(None);
+        }
+        let table = self
+            .conn
+            .open_table(index)
+            .execute()
+            .await
+            .map_err(|e| anyhow!("unable to open table {} ", e))?;
+        let schema = table.schema().await?;
+        match schema.field_with_name("vector")?.data_type() {
+            DataType::FixedSizeList(_, dim) => Ok(Some(*dim as u64)),
+            data_type => Err(anyhow!(
+                "unexpected vector column type {} in table {}",
+                data_type,
+                index
+            )),
+        }
+    }
 }
 
 fn from_serde_json_to_arrow_array(
@@ -636,16 +932,20 @@ mod tests {
     use std::sync::Arc;
 
     use super::*;
-    use crate::vectordbs::{
-        tests::{
-            basic_search,
-            crud_operations,
-            insertion_idempotent,
-            search_filters,
-            store_metadata,
+    use crate::{
+        test_util::db_utils::test_mock_content_metadata,
+        vectordbs::{
+            tests::{
+                basic_search,
+                crud_operations,
+                existence_filters,
+                insertion_idempotent,
+                search_filters,
+                store_metadata,
+            },
+            IndexDistance,
+            VectorDBTS,
         },
-        IndexDistance,
-        VectorDBTS,
     };
 
     #[tokio::test]
@@ -665,6 +965,7 @@ mod tests {
                 vector_dim: 2,
                 distance: crate::vectordbs::IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -688,6 +989,7 @@ mod tests {
                 vector_dim: 2,
                 distance: crate::vectordbs::IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -712,6 +1014,7 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -736,6 +1039,7 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -759,9 +1063,219 @@ mod tests {
                 vector_dim: 2,
                 distance: crate::vectordbs::IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
         search_filters(lance, "hello-index").await;
     }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_existence_filters() {
+        let _ = std::fs::remove_dir_all("/tmp/lance.db/");
+        let lance: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        lance
+            .create_index(CreateIndexParams {
+                vectordb_index_name: "existence-index".into(),
+                vector_dim: 2,
+                distance: crate::vectordbs::IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F32,
+            })
+            .await
+            .unwrap();
+        existence_filters(lance, "existence-index").await;
+    }
+
+    #[tokio::test]
+    async fn test_index_dim() {
+        let index_name = "dim-index";
+        let _ = std::fs::remove_dir_all("/tmp/lance.db/");
+        let lance: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        assert_eq!(lance.index_dim(index_name).await.unwrap(), None);
+        lance
+            .create_index(CreateIndexParams {
+                vectordb_index_name: index_name.into(),
+                vector_dim: 3,
+                distance: IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F32,
+            })
+            .await
+            .unwrap();
+        assert_eq!(lance.index_dim(index_name).await.unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_f16_storage() {
+        let index_name = "f16-index";
+        let _ = std::fs::remove_dir_all("/tmp/lance.db/");
+        let lance: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        lance
+            .create_index(CreateIndexParams {
+                vectordb_index_name: index_name.into(),
+                vector_dim: 2,
+                distance: IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F16,
+            })
+            .await
+            .unwrap();
+        let chunk = VectorChunk {
+            content_id: "0".into(),
+            embedding: vec![0.5, 0.333],
+            metadata: HashMap::new(),
+            root_content_metadata: None,
+            content_metadata: test_mock_content_metadata("0", "1", "graph1"),
+        };
+        lance.add_embedding(index_name, vec![chunk]).await.unwrap();
+        let embedding = lance.get_vector(index_name, "0").await.unwrap().unwrap();
+        assert_eq!(embedding[0], 0.5);
+        assert!((embedding[1] - 0.333).abs() < 1e-3);
+        basic_search(lance, index_name).await;
+    }
+
+    #[tokio::test]
+    async fn test_scroll() {
+        let index_name = "scroll-index";
+        let _ = std::fs::remove_dir_all("/tmp/lance.db/");
+        let lance: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        lance
+            .create_index(CreateIndexParams {
+                vectordb_index_name: index_name.into(),
+                vector_dim: 2,
+                distance: IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F32,
+            })
+            .await
+            .unwrap();
+        let chunks = (0..3)
+            .map(|i| VectorChunk {
+                content_id: i.to_string(),
+                embedding: vec![i as f32, 1.],
+                metadata: HashMap::new(),
+                root_content_metadata: None,
+                content_metadata: test_mock_content_metadata(&i.to_string(), "1", "graph1"),
+            })
+            .collect();
+        lance.add_embedding(index_name, chunks).await.unwrap();
+
+        let page = lance.scroll(index_name, None, 2).await.unwrap();
+        assert_eq!(page.chunks.len(), 2);
+        assert!(page.next_cursor.is_some());
+        let last_page = lance.scroll(index_name, page.next_cursor, 2).await.unwrap();
+        assert_eq!(last_page.chunks.len(), 1);
+        assert!(last_page.next_cursor.is_none());
+
+        let ids: Vec<String> = page
+            .chunks
+            .iter()
+            .chain(last_page.chunks.iter())
+            .map(|chunk| chunk.content_id.clone())
+            .collect();
+        assert_eq!(ids, vec!["0", "1", "2"]);
+        assert_eq!(page.next_cursor, Some("1".to_string()));
+        let embedding = &last_page.chunks[0].embedding;
+        assert_eq!(embedding.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_quoted_content_ids() {
+        let index_name = "quoted-index";
+        let _ = std::fs::remove_dir_all("/tmp/lance-quoted.db/");
+        let lance: VectorDBTS = Arc::new(
+            LanceDb::new(&LancedbConfig {
+                path: "/tmp/lance-quoted.db".to_string(),
+            })
+            .await
+            .unwrap(),
+        );
+        lance
+            .create_index(CreateIndexParams {
+                vectordb_index_name: index_name.into(),
+                vector_dim: 2,
+                distance: IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F32,
+            })
+            .await
+            .unwrap();
+        let chunks = ["a", "it's"]
+            .iter()
+            .map(|id| VectorChunk {
+                content_id: id.to_string(),
+                embedding: vec![1., 1.],
+                metadata: HashMap::new(),
+                root_content_metadata: None,
+                content_metadata: test_mock_content_metadata(id, "1", "graph1"),
+            })
+            .collect();
+        lance.add_embedding(index_name, chunks).await.unwrap();
+
+        // The quote can't end the literal and match every row
+        lance
+            .remove_embedding(index_name, "x' OR '1' = '1")
+            .await
+            .unwrap();
+        assert_eq!(lance.num_vectors(index_name).await.unwrap(), 2);
+
+        let points = lance
+            .get_points(index_name, vec!["it's".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(points.len(), 1);
+        lance.remove_embedding(index_name, "it's").await.unwrap();
+        assert_eq!(lance.num_vectors(index_name).await.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_push_smallest() {
+        let chunk = |id: &str| VectorChunk {
+            content_id: id.to_string(),
+            embedding: vec![1., 1.],
+            metadata: HashMap::new(),
+            root_content_metadata: None,
+            content_metadata: test_mock_content_metadata(id, "1", "graph1"),
+        };
+        let mut page = BinaryHeap::new();
+        for id in ["d", "b", "e", "a", "c"] {
+            push_smallest(&mut page, chunk(id), 3);
+        }
+        let ids: Vec<String> = page
+            .into_sorted_vec()
+            .into_iter()
+            .map(|chunk| chunk.0.content_id)
+            .collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+
+        let mut page = BinaryHeap::new();
+        push_smallest(&mut page, chunk("a"), 0);
+        assert!(page.is_empty());
+    }
 }
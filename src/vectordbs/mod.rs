@@ -1,13 +1,17 @@
 use std::{collections::HashMap, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use filter::Expression;
 use indexify_internal_api::ContentMetadata;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString};
+use thiserror::Error;
 
-use crate::server_config::{IndexStoreKind, VectorIndexConfig};
+use crate::{
+    metrics::vector_storage::Metrics,
+    server_config::{IndexStoreKind, StorageDtype, VectorIndexConfig},
+};
 
 pub mod lancedb;
 //pub mod open_search;
@@ -16,7 +20,7 @@ pub mod qdrant;
 
 use qdrant::QdrantDb;
 
-#[derive(Display, Debug, Clone, EnumString, Serialize, Deserialize)]
+#[derive(Display, Debug, Clone, PartialEq, EnumString, Serialize, Deserialize)]
 pub enum IndexDistance {
     #[strum(serialize = "cosine")]
     #[serde(rename = "cosine")]
@@ -39,6 +43,84 @@ pub struct CreateIndexParams {
     pub distance: IndexDistance,
     // TODO: Probably better if this is a HashMap<String, String> (?), or a generic (?)
     pub unique_params: Option<Vec<String>>,
+    pub storage_dtype: StorageDtype,
+}
+
+/// Features which differ between vector databases. New indexes are checked
+/// against the capabilities of their vector database before they are created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorDbCapabilities {
+    /// Distances which searches of an index are ranked by
+    pub distances: Vec<IndexDistance>,
+    /// Precisions embeddings can be stored with
+    pub storage_dtypes: Vec<StorageDtype>,
+    /// Whether search_exact compares the query with every vector instead of
+    /// falling back to search
+    pub exact_search: bool,
+    /// Whether estimate_num_vectors returns an estimate
+    pub approximate_count: bool,
+    /// Whether indexes whose names differ only by case are stored as
+    /// distinct tables
+    pub case_sensitive_names: bool,
+}
+
+/// Returned when an index is created with a distance or storage dtype its
+/// vector database doesn't support
+#[derive(Debug, Error)]
+#[error("{vector_db} doesn't support {setting} {value} of index {index}, supported: {supported}")]
+pub struct UnsupportedIndexError {
+    pub index: String,
+    pub vector_db: String,
+    pub setting: String,
+    pub value: String,
+    pub supported: String,
+}
+
+/// Returned when a vector is written with a content id its vector database
+/// can't store, for example an id which isn't hexadecimal in Qdrant, whose
+/// point ids are numbers
+#[derive(Debug, Error)]
+#[error("{vector_db} can't store content id {content_id}: {reason}")]
+pub struct InvalidContentIdError {
+    pub vector_db: String,
+    pub content_id: String,
+    pub reason: String,
+}
+
+impl VectorDbCapabilities {
+    /// Checks that the vector database named vector_db can create the index
+    pub fn check(
+        &self,
+        vector_db: &str,
+        index: &CreateIndexParams,
+    ) -> Result<(), UnsupportedIndexError> {
+        let unsupported =
+            |setting: &str, value: String, supported: Vec<String>| UnsupportedIndexError {
+                index: index.vectordb_index_name.clone(),
+                vector_db: vector_db.to_string(),
+                setting: setting.to_string(),
+                value,
+                supported: supported.join(", "),
+            };
+        if !self.distances.contains(&index.distance) {
+            return Err(unsupported(
+                "distance",
+                index.distance.to_string(),
+                self.distances.iter().map(ToString::to_string).collect(),
+            ));
+        }
+        if !self.storage_dtypes.contains(&index.storage_dtype) {
+            return Err(unsupported(
+                "storage_dtype",
+                index.storage_dtype.name().to_string(),
+                self.storage_dtypes
+                    .iter()
+                    .map(|dtype| dtype.name().to_string())
+                    .collect(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -50,8 +132,61 @@ pub struct SearchResult {
     pub content_metadata: ContentMetadata,
 }
 
+/// Distance of a search result to the query and its score normalized to
+/// [0, 1], which is higher for closer vectors whatever the backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreDetails {
+    pub raw_distance: f32,
+    pub normalized_score: f32,
+}
+
+impl ScoreDetails {
+    /// Details of a cosine similarity or dot product, whose distance is one
+    /// minus the similarity
+    pub fn from_similarity(similarity: f32) -> Self {
+        Self {
+            raw_distance: 1.0 - similarity,
+            normalized_score: ((1.0 + similarity) / 2.0).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn from_cosine_distance(distance: f32) -> Self {
+        Self {
+            raw_distance: distance,
+            normalized_score: (1.0 - distance / 2.0).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn from_euclidean_distance(distance: f32) -> Self {
+        Self {
+            raw_distance: distance,
+            normalized_score: 1.0 / (1.0 + distance.max(0.0)),
+        }
+    }
+}
+
 pub type VectorDBTS = Arc<dyn VectorDb + Sync + Send>;
 
+/// A page of vectors read from an index. next_cursor is None once the last
+/// page has been read.
+#[derive(Debug, Clone, Default)]
+pub struct ScrollPage {
+    pub chunks: Vec<VectorChunk>,
+    pub next_cursor: Option<String>,
+}
+
+/// Converts an embedding to half precision for storage. Components are
+/// rounded to the nearest f16 value and values outside its range become
+/// infinite.
+pub fn to_f16(embedding: &[f32]) -> Vec<half::f16> {
+    embedding.iter().map(|v| half::f16::from_f32(*v)).collect()
+}
+
+/// Converts a half precision embedding back to f32.
+pub fn from_f16(embedding: &[half::f16]) -> Vec<f32> {
+    embedding.iter().map(|v| v.to_f32()).collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct VectorChunk {
     pub content_id: String,
@@ -97,12 +232,24 @@ pub trait VectorDb {
     /// Retrieves the vector embeddings for the specified content IDs
     async fn get_points(&self, index: &str, content_ids: Vec<String>) -> Result<Vec<VectorChunk>>;
 
-    /// Update metadata for the specified content ID
+    /// Retrieves the raw embedding stored for the specified content ID, or
+    /// None if the content ID is not present in the index
+    async fn get_vector(&self, index: &str, content_id: &str) -> Result<Option<Vec<f32>>>;
+
+    /// Reads up to limit vectors of the index, including their embeddings,
+    /// starting at cursor. The cursor of the first page is None, following
+    /// pages are read with the next_cursor of the previous page.
+    async fn scroll(&self, index: &str, cursor: Option<String>, limit: u64) -> Result<ScrollPage>;
+
+    /// Update metadata for the specified content ID. With merge the keys of
+    /// metadata replace the same keys of the stored metadata, otherwise
+    /// metadata replaces all of it.
     async fn update_metadata(
         &self,
         index: &str,
         content_id: String,
         metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
     ) -> Result<()>;
 
     /// Searches for the nearest neighbors of a query vector in the specified
@@ -115,34 +262,146 @@ pub trait VectorDb {
         filter: filter::LabelsFilter,
     ) -> Result<Vec<SearchResult>>;
 
+    /// Searches for the nearest neighbors of a query vector by comparing it
+    /// with every vector of the index instead of using an approximate nearest
+    /// neighbor index. Vector databases which can't search exactly fall back
+    /// to search.
+    async fn search_exact(
+        &self,
+        index: String,
+        query_embedding: Vec<f32>,
+        k: u64,
+        filter: filter::LabelsFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.search(index, query_embedding, k, filter).await
+    }
+
     /// Deletes the specified vector index from the vector database.
     async fn drop_index(&self, index: &str) -> Result<()>;
 
     /// Returns the number of vectors in the specified index.
     async fn num_vectors(&self, index: &str) -> Result<u64>;
 
+    /// Returns an estimate of the number of vectors in the specified index
+    /// which is cheaper to read than num_vectors, or None if the vector
+    /// database has no such estimate.
+    async fn estimate_num_vectors(&self, _index: &str) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Returns the dimension of the vectors stored in the specified index, or
+    /// None if the index does not exist in the vector database.
+    async fn index_dim(&self, index: &str) -> Result<Option<u64>>;
+
     fn name(&self) -> String;
 
+    /// Distances, storage dtypes and search features supported by the vector
+    /// database
+    fn capabilities(&self) -> VectorDbCapabilities;
+
+    /// Registers the instruments the vector database reports to, such as its
+    /// connections, with the metrics of the vector index manager
+    fn register_metrics(&self, _metrics: &Metrics) {}
+
+    /// Whether the confidence scores of search results are distances, which
+    /// are lower for closer vectors, instead of similarities.
+    fn scores_are_distances(&self) -> bool {
+        false
+    }
+
+    /// Whether the confidence scores of an index with the given distance are
+    /// distances, lower for closer vectors
+    fn scores_are_distances_for(&self, _distance: &IndexDistance) -> bool {
+        self.scores_are_distances()
+    }
+
+    /// Distance and normalized score of the confidence score of a search
+    /// result of an index with the given distance
+    fn score_details(&self, confidence_score: f32, _distance: &IndexDistance) -> ScoreDetails {
+        if self.scores_are_distances() {
+            ScoreDetails::from_cosine_distance(confidence_score)
+        } else {
+            ScoreDetails::from_similarity(confidence_score)
+        }
+    }
+
     //  TODO: Add delete content using namespace and content id
 }
 
 /// Creates a new vector database based on the specified configuration.
 pub async fn create_vectordb(config: VectorIndexConfig) -> Result<VectorDBTS> {
-    match config.index_store {
-        IndexStoreKind::Qdrant => Ok(Arc::new(QdrantDb::new(config.qdrant_config.unwrap()))),
+    create_vectordb_of_kind(&config, &config.index_store).await
+}
+
+/// Creates the vector database of the given kind with its configuration in
+/// config, which does not have to be the default index store.
+async fn create_vectordb_of_kind(
+    config: &VectorIndexConfig,
+    kind: &IndexStoreKind,
+) -> Result<VectorDBTS> {
+    let missing_config = |field: &str| anyhow!("index store {} requires {}", kind, field);
+    match kind {
+        IndexStoreKind::Qdrant => Ok(Arc::new(QdrantDb::new(
+            config
+                .qdrant_config
+                .clone()
+                .ok_or_else(|| missing_config("qdrant_config"))?,
+        ))),
         IndexStoreKind::PgVector => Ok(Arc::new(
-            pg_vector::PgVector::new(config.pg_vector_config.unwrap()).await?,
+            pg_vector::PgVector::new(
+                config
+                    .pg_vector_config
+                    .clone()
+                    .ok_or_else(|| missing_config("pg_vector_config"))?,
+            )
+            .await?,
         )),
         // TODO Bring it back
         //IndexStoreKind::OpenSearchKnn => Ok(Arc::new(OpenSearchKnn::new(
         //    config.open_search_basic.unwrap(),
         //))),
         IndexStoreKind::Lancedb => Ok(Arc::new(
-            lancedb::LanceDb::new(&config.lancedb_config.unwrap()).await?,
+            lancedb::LanceDb::new(
+                config
+                    .lancedb_config
+                    .as_ref()
+                    .ok_or_else(|| missing_config("lancedb_config"))?,
+            )
+            .await?,
         )),
     }
 }
 
+/// Creates the vector databases of the indexes whose settings select another
+/// index store than the default one, keyed by the index table name. Indexes
+/// using the same store share its vector database.
+pub async fn create_index_vectordbs(
+    config: &VectorIndexConfig,
+) -> Result<HashMap<String, VectorDBTS>> {
+    let mut vector_dbs: HashMap<IndexStoreKind, VectorDBTS> = HashMap::new();
+    let mut index_vector_dbs = HashMap::new();
+    for (index_name, settings) in &config.indexes {
+        let Some(kind) = &settings.index_store else {
+            continue;
+        };
+        if *kind == config.index_store {
+            continue;
+        }
+        if !vector_dbs.contains_key(kind) {
+            let vector_db = create_vectordb_of_kind(config, kind).await.map_err(|e| {
+                anyhow!(
+                    "unable to create index store of index {}: {}",
+                    index_name,
+                    e
+                )
+            })?;
+            vector_dbs.insert(kind.clone(), vector_db);
+        }
+        index_vector_dbs.insert(index_name.clone(), vector_dbs[kind].clone());
+    }
+    Ok(index_vector_dbs)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -150,13 +409,96 @@ mod tests {
     use filter::{Expression, Operator};
     use serde_json::json;
 
-    use super::VectorDBTS;
+    use super::{
+        from_f16,
+        to_f16,
+        CreateIndexParams,
+        IndexDistance,
+        ScoreDetails,
+        VectorDBTS,
+        VectorDbCapabilities,
+    };
     use crate::{
         data_manager::DataManager,
+        server_config::StorageDtype,
         test_util::db_utils::{create_metadata, test_mock_content_metadata},
         vectordbs::VectorChunk,
     };
 
+    #[test]
+    fn f16_round_trip() {
+        let embedding = vec![0.0, 1.0, -2.5, 0.333, 1024.0];
+        let round_trip = from_f16(&to_f16(&embedding));
+        assert_eq!(round_trip.len(), embedding.len());
+        for (original, converted) in embedding.iter().zip(round_trip.iter()) {
+            assert!((original - converted).abs() <= original.abs() * 1e-3);
+        }
+        // Values that are exactly representable survive unchanged
+        assert_eq!(round_trip[1], 1.0);
+        assert_eq!(round_trip[2], -2.5);
+    }
+
+    #[test]
+    fn check_capabilities() {
+        let capabilities = VectorDbCapabilities {
+            distances: vec![IndexDistance::Cosine],
+            storage_dtypes: vec![StorageDtype::F32, StorageDtype::F16],
+            exact_search: false,
+            approximate_count: false,
+            case_sensitive_names: true,
+        };
+        let index = |distance, storage_dtype| CreateIndexParams {
+            vectordb_index_name: "index".to_string(),
+            vector_dim: 2,
+            distance,
+            unique_params: None,
+            storage_dtype,
+        };
+        assert!(capabilities
+            .check("lancedb", &index(IndexDistance::Cosine, StorageDtype::F16))
+            .is_ok());
+        let err = capabilities
+            .check("lancedb", &index(IndexDistance::Dot, StorageDtype::F32))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lancedb doesn't support distance dot of index index, supported: cosine"
+        );
+        let capabilities = VectorDbCapabilities {
+            storage_dtypes: vec![StorageDtype::F32],
+            ..capabilities
+        };
+        let err = capabilities
+            .check("qdrant", &index(IndexDistance::Cosine, StorageDtype::F16))
+            .unwrap_err();
+        assert_eq!(err.setting, "storage_dtype");
+        assert_eq!(err.value, "f16");
+    }
+
+    #[test]
+    fn score_details() {
+        // Identical vectors have a distance of 0 and a score of 1 whatever
+        // the backend reports
+        let expected = ScoreDetails {
+            raw_distance: 0.0,
+            normalized_score: 1.0,
+        };
+        assert_eq!(ScoreDetails::from_similarity(1.0), expected);
+        assert_eq!(ScoreDetails::from_cosine_distance(0.0), expected);
+        assert_eq!(ScoreDetails::from_euclidean_distance(0.0), expected);
+
+        // Opposite vectors have the lowest score
+        assert_eq!(ScoreDetails::from_similarity(-1.0).normalized_score, 0.0);
+        assert_eq!(
+            ScoreDetails::from_cosine_distance(2.0).normalized_score,
+            0.0
+        );
+        assert_eq!(
+            ScoreDetails::from_euclidean_distance(1.0).normalized_score,
+            0.5
+        );
+    }
+
     pub async fn crud_operations(vector_db: VectorDBTS, index_name: &str) {
         let content_id = "0";
         let chunk = VectorChunk {
@@ -254,7 +596,12 @@ mod tests {
 
         let new_metadata = create_metadata(vec![("key1", "value5"), ("key2", "value6")]);
         vector_db
-            .update_metadata(index_name, content_ids[0].clone(), new_metadata.clone())
+            .update_metadata(
+                index_name,
+                content_ids[0].clone(),
+                new_metadata.clone(),
+                true,
+            )
             .await
             .unwrap();
         let result = vector_db
@@ -508,4 +855,57 @@ mod tests {
 
         assert_eq!(res.len(), 0);
     }
+
+    pub async fn existence_filters(vector_db: VectorDBTS, index_name: &str) {
+        let content_ids = [make_id(), make_id(), make_id()];
+        // Only the first vector has a reviewer
+        let metadatas = [
+            HashMap::from([
+                ("tag".to_string(), json!("value1")),
+                ("reviewer".to_string(), json!("alice")),
+            ]),
+            HashMap::from([("tag".to_string(), json!("value2"))]),
+            HashMap::from([("tag".to_string(), json!("value2"))]),
+        ];
+        let mut chunks = vec![];
+        for (i, (content_id, metadata)) in content_ids.iter().zip(metadatas.iter()).enumerate() {
+            let content_metadata = test_mock_content_metadata(content_id, "1", "graph1");
+            chunks.push(VectorChunk {
+                content_id: content_id.clone(),
+                embedding: vec![i as f32 + 0.1, i as f32 + 0.2],
+                metadata: metadata.clone(),
+                root_content_metadata: Some(content_metadata.clone()),
+                content_metadata,
+            });
+        }
+        vector_db.add_embedding(index_name, chunks).await.unwrap();
+
+        let k = 3;
+        let query = vec![0.1, 0.2];
+        // Including a key no vector has
+        for (filter, expected) in [
+            ("exists(reviewer)", vec![content_ids[0].clone()]),
+            (
+                "not_exists(reviewer)",
+                vec![content_ids[1].clone(), content_ids[2].clone()],
+            ),
+            ("exists(missing)", vec![]),
+            ("not_exists(missing)", content_ids.to_vec()),
+        ] {
+            let res = vector_db
+                .search(
+                    index_name.to_string(),
+                    query.clone(),
+                    k,
+                    filter::LabelsFilter(vec![Expression::from_str(filter).unwrap()]),
+                )
+                .await
+                .unwrap();
+            let mut result_ids: Vec<String> = res.into_iter().map(|r| r.content_id).collect();
+            result_ids.sort();
+            let mut expected = expected;
+            expected.sort();
+            assert_eq!(result_ids, expected, "filter {}", filter);
+        }
+    }
 }
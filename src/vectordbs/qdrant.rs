@@ -22,6 +22,9 @@ use qdrant_client::{
         PointsIdsList,
         PointsSelector,
         Range,
+        RetrievedPoint,
+        ScrollPoints,
+        SearchParams,
         SearchPoints,
         VectorParams,
         VectorsConfig,
@@ -30,16 +33,32 @@ use qdrant_client::{
 };
 use serde::{Deserialize, Serialize};
 
-use super::{CreateIndexParams, VectorDb};
+use super::{
+    CreateIndexParams,
+    InvalidContentIdError,
+    ScrollPage,
+    VectorDb,
+    VectorDbCapabilities,
+};
 use crate::{
-    server_config::QdrantConfig,
-    vectordbs::{IndexDistance, SearchResult, VectorChunk},
+    server_config::{QdrantConfig, StorageDtype},
+    vectordbs::{IndexDistance, ScoreDetails, SearchResult, VectorChunk},
 };
 
 fn hex_to_u64(hex: &str) -> Result<u64, std::num::ParseIntError> {
     u64::from_str_radix(hex, 16)
 }
 
+/// Returns the point id of a content id. Content ids come from requests, so
+/// ids which aren't hexadecimal are an error rather than a panic.
+fn point_id(content_id: &str) -> Result<u64, InvalidContentIdError> {
+    hex_to_u64(content_id).map_err(|e| InvalidContentIdError {
+        vector_db: "qdrant".to_string(),
+        content_id: content_id.to_string(),
+        reason: e.to_string(),
+    })
+}
+
 #[allow(dead_code)]
 fn u64_to_hex(number: u64) -> String {
     format!("{:x}", number)
@@ -77,6 +96,65 @@ impl QdrantDb {
         Ok(client)
     }
 
+    async fn search_points(
+        &self,
+        index: String,
+        query_embedding: Vec<f32>,
+        k: u64,
+        filter: filter::LabelsFilter,
+        exact: bool,
+    ) -> Result<Vec<SearchResult>> {
+        let filter = if !filter.is_empty() {
+            Some(get_filters(filter.0)?)
+        } else {
+            None
+        };
+        tracing::debug!(
+            "qdrant search: collection: {}, k: {}, exact: {}, filter: {:?}",
+            index,
+            k,
+            exact,
+            filter
+        );
+        let collection_name = index.clone();
+        let result = self
+            .create_client()?
+            .search_points(&SearchPoints {
+                collection_name,
+                vector: query_embedding,
+                limit: k,
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(SelectorOptions::Enable(true)),
+                }),
+                filter,
+                params: exact.then(|| SearchParams {
+                    exact: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| anyhow!("unable to read index: {}", e.to_string()))?;
+        let mut documents: Vec<SearchResult> = Vec::new();
+        for point in result.result {
+            let (metadata, indexify_payload) = extract_metadata_from_payload(point.payload)?;
+            // TODO similarity score
+            documents.push(SearchResult {
+                confidence_score: point.score,
+                content_id: content_id_from_point_id(point.id)?,
+                metadata,
+                content_metadata: indexify_payload.content_metadata.clone(),
+                root_content_metadata: indexify_payload.root_content_metadata.clone(),
+            });
+        }
+        tracing::debug!(
+            "qdrant search: collection: {}, candidates: {}",
+            index,
+            documents.len()
+        );
+        Ok(documents)
+    }
+
     fn convert_to_qdrant_distance(distance: IndexDistance) -> Distance {
         match distance {
             IndexDistance::Cosine => Distance::Cosine,
@@ -97,6 +175,22 @@ fn content_id_from_point_id(point_id: Option<PointId>) -> Result<String> {
     }
 }
 
+fn vector_chunk_from_point(point: RetrievedPoint) -> Result<VectorChunk> {
+    let (metadata, indexify_payload) = extract_metadata_from_payload(point.payload)?;
+    let vector = point.vectors.unwrap().vectors_options.unwrap(); // Unwrap the Option<VectorsOptions>
+    let embedding = match vector {
+        VectorsOptions::Vector(vector) => vector,
+        _ => return Err(anyhow!("Invalid vector type")),
+    };
+    Ok(VectorChunk {
+        content_id: content_id_from_point_id(point.id)?,
+        embedding: embedding.data,
+        metadata,
+        root_content_metadata: indexify_payload.root_content_metadata,
+        content_metadata: indexify_payload.content_metadata,
+    })
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct IndexifyPayload {
     pub content_metadata: ContentMetadata,
@@ -121,8 +215,43 @@ impl VectorDb for QdrantDb {
         "qdrant".into()
     }
 
+    fn capabilities(&self) -> VectorDbCapabilities {
+        VectorDbCapabilities {
+            distances: vec![
+                IndexDistance::Cosine,
+                IndexDistance::Dot,
+                IndexDistance::Euclidean,
+            ],
+            storage_dtypes: vec![StorageDtype::F32],
+            exact_search: true,
+            approximate_count: false,
+            case_sensitive_names: true,
+        }
+    }
+
+    /// Qdrant scores are similarities, except for euclidean indexes whose
+    /// scores are distances
+    fn scores_are_distances_for(&self, distance: &IndexDistance) -> bool {
+        matches!(distance, IndexDistance::Euclidean)
+    }
+
+    fn score_details(&self, confidence_score: f32, distance: &IndexDistance) -> ScoreDetails {
+        match distance {
+            IndexDistance::Euclidean => ScoreDetails::from_euclidean_distance(confidence_score),
+            IndexDistance::Cosine | IndexDistance::Dot => {
+                ScoreDetails::from_similarity(confidence_score)
+            }
+        }
+    }
+
     #[tracing::instrument]
     async fn create_index(&self, index: CreateIndexParams) -> Result<()> {
+        if index.storage_dtype != StorageDtype::F32 {
+            return Err(anyhow!(
+                "storage dtype {:?} is not supported by qdrant",
+                index.storage_dtype
+            ));
+        }
         let result = self
             .create_client()?
             .create_collection(&CreateCollection {
@@ -150,8 +279,13 @@ impl VectorDb for QdrantDb {
             .map_err(|e| anyhow!("unable to create index: {}", e.to_string()))
     }
 
-    #[tracing::instrument]
+    #[tracing::instrument(skip(chunks))]
     async fn add_embedding(&self, index: &str, chunks: Vec<VectorChunk>) -> Result<()> {
+        tracing::debug!(
+            "qdrant upsert: collection: {}, points: {}",
+            index,
+            chunks.len()
+        );
         let mut points = Vec::<PointStruct>::new();
         for chunk in chunks {
             let chunk_id = chunk.content_id.clone();
@@ -171,7 +305,7 @@ impl VectorDb for QdrantDb {
                 )
             })?;
             points.push(PointStruct::new(
-                hex_to_u64(&chunk_id).unwrap(),
+                point_id(&chunk_id)?,
                 chunk.embedding.clone(),
                 metadata,
             ));
@@ -185,12 +319,16 @@ impl VectorDb for QdrantDb {
     }
 
     async fn get_points(&self, index: &str, ids: Vec<String>) -> Result<Vec<VectorChunk>> {
-        let mut points = Vec::<PointId>::new();
-        for id in ids {
-            let point_id = hex_to_u64(&id).unwrap();
-            points.push(PointId {
+        // Ids which aren't point ids can't be stored, so they are not found
+        let points: Vec<PointId> = ids
+            .iter()
+            .filter_map(|id| point_id(id).ok())
+            .map(|point_id| PointId {
                 point_id_options: Some(Num(point_id)),
-            });
+            })
+            .collect();
+        if points.is_empty() {
+            return Ok(Vec::new());
         }
         let client = self.create_client()?;
 
@@ -200,50 +338,94 @@ impl VectorDb for QdrantDb {
             .map_err(|e| anyhow!("unable to read index: {}", e.to_string()))?;
         let mut documents: Vec<VectorChunk> = Vec::new();
         for point in result.result {
-            let (metadata, indexify_payload) = extract_metadata_from_payload(point.payload)?;
-            let vector = point.vectors.unwrap().vectors_options.unwrap(); // Unwrap the Option<VectorsOptions>
-            let embedding = match vector {
-                VectorsOptions::Vector(vector) => vector,
-                _ => return Err(anyhow!("Invalid vector type")),
-            };
-            documents.push(VectorChunk {
-                content_id: content_id_from_point_id(point.id)?,
-                embedding: embedding.data,
-                metadata,
-                root_content_metadata: indexify_payload.root_content_metadata,
-                content_metadata: indexify_payload.content_metadata,
-            });
+            documents.push(vector_chunk_from_point(point)?);
         }
         Ok(documents)
     }
 
+    #[tracing::instrument]
+    async fn scroll(&self, index: &str, cursor: Option<String>, limit: u64) -> Result<ScrollPage> {
+        let offset = cursor
+            .map(|cursor| hex_to_u64(&cursor))
+            .transpose()
+            .map_err(|e| anyhow!("invalid scroll cursor: {}", e))?
+            .map(|id| PointId {
+                point_id_options: Some(Num(id)),
+            });
+        let result = self
+            .create_client()?
+            .scroll(&ScrollPoints {
+                collection_name: index.to_string(),
+                offset,
+                limit: Some(limit as u32),
+                with_payload: Some(true.into()),
+                with_vectors: Some(true.into()),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| anyhow!("unable to scroll index: {}", e.to_string()))?;
+        let mut chunks = Vec::new();
+        for point in result.result {
+            chunks.push(vector_chunk_from_point(point)?);
+        }
+        let next_cursor = result
+            .next_page_offset
+            .map(|id| content_id_from_point_id(Some(id)))
+            .transpose()?;
+        Ok(ScrollPage {
+            chunks,
+            next_cursor,
+        })
+    }
+
+    async fn get_vector(&self, index: &str, content_id: &str) -> Result<Option<Vec<f32>>> {
+        let chunks = self.get_points(index, vec![content_id.to_string()]).await?;
+        Ok(chunks.into_iter().next().map(|chunk| chunk.embedding))
+    }
+
     async fn update_metadata(
         &self,
         index: &str,
         content_id: String,
         metadata: HashMap<String, serde_json::Value>,
+        merge: bool,
     ) -> Result<()> {
         let metadata = serde_json::to_value(metadata)
             .map_err(|e| anyhow!("unable to serialize metadata: {}", e.to_string()))?;
         let metadata = serde_json::from_value(metadata)
             .map_err(|e| anyhow!("unable to read metadata: {}", e.to_string()))?;
-        let point_id = hex_to_u64(&content_id).unwrap();
-        let points: Vec<PointId> = vec![point_id.into()];
-        let _result = self
-            .create_client()?
-            .set_payload(&index, None, &points.into(), metadata, None, None)
-            .await
-            .map_err(|e| anyhow!("unable to update metadata: {}", e.to_string()))?;
+        let points: Vec<PointId> = vec![point_id(&content_id)?.into()];
+        let client = self.create_client()?;
+        let result = if merge {
+            client
+                .set_payload(&index, None, &points.into(), metadata, None, None)
+                .await
+        } else {
+            client
+                .overwrite_payload(&index, None, &points.into(), metadata, None, None)
+                .await
+        };
+        result.map_err(|e| anyhow!("unable to update metadata: {}", e.to_string()))?;
         Ok(())
     }
 
     #[tracing::instrument]
     async fn remove_embedding(&self, index: &str, content_id: &str) -> Result<()> {
+        // Ids which aren't point ids can't be stored, so there is nothing to
+        // remove
+        let Ok(point_id) = point_id(content_id) else {
+            return Ok(());
+        };
         let points_selector: PointsSelector = PointsSelector {
             points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
-                ids: vec![hex_to_u64(content_id).unwrap().into()],
+                ids: vec![point_id.into()],
             })),
         };
+        tracing::debug!(
+            "qdrant delete: collection: {}, content_id: {}",
+            index,
+            content_id
+        );
         self.create_client()?
             .delete_points_blocking(index, None, &points_selector, None)
             .await
@@ -259,7 +441,7 @@ impl VectorDb for QdrantDb {
         Ok(())
     }
 
-    #[tracing::instrument]
+    #[tracing::instrument(skip(query_embedding))]
     async fn search(
         &self,
         index: String,
@@ -267,38 +449,20 @@ impl VectorDb for QdrantDb {
         k: u64,
         filter: filter::LabelsFilter,
     ) -> Result<Vec<SearchResult>> {
-        let filter = if !filter.is_empty() {
-            Some(get_filters(filter.0)?)
-        } else {
-            None
-        };
-        let result = self
-            .create_client()?
-            .search_points(&SearchPoints {
-                collection_name: index,
-                vector: query_embedding,
-                limit: k,
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(SelectorOptions::Enable(true)),
-                }),
-                filter,
-                ..Default::default()
-            })
+        self.search_points(index, query_embedding, k, filter, false)
+            .await
+    }
+
+    /// Qdrant scans every point of the collection for exact searches
+    async fn search_exact(
+        &self,
+        index: String,
+        query_embedding: Vec<f32>,
+        k: u64,
+        filter: filter::LabelsFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_points(index, query_embedding, k, filter, true)
             .await
-            .map_err(|e| anyhow!("unable to read index: {}", e.to_string()))?;
-        let mut documents: Vec<SearchResult> = Vec::new();
-        for point in result.result {
-            let (metadata, indexify_payload) = extract_metadata_from_payload(point.payload)?;
-            // TODO similarity score
-            documents.push(SearchResult {
-                confidence_score: point.score,
-                content_id: content_id_from_point_id(point.id)?,
-                metadata,
-                content_metadata: indexify_payload.content_metadata.clone(),
-                root_content_metadata: indexify_payload.root_content_metadata.clone(),
-            });
-        }
-        Ok(documents)
     }
 
     #[tracing::instrument]
@@ -327,6 +491,32 @@ impl VectorDb for QdrantDb {
         let collection_info = result.result.ok_or(anyhow!("index not found: {}", index))?;
         Ok(collection_info.points_count.unwrap_or_default())
     }
+
+    #[tracing::instrument]
+    async fn index_dim(&self, index: &str) -> Result<Option<u64>> {
+        let client = self.create_client()?;
+        let exists = client
+            .has_collection(index)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        if !exists {
+            return Ok(None);
+        }
+        let result = client
+            .collection_info(index)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        let vectors_config = result
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| vectors_config.config);
+        match vectors_config {
+            Some(Config::Params(params)) => Ok(Some(params.size)),
+            _ => Err(anyhow!("unable to read vector params for index: {}", index)),
+        }
+    }
 }
 
 /// Convert Indexify Filters to Qdrant Filters.
@@ -384,6 +574,11 @@ fn get_filters(filters: Vec<super::Expression>) -> Result<Filter> {
                     },
                 ));
             }
+            // is_empty matches missing keys, null values and empty lists
+            Operator::Exists => must_not.push(Condition::is_empty(f.key)),
+            Operator::NotExists => must.push(Condition::is_empty(f.key)),
+            // Fuzzy filters are applied to the results of the search
+            Operator::Fuzzy { .. } => {}
         }
     }
 
@@ -420,16 +615,29 @@ fn get_f64_from_json_value(value: serde_json::Value) -> Result<f64> {
 mod tests {
     use std::sync::Arc;
 
-    use super::{CreateIndexParams, QdrantDb};
+    use super::{point_id, CreateIndexParams, QdrantDb};
     use crate::{
-        server_config::QdrantConfig,
+        server_config::{QdrantConfig, StorageDtype},
         vectordbs::{
-            tests::{basic_search, insertion_idempotent, search_filters, store_metadata},
+            tests::{
+                basic_search,
+                existence_filters,
+                insertion_idempotent,
+                search_filters,
+                store_metadata,
+            },
             IndexDistance,
             VectorDBTS,
         },
     };
 
+    #[test]
+    fn test_point_id() {
+        assert_eq!(point_id("ff").unwrap(), 255);
+        let err = point_id("1' OR '1").unwrap_err();
+        assert_eq!(err.content_id, "1' OR '1");
+    }
+
     #[tokio::test]
     #[tracing_test::traced_test]
     async fn test_search_basic() {
@@ -443,6 +651,7 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -461,6 +670,7 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -482,6 +692,7 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: Some(hash_on.clone()),
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
@@ -501,9 +712,30 @@ mod tests {
                 vector_dim: 2,
                 distance: IndexDistance::Cosine,
                 unique_params: None,
+                storage_dtype: StorageDtype::F32,
             })
             .await
             .unwrap();
         search_filters(vector_db, index_name).await;
     }
+
+    #[tokio::test]
+    async fn test_existence_filters() {
+        let index_name = "existence-index";
+        let vector_db: VectorDBTS = Arc::new(QdrantDb::new(QdrantConfig {
+            addr: "http://localhost:6334".into(),
+        }));
+        vector_db.drop_index("existence-index").await.unwrap();
+        vector_db
+            .create_index(CreateIndexParams {
+                vectordb_index_name: "existence-index".into(),
+                vector_dim: 2,
+                distance: IndexDistance::Cosine,
+                unique_params: None,
+                storage_dtype: StorageDtype::F32,
+            })
+            .await
+            .unwrap();
+        existence_filters(vector_db, index_name).await;
+    }
 }